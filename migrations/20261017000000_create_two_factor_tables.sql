@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS user_totp(
+  user_id           TEXT NOT NULL UNIQUE PRIMARY KEY REFERENCES users(user_id),
+  secret            TEXT NOT NULL,
+  confirmed         BOOLEAN NOT NULL,
+  last_used_step    BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS login_challenges(
+  challenge_id      TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  expiry            DATETIME NOT NULL,
+  attempts          BIGINT NOT NULL
+);
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS passkey_credentials(
+  credential_id     TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  name              TEXT NOT NULL,
+  passkey           TEXT NOT NULL,
+  created           DATETIME NOT NULL,
+  last_used         DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS passkey_credentials_user_id ON passkey_credentials(user_id);
+
+CREATE TABLE IF NOT EXISTS passkey_challenges(
+  csrf_cookie       TEXT NOT NULL UNIQUE PRIMARY KEY,
+  ceremony          TEXT NOT NULL,
+  user_id           TEXT REFERENCES users(user_id),
+  state             TEXT NOT NULL,
+  expiry            DATETIME NOT NULL
+);
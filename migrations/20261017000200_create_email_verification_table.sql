@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS email_verification_tokens(
+  token_hash        TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  email             TEXT NOT NULL,
+  expiry            DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS email_verification_tokens_user_id ON email_verification_tokens(user_id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS password_reset_tokens(
+  token_hash        TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  expiry            DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS password_reset_tokens_user_id ON password_reset_tokens(user_id);
@@ -0,0 +1,6 @@
+ALTER TABLE active_sesssions ADD COLUMN created DATETIME;
+ALTER TABLE active_sesssions ADD COLUMN last_seen DATETIME;
+ALTER TABLE active_sesssions ADD COLUMN ip TEXT NOT NULL DEFAULT '';
+ALTER TABLE active_sesssions ADD COLUMN user_agent TEXT NOT NULL DEFAULT '';
+
+CREATE INDEX IF NOT EXISTS active_sesssions_user_id ON active_sesssions(user_id);
@@ -0,0 +1,12 @@
+ALTER TABLE active_sesssions ADD COLUMN absolute_expiry DATETIME;
+ALTER TABLE active_sesssions ADD COLUMN remember BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE active_sesssions ADD COLUMN rotated DATETIME;
+ALTER TABLE active_sesssions ADD COLUMN previous_session_id TEXT;
+
+-- sessions from before this migration keep their 30 day expiry as the absolute one
+UPDATE active_sesssions SET absolute_expiry = expiry;
+
+CREATE INDEX IF NOT EXISTS active_sesssions_previous_session_id
+  ON active_sesssions(previous_session_id);
+
+ALTER TABLE login_challenges ADD COLUMN remember BOOLEAN NOT NULL DEFAULT false;
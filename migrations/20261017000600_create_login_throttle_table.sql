@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS login_throttle(
+  throttle_key      TEXT NOT NULL UNIQUE PRIMARY KEY,
+  failures          BIGINT NOT NULL,
+  blocked_until     DATETIME,
+  expiry            DATETIME NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS pow_redemptions(
+  challenge_hash    TEXT NOT NULL UNIQUE PRIMARY KEY,
+  redeemed          DATETIME NOT NULL,
+  expiry            DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS pow_redemptions_redeemed ON pow_redemptions(redeemed);
@@ -0,0 +1,7 @@
+-- look-alike names share a canonical form, filled in by the server on start for older rows
+ALTER TABLE users ADD COLUMN username_canonical TEXT;
+ALTER TABLE users ADD COLUMN display_name_canonical TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_canonical ON users(username_canonical);
+CREATE UNIQUE INDEX IF NOT EXISTS users_display_name_canonical
+  ON users(display_name_canonical);
@@ -0,0 +1,16 @@
+-- addresses are compared by their normalized form, the server applies the plus tag policy
+-- on start, here only the domain, which is case insensitive, is lowercased
+ALTER TABLE users ADD COLUMN email_normalized TEXT;
+
+UPDATE users SET email_normalized =
+  substr(email, 1, instr(email, '@')) || lower(substr(email, instr(email, '@') + 1))
+  WHERE instr(email, '@') > 0;
+
+-- of accounts that already share an address only the oldest keeps it, the others stay
+-- NULL so the index can be created
+UPDATE users SET email_normalized = NULL
+  WHERE email_normalized IS NOT NULL AND rowid NOT IN (
+    SELECT MIN(rowid) FROM users WHERE email_normalized IS NOT NULL GROUP BY email_normalized
+  );
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_normalized ON users(email_normalized);
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS recovery_codes(
+  code_id           TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  code_hash         TEXT NOT NULL,
+  -- when the code was used, NULL while it can still be used
+  used              DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id ON recovery_codes(user_id);
+
+CREATE TABLE IF NOT EXISTS audit_log(
+  event_id          TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  event             TEXT NOT NULL,
+  ip                TEXT NOT NULL,
+  user_agent        TEXT NOT NULL,
+  created           DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_user_id ON audit_log(user_id);
@@ -0,0 +1,33 @@
+-- accounts that sign in through an OpenID Connect provider, a provider names each of its
+-- users by a subject that is only unique together with the issuer
+CREATE TABLE IF NOT EXISTS linked_identities(
+  issuer            TEXT NOT NULL,
+  subject           TEXT NOT NULL,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  provider          TEXT NOT NULL,
+  created           DATETIME NOT NULL,
+  PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS linked_identities_user_id ON linked_identities(user_id);
+
+-- logins that left for a provider and have not come back yet
+CREATE TABLE IF NOT EXISTS oidc_flows(
+  state             TEXT NOT NULL UNIQUE PRIMARY KEY,
+  provider          TEXT NOT NULL,
+  csrf_cookie       TEXT NOT NULL,
+  pkce_verifier     TEXT NOT NULL,
+  nonce             TEXT NOT NULL,
+  expiry            DATETIME NOT NULL
+);
+
+-- first time provider users that still have to choose a display name, only addresses the
+-- provider verified are accepted
+CREATE TABLE IF NOT EXISTS oidc_pending_signups(
+  token_hash        TEXT NOT NULL UNIQUE PRIMARY KEY,
+  provider          TEXT NOT NULL,
+  issuer            TEXT NOT NULL,
+  subject           TEXT NOT NULL,
+  email             TEXT NOT NULL,
+  expiry            DATETIME NOT NULL
+);
@@ -0,0 +1,9 @@
+-- emailed login links, each only works in the browser whose __Host-csrf cookie asked for it
+CREATE TABLE IF NOT EXISTS magic_link_tokens(
+  token_hash        TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  csrf_cookie       TEXT NOT NULL,
+  expiry            DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS magic_link_tokens_user_id ON magic_link_tokens(user_id);
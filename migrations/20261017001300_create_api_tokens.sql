@@ -0,0 +1,13 @@
+-- personal access tokens for scripts, sent as "Authorization: Bearer <token>"
+CREATE TABLE IF NOT EXISTS api_tokens(
+  token_id          TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  name              TEXT NOT NULL,
+  token_hash        TEXT NOT NULL UNIQUE,
+  -- comma separated ApiTokenScope names
+  scopes            TEXT NOT NULL,
+  created           DATETIME NOT NULL,
+  expiry            DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id ON api_tokens(user_id);
@@ -0,0 +1,10 @@
+-- encrypted session cookies are only checked against these, see SESSION_STORE
+-- bumped to end every encrypted session of a user at once
+ALTER TABLE users ADD COLUMN session_generation BIGINT NOT NULL DEFAULT 0;
+
+-- encrypted sessions that were logged out before their cookie expires
+CREATE TABLE IF NOT EXISTS revoked_session_cookies(
+  session_id        TEXT NOT NULL UNIQUE PRIMARY KEY,
+  user_id           TEXT NOT NULL REFERENCES users(user_id),
+  expiry            DATETIME NOT NULL
+);
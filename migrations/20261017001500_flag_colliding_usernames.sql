@@ -0,0 +1,3 @@
+-- older accounts whose username looks like one that was taken first, they keep logging in
+-- with their exact name until they choose a new one
+ALTER TABLE users ADD COLUMN rename_required BOOLEAN NOT NULL DEFAULT 0;
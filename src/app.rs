@@ -1,23 +1,61 @@
 use cfg_if::cfg_if;
-use leptos::{either::Either, prelude::*};
+use leptos::{either::Either, prelude::*, spawn::spawn_local};
 use leptos_router::{
     components::{Redirect, Route, Router, Routes, A},
-    SsrMode, StaticSegment,
+    ParamSegment, SsrMode, StaticSegment,
 };
 mod components;
-use components::{csrf::CSRFField, logheader::LogHeader};
+use components::{
+    csrf::CSRFField,
+    logheader::LogHeader,
+    oidc::OidcButtons,
+    passkey::{FinishPasskeyLogin, PasskeyLogin},
+};
 mod homepage;
+mod magic_link;
+mod oidc;
+mod password_reset;
+mod settings;
+mod verify;
 use crate::database::APIUserData;
 use crate::defs::*;
+use crate::security::{
+    password_policy::estimate_strength,
+    proof_of_work::{solve_challenge, PowChallenge},
+};
 use leptos_meta::{provide_meta_context, MetaTags};
 
 use homepage::HomePage;
+use magic_link::{EmailLogin, MagicLinkLogin, RedeemLoginLink};
+use oidc::{ChooseDisplayName, OidcCallback, OidcSignup};
+use password_reset::{ForgotPassword, ResetPassword};
+use settings::{
+    admin::AdminSettings,
+    api_tokens::ApiTokenSettings,
+    email::EmailSettings,
+    passkeys::PasskeySettings,
+    password::PasswordSettings,
+    sessions::SessionSettings,
+    two_factor::TwoFactorSettings,
+    username::{ChooseUsername, UsernameSettings},
+};
+use verify::VerifyEmail;
 
 use leptos_meta::{Link, Stylesheet, Title};
 
 cfg_if! { if #[cfg(feature = "ssr")] {
-    use crate::cookies::{destroy_session, issue_session_cookie, validate_session};
-    use crate::security::{gen_128bit_base64, validate_login, validate_registration};
+    use crate::cookies::{
+        destroy_login_challenge_cookie, destroy_session, issue_login_challenge_cookie,
+        issue_session_cookie, validate_session,
+    };
+    use crate::security::{
+        gen_128bit_base64,
+        proof_of_work::issue_pow_challenge,
+        two_factor::validate_second_factor,
+        validate_login, validate_registration,
+        verification::{send_signup_notice, send_verification_email},
+        LoginOutcome, RegistrationOutcome,
+    };
     //use leptos_meta::{Meta, MetaTags};
     use axum::http::{header::CONTENT_TYPE, HeaderValue};
     use leptos_axum::redirect as axum_redirect;
@@ -155,16 +193,6 @@ fn set_headers() {
     )
 }
 
-// returns false if still waiting for resolution
-fn is_logged_in(user_data: Option<Result<Option<APIUserData>, ServerFnError>>) -> bool {
-    match user_data {
-        None => false,
-        Some(Err(_)) => false,
-        Some(Ok(None)) => false,
-        Some(Ok(Some(_))) => true,
-    }
-}
-
 //returns false if still waiting for resolution
 fn is_not_logged_in(user_data: Option<Result<Option<APIUserData>, ServerFnError>>) -> bool {
     match user_data {
@@ -178,15 +206,25 @@ fn is_not_logged_in(user_data: Option<Result<Option<APIUserData>, ServerFnError>
 #[component]
 pub fn App() -> impl IntoView {
     let login = ServerAction::<Login>::new();
+    let login_second_factor = ServerAction::<LoginSecondFactor>::new();
+    let passkey_login = ServerAction::<FinishPasskeyLogin>::new();
+    let magic_link_login = ServerAction::<RedeemLoginLink>::new();
     let logout = ServerAction::<Logout>::new();
     let signup = ServerAction::<Signup>::new();
+    let oidc_signup = ServerAction::<ChooseDisplayName>::new();
+    let choose_username = ServerAction::<ChooseUsername>::new();
     let (is_routing, set_is_routing) = signal(false);
     let user_data = Resource::new(
         move || {
             (
                 // changing these conditions may reduce "get_user_data" server calls
                 login.version().get(),
+                login_second_factor.version().get(),
+                passkey_login.version().get(),
+                magic_link_login.version().get(),
                 signup.version().get(),
+                oidc_signup.version().get(),
+                choose_username.version().get(),
                 logout.version().get(),
             )
         },
@@ -228,6 +266,12 @@ pub fn App() -> impl IntoView {
                                     <A href="/settings">"Settings"</A>
                                     <br />
                                     <span>{format!("Logged in as: {}", user.display_name)}</span>
+                                    {user.rename_required.then(|| view! {
+                                        <br />
+                                        <span>"Your username looks like another account's, "
+                                            <A href="/settings">"please choose a new one"</A>"."
+                                        </span>
+                                    })}
                                 }),
                             }
                         )
@@ -243,9 +287,36 @@ pub fn App() -> impl IntoView {
                 }/>
                 <Route path=StaticSegment("/signup") ssr=SsrMode::Async view=move || view! {
                     <Signup action=signup is_routing />
+                    <OidcButtons/>
+                }/>
+                <Route path=(StaticSegment("signup"), StaticSegment("oidc")) ssr=SsrMode::Async view=move || view! {
+                    <OidcSignup action=oidc_signup is_routing />
                 }/>
                 <Route path=StaticSegment("/login") ssr=SsrMode::Async view=move || view! {
                     <Login action=login is_routing />
+                    <PasskeyLogin action=passkey_login />
+                    <OidcButtons/>
+                }/>
+                <Route path=(StaticSegment("oidc"), ParamSegment("provider"), StaticSegment("callback")) ssr=SsrMode::Async view=move || view! {
+                    <OidcCallback/>
+                }/>
+                <Route path=(StaticSegment("login"), StaticSegment("email")) ssr=SsrMode::Async view=move || view! {
+                    <EmailLogin/>
+                }/>
+                <Route path=(StaticSegment("login"), StaticSegment("email"), ParamSegment("token")) ssr=SsrMode::Async view=move || view! {
+                    <MagicLinkLogin action=magic_link_login is_routing />
+                }/>
+                <Route path=(StaticSegment("login"), StaticSegment("2fa")) ssr=SsrMode::Async view=move || view! {
+                    <LoginSecondFactor action=login_second_factor is_routing />
+                }/>
+                <Route path=StaticSegment("/forgot-password") ssr=SsrMode::Async view=move || view! {
+                    <ForgotPassword/>
+                }/>
+                <Route path=(StaticSegment("reset-password"), ParamSegment("token")) ssr=SsrMode::Async view=move || view! {
+                    <ResetPassword/>
+                }/>
+                <Route path=(StaticSegment("verify"), ParamSegment("token")) ssr=SsrMode::Async view=move || view! {
+                    <VerifyEmail/>
                 }/>
                 <Route path=StaticSegment("/settings") ssr=SsrMode::Async view=move || view! {
                     <Transition>
@@ -255,6 +326,14 @@ pub fn App() -> impl IntoView {
                     </Transition>
                     <h1>"Settings"</h1>
                     <Logout action=logout />
+                    <UsernameSettings action=choose_username user_data />
+                    <EmailSettings/>
+                    <PasswordSettings/>
+                    <TwoFactorSettings/>
+                    <PasskeySettings/>
+                    <ApiTokenSettings/>
+                    <SessionSettings/>
+                    <AdminSettings/>
                 }/>
             </Routes>
             </main>
@@ -283,7 +362,7 @@ pub fn Login(action: ServerAction<Login>, is_routing: ReadSignal<bool>) -> impl
         match action.value().get() {
             Some(Ok(val)) => set_login_result.set(val),
             Some(Err(ServerFnError::ServerError(e))) => set_login_result.set(e.to_string()),
-            _ => return,
+            _ => {}
         };
     });
 
@@ -294,7 +373,7 @@ pub fn Login(action: ServerAction<Login>, is_routing: ReadSignal<bool>) -> impl
 
     view! {
         <ActionForm action=action>
-                <CSRFField/>
+                <CSRFField purpose=CsrfPurpose::Login/>
                 <div>
                     <label>"Username: "
                         <input type="text" maxlength=USERNAME_MAX_LEN_STR minlength=USERNAME_MIN_LEN_STR name="username" required value/>
@@ -304,12 +383,20 @@ pub fn Login(action: ServerAction<Login>, is_routing: ReadSignal<bool>) -> impl
                     <label>"Password: "
                         <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password" required value/>
                     </label>
+                </div>
+                <div>
+                    <label>
+                        <input type="checkbox" name="remember" value="true"/>
+                        "Remember me"
+                    </label>
                 </div>
                     <button type="submit" disabled=submit_disabled value="Login">"Login"</button>
                 <div>
                     {login_result}
                 </div>
             </ActionForm>
+        <p><a href="/forgot-password">"Forgot your password?"</a></p>
+        <p><a href="/login/email">"Log in with an email link instead"</a></p>
         <p><a href="/">"Return to landing page"</a></p>
     }
 }
@@ -319,9 +406,25 @@ pub async fn login(
     csrf: String,
     username: String,
     password: String,
+    // unchecked checkboxes are not submitted at all
+    remember: Option<String>,
 ) -> Result<String, ServerFnError> {
+    let remember = remember.is_some();
     let user_id = match validate_login(csrf, username, SecretString::from(password)).await {
-        Ok(id) => id,
+        Ok(LoginOutcome::Complete(id)) => id,
+        Ok(LoginOutcome::SecondFactorRequired(id)) => {
+            issue_login_challenge_cookie(id, gen_128bit_base64(), remember).await?;
+            axum_redirect("/login/2fa");
+            return Ok(String::from("Please enter your two-factor code"));
+        }
+        // only returned once the password was accepted
+        Err(e @ AppError::Verification(VerificationError::Unverified)) => {
+            return Ok(format!("{}", e));
+        }
+        // returned for registered and unregistered usernames alike
+        Err(e @ AppError::Login(LoginError::Throttled)) => {
+            return Ok(format!("{}", e));
+        }
         Err(e) => {
             log::trace!("login attempt failed: {:?}", e);
             // please note this string is sent to the client,
@@ -330,7 +433,62 @@ pub async fn login(
         }
     };
     let session_id = gen_128bit_base64();
-    issue_session_cookie(user_id, session_id).await?;
+    issue_session_cookie(user_id, session_id, remember).await?;
+    axum_redirect("/");
+    Ok(String::from("Login Successful"))
+}
+
+/// Renders the second step of a login for accounts with two-factor authentication enabled.
+#[component]
+pub fn LoginSecondFactor(
+    action: ServerAction<LoginSecondFactor>,
+    is_routing: ReadSignal<bool>,
+) -> impl IntoView {
+    let (login_result, set_login_result) = signal(" ".to_string());
+
+    Effect::new(move |_| {
+        action.version().get();
+        match action.value().get() {
+            Some(Ok(val)) => set_login_result.set(val),
+            Some(Err(ServerFnError::ServerError(e))) => set_login_result.set(e.to_string()),
+            _ => {}
+        };
+    });
+
+    Effect::new(move |_| {
+        is_routing.get();
+        set_login_result.set(String::default());
+    });
+
+    view! {
+        <ActionForm action=action>
+                <CSRFField purpose=CsrfPurpose::SecondFactor/>
+                <div>
+                    <label>"Authenticator or Recovery Code: "
+                        <input type="text" autocomplete="one-time-code" maxlength=SECOND_FACTOR_CODE_MAX_LEN_STR minlength=TOTP_CODE_LEN_STR name="code" required/>
+                    </label>
+                </div>
+                    <button type="submit" value="Verify">"Verify"</button>
+                <div>
+                    {login_result}
+                </div>
+            </ActionForm>
+        <p><a href="/login">"Start over"</a></p>
+    }
+}
+
+#[server(LoginSecondFactor, "/api")]
+pub async fn login_second_factor(csrf: String, code: String) -> Result<String, ServerFnError> {
+    let (user_id, remember) = match validate_second_factor(csrf, code).await {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            log::trace!("second factor attempt failed: {:?}", e);
+            return Ok(format!("{}", e));
+        }
+    };
+    destroy_login_challenge_cookie();
+    let session_id = gen_128bit_base64();
+    issue_session_cookie(user_id, session_id, remember).await?;
     axum_redirect("/");
     Ok(String::from("Login Successful"))
 }
@@ -345,12 +503,48 @@ pub fn Signup(action: ServerAction<Signup>, is_routing: ReadSignal<bool>) -> imp
 
     let (signup_result, set_signup_result) = signal(String::from(" "));
 
+    // a solved challenge is used up by every attempt, so each attempt gets a new one
+    let pow_resource =
+        Resource::new(move || action.version().get(), |_| get_signup_challenge());
+    let (pow_solution, set_pow_solution) = signal(None::<(String, String)>);
+
+    // the same estimate the server checks, so the meter agrees with its verdict
+    let (username, set_username) = signal(String::new());
+    let (display_name, set_display_name) = signal(String::new());
+    let (email, set_email) = signal(String::new());
+    let (password, set_password) = signal(String::new());
+    let strength = Memo::new(move |_| {
+        let password = password.get();
+        (!password.is_empty()).then(|| {
+            estimate_strength(
+                &password,
+                &[&username.get(), &display_name.get(), &email.get()],
+            )
+        })
+    });
+
     Effect::new(move |_| match action.value().get() {
         Some(Ok(res)) => set_signup_result.set(res),
         Some(Err(e)) => set_signup_result.set(format!("Error processing request: {e}")),
         None => {}
     });
 
+    // effects only run in the browser, which is where the work has to happen
+    Effect::new(move |_| {
+        set_pow_solution.set(None);
+        if let Some(Ok(challenge)) = pow_resource.get() {
+            spawn_local(async move {
+                let nonce = solve_challenge(&challenge).await;
+                // a newer challenge may have arrived while this one was being solved
+                if let Some(Ok(current)) = pow_resource.get_untracked() {
+                    if current == challenge {
+                        set_pow_solution.set(Some((challenge.challenge, nonce.to_string())));
+                    }
+                }
+            });
+        }
+    });
+
     Effect::new(move |_| {
         is_routing.get();
         set_signup_result.set(String::from(" "));
@@ -359,20 +553,23 @@ pub fn Signup(action: ServerAction<Signup>, is_routing: ReadSignal<bool>) -> imp
     view! {
         <h2>"Sign Up"</h2>
         <ActionForm action=action>
-                <CSRFField/>
+                <CSRFField purpose=CsrfPurpose::Signup/>
             <div>
                 <label>"Username: "
-                    <input type="text" maxlength=USERNAME_MAX_LEN_STR minlength=USERNAME_MIN_LEN_STR name="username" required class="auth-input"/>
+                    <input type="text" maxlength=USERNAME_MAX_LEN_STR minlength=USERNAME_MIN_LEN_STR name="username" required class="auth-input"
+                        on:input=move |ev| set_username.set(event_target_value(&ev))/>
                 </label>
             </div>
             <div>
                 <label>"Display Name: "
-                    <input type="text" maxlength=DISPLAY_NAME_MAX_LEN minlength=DISPLAY_NAME_MIN_LEN name="display" required/>
+                    <input type="text" maxlength=DISPLAY_NAME_MAX_LEN minlength=DISPLAY_NAME_MIN_LEN name="display" required
+                        on:input=move |ev| set_display_name.set(event_target_value(&ev))/>
                 </label>
             </div>
             <div>
                 <label>"E-Mail Address: "
-                    <input type="email" name="email" required/>
+                    <input type="email" name="email" required
+                        on:input=move |ev| set_email.set(event_target_value(&ev))/>
                 </label>
             </div>
             <div>
@@ -382,15 +579,36 @@ pub fn Signup(action: ServerAction<Signup>, is_routing: ReadSignal<bool>) -> imp
             </div>
             <div>
                 <label>"Password: "
-                    <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password" required class="auth-input"/>
+                    <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password" required class="auth-input"
+                        on:input=move |ev| set_password.set(event_target_value(&ev))/>
                 </label>
             </div>
+            { move || strength.get().map(|strength| view! {
+                <div>
+                    <label>"Strength: "
+                        <meter min="0" max="4" low=PASSWORD_MIN_SCORE optimum="4" value=strength.score></meter>
+                    </label>
+                    " "{ match strength.acceptable() {
+                        true => "Strong enough",
+                        false => "Too easy to guess",
+                    }}
+                </div>
+                <div>{strength.feedback()}</div>
+            })}
             <div>
                 <label>"Password (Confirmation): "
                     <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password_confirmation" required/>
                 </label>
             </div>
-                <button type="submit" disabled=submit_disabled>"Sign Up"</button>
+            <input type="hidden" name="pow_challenge" value=move || pow_solution.get().map(|(challenge, _)| challenge).unwrap_or_default()/>
+            <input type="hidden" name="pow_nonce" value=move || pow_solution.get().map(|(_, nonce)| nonce).unwrap_or_default()/>
+                <button type="submit" disabled=move || submit_disabled || pow_solution.get().is_none()>"Sign Up"</button>
+            <div>
+                { move || match pow_solution.get() {
+                    Some(_) => String::default(),
+                    None => String::from("Checking your browser..."),
+                }}
+            </div>
             <div>
                 {signup_result}
             </div>
@@ -399,7 +617,13 @@ pub fn Signup(action: ServerAction<Signup>, is_routing: ReadSignal<bool>) -> imp
     }
 }
 
+#[server(GetSignupChallenge, "/api")]
+pub async fn get_signup_challenge() -> Result<PowChallenge, ServerFnError> {
+    Ok(issue_pow_challenge().await?)
+}
+
 #[server(Signup, "/api")]
+#[allow(clippy::too_many_arguments)]
 pub async fn signup(
     csrf: String,
     username: String,
@@ -408,9 +632,13 @@ pub async fn signup(
     email_confirmation: String,
     password: String,
     password_confirmation: String,
+    pow_challenge: String,
+    pow_nonce: String,
 ) -> Result<String, ServerFnError> {
-    let user_id = match validate_registration(
+    let outcome = match validate_registration(
         csrf,
+        pow_challenge,
+        pow_nonce,
         username,
         display,
         email,
@@ -420,16 +648,31 @@ pub async fn signup(
     )
     .await
     {
-        Ok(id) => id,
+        Ok(outcome) => outcome,
         Err(e) => {
             log::trace!("signup attempt failed: {:?}, {}", e, e);
             return Ok(format!("{}", e));
         }
     };
-    let session_id = gen_128bit_base64();
-    issue_session_cookie(user_id, session_id).await?;
-    axum_redirect("/");
-    Ok(String::from("Registration Successful"))
+    // a taken address is answered exactly like a new account, only the mail differs,
+    // so signups never log in directly
+    match outcome {
+        RegistrationOutcome::Created(user_id) => {
+            if let Err(e) = send_verification_email(user_id).await {
+                // the account exists either way, the email can be resent from the settings page
+                log::error!("could not send verification email for {user_id}: {e}");
+            }
+        }
+        RegistrationOutcome::EmailTaken(owner) => {
+            if let Err(e) = send_signup_notice(owner).await {
+                log::error!("could not send signup notice for {owner}: {e}");
+            }
+        }
+    }
+    Ok(String::from(
+        "Registration Successful. Please verify your email address using the link we sent \
+         you, then log in.",
+    ))
 }
 
 #[component]
@@ -1,2 +1,4 @@
 pub mod csrf;
 pub mod logheader;
+pub mod oidc;
+pub mod passkey;
@@ -1,17 +1,20 @@
+use crate::defs::CsrfPurpose;
 use cfg_if::cfg_if;
 use leptos::{either::Either, prelude::*};
 cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::rate_limit::{rate_limit, LimitedAction};
     use crate::security::generate_csrf;
 
 }}
 
 /// This component forces SSR to resolve in an async route.
 /// This will add a hidden input field to any ActionForm which can be used
-/// to mitigate CSRF attacks using a __Host-csrf cookie
+/// to mitigate CSRF attacks using a __Host-csrf cookie.
+/// The token is only accepted by the server function for `purpose`.
 #[allow(unused_braces)]
 #[component]
-pub fn CSRFField() -> impl IntoView {
-    let csrf_resource = Resource::new(|| (), |_| issue_csrf());
+pub fn CSRFField(purpose: CsrfPurpose) -> impl IntoView {
+    let csrf_resource = Resource::new(move || purpose, issue_csrf);
 
     view! {
         <Transition fallback= || view! {<p>"Loading..."</p>}>
@@ -33,6 +36,7 @@ pub fn CSRFField() -> impl IntoView {
 
 // #[server(IssueCSRF, "/api")]
 #[server]
-async fn issue_csrf() -> Result<String, ServerFnError> {
-    Ok(generate_csrf())
+pub async fn issue_csrf(purpose: CsrfPurpose) -> Result<String, ServerFnError> {
+    rate_limit(LimitedAction::Csrf)?;
+    Ok(generate_csrf(purpose))
 }
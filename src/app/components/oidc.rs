@@ -0,0 +1,69 @@
+use crate::{
+    app::components::csrf::issue_csrf, defs::CsrfPurpose, security::oidc::OidcProviderSummary,
+};
+use cfg_if::cfg_if;
+use leptos::{either::Either, prelude::*};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::oidc::{oidc_provider_summaries, start_oidc_login};
+    use leptos_axum::redirect as axum_redirect;
+}}
+
+/// Renders a button for every configured OpenID Connect provider, nothing if there are none.
+#[component]
+pub fn OidcButtons() -> impl IntoView {
+    let action = ServerAction::<StartOidcLogin>::new();
+    let providers = Resource::new(|| (), |_| get_oidc_providers());
+    let csrf_resource = Resource::new(|| CsrfPurpose::OidcLogin, issue_csrf);
+
+    let (oidc_result, set_oidc_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Err(ServerFnError::ServerError(e))) => set_oidc_result.set(e),
+        Some(Err(e)) => set_oidc_result.set(format!("{e}")),
+        _ => {}
+    });
+
+    view! {
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                match (providers.get(), csrf_resource.get()) {
+                    (Some(Ok(providers)), Some(Ok(csrf))) => Either::Left(
+                        providers
+                            .into_iter()
+                            .map(|provider: OidcProviderSummary| {
+                                let csrf = csrf.clone();
+                                view! {
+                                    <ActionForm action=action>
+                                        <input type="hidden" name="csrf" value=csrf/>
+                                        <input type="hidden" name="provider" value=provider.id/>
+                                        <button type="submit">{format!("Continue with {}", provider.name)}</button>
+                                    </ActionForm>
+                                }
+                            })
+                            .collect_view(),
+                    ),
+                    (Some(Err(e)), _) | (_, Some(Err(e))) => Either::Right(Some(view! {
+                        { format!("Page Load Failed: {e}. Please reload the page or try again later.") }
+                    })),
+                    _ => Either::Right(None),
+                }
+            }}
+        </Transition>
+        <div>
+            {oidc_result}
+        </div>
+    }
+}
+
+#[server(GetOidcProviders, "/api")]
+pub async fn get_oidc_providers() -> Result<Vec<OidcProviderSummary>, ServerFnError> {
+    Ok(oidc_provider_summaries()?)
+}
+
+#[server(StartOidcLogin, "/api")]
+pub async fn start_oidc(csrf: String, provider: String) -> Result<(), ServerFnError> {
+    let url = start_oidc_login(csrf, provider).await?;
+    axum_redirect(&url);
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crate::{app::components::csrf::issue_csrf, defs::CsrfPurpose};
+use cfg_if::cfg_if;
+use leptos::{either::Either, prelude::*, server_fn::codec::Json, spawn::spawn_local};
+use webauthn_rs_proto::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse,
+};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::issue_session_cookie;
+    use crate::defs::{AppError, VerificationError};
+    use crate::security::{
+        gen_128bit_base64,
+        passkeys::{begin_passkey_login, complete_passkey_login},
+    };
+    use leptos_axum::redirect as axum_redirect;
+} else if #[cfg(feature = "hydrate")] {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+}}
+
+/// Asks the browser to create a new passkey answering the registration challenge.
+pub async fn create_passkey(
+    challenge: CreationChallengeResponse,
+) -> Result<RegisterPublicKeyCredential, String> {
+    cfg_if! { if #[cfg(feature = "hydrate")] {
+        let options = web_sys::CredentialCreationOptions::from(challenge);
+        let promise = window()
+            .navigator()
+            .credentials()
+            .create_with_options(&options)
+            .map_err(|e| format!("Passkeys are not supported by this browser: {e:?}"))?;
+        let credential = JsFuture::from(promise)
+            .await
+            .map_err(|_| String::from("The passkey request was cancelled."))?;
+        Ok(RegisterPublicKeyCredential::from(
+            credential.unchecked_into::<web_sys::PublicKeyCredential>(),
+        ))
+    } else {
+        let _ = challenge;
+        Err(String::from("Passkeys can only be created in the browser."))
+    }}
+}
+
+/// Asks the browser to sign the login challenge with one of its passkeys.
+pub async fn request_passkey(
+    challenge: RequestChallengeResponse,
+) -> Result<PublicKeyCredential, String> {
+    cfg_if! { if #[cfg(feature = "hydrate")] {
+        let options = web_sys::CredentialRequestOptions::from(challenge);
+        let promise = window()
+            .navigator()
+            .credentials()
+            .get_with_options(&options)
+            .map_err(|e| format!("Passkeys are not supported by this browser: {e:?}"))?;
+        let credential = JsFuture::from(promise)
+            .await
+            .map_err(|_| String::from("The passkey request was cancelled."))?;
+        Ok(PublicKeyCredential::from(
+            credential.unchecked_into::<web_sys::PublicKeyCredential>(),
+        ))
+    } else {
+        let _ = challenge;
+        Err(String::from("Passkeys can only be used in the browser."))
+    }}
+}
+
+/// Renders a button that logs in with a passkey instead of a username and password.
+#[component]
+pub fn PasskeyLogin(action: ServerAction<FinishPasskeyLogin>) -> impl IntoView {
+    let csrf_resource = Resource::new(|| CsrfPurpose::PasskeyLogin, issue_csrf);
+
+    let (passkey_result, set_passkey_result) = signal(String::from(" "));
+    let (remember, set_remember) = signal(false);
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(val)) => set_passkey_result.set(val),
+        Some(Err(ServerFnError::ServerError(e))) => set_passkey_result.set(e),
+        _ => {}
+    });
+
+    let sign_in = move |csrf: String| {
+        spawn_local(async move {
+            let challenge = match start_passkey_login(csrf.clone()).await {
+                Ok(challenge) => challenge,
+                Err(ServerFnError::ServerError(e)) => return set_passkey_result.set(e),
+                Err(e) => return set_passkey_result.set(format!("{e}")),
+            };
+            match request_passkey(challenge).await {
+                Ok(credential) => {
+                    action.dispatch(FinishPasskeyLogin {
+                        csrf,
+                        credential,
+                        remember: remember.get_untracked(),
+                    });
+                }
+                Err(e) => set_passkey_result.set(e),
+            }
+        });
+    };
+
+    view! {
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                csrf_resource.get().map(|csrf| match csrf {
+                    Err(e) => Either::Left(view! {
+                        { format!("Page Load Failed: {e}. Please reload the page or try again later.") }
+                    }),
+                    Ok(csrf) => Either::Right(view! {
+                        <button type="button" on:click=move |_| sign_in(csrf.clone())>
+                            "Log in with a Passkey"
+                        </button>
+                    }),
+                })
+            }}
+        </Transition>
+        <div>
+            <label>
+                <input type="checkbox" on:change=move |ev| set_remember.set(event_target_checked(&ev))/>
+                "Remember me"
+            </label>
+        </div>
+        <div>
+            {passkey_result}
+        </div>
+    }
+}
+
+#[server(StartPasskeyLogin, "/api")]
+pub async fn start_passkey_login(
+    csrf: String,
+) -> Result<RequestChallengeResponse, ServerFnError> {
+    Ok(begin_passkey_login(csrf).await?)
+}
+
+#[server(name = FinishPasskeyLogin, prefix = "/api", input = Json)]
+pub async fn finish_passkey_login(
+    csrf: String,
+    credential: PublicKeyCredential,
+    remember: bool,
+) -> Result<String, ServerFnError> {
+    let user_id = match complete_passkey_login(csrf, credential).await {
+        Ok(id) => id,
+        // only returned once the passkey was accepted
+        Err(e @ AppError::Verification(VerificationError::Unverified)) => {
+            return Ok(format!("{}", e));
+        }
+        Err(e) => {
+            log::trace!("passkey login attempt failed: {:?}", e);
+            // please note this string is sent to the client,
+            //   provide as little information as possible as to the reason
+            return Ok(String::from("Login failed, please try again"));
+        }
+    };
+    let session_id = gen_128bit_base64();
+    issue_session_cookie(user_id, session_id, remember).await?;
+    axum_redirect("/");
+    Ok(String::from("Login Successful"))
+}
@@ -45,7 +45,7 @@ pub fn ErrorTemplate(#[prop(into)] errors: MaybeSignal<Errors>) -> impl IntoView
     {
         let response = use_context::<ResponseOptions>();
         if let Some(response) = response {
-            if errors.read_untracked().len() > 0 {
+            if !errors.read_untracked().is_empty() {
                 response.set_status(errors.read_untracked()[0].status_code());
             } else {
                 response.set_status(StatusCode::INTERNAL_SERVER_ERROR);
@@ -95,11 +95,11 @@ pub fn HomepageLoggedIn(user_data: APIUserData) -> impl IntoView {
         WEBSOCKET_URL,
         WebSysWebSocketOptions::default()
             .immediate(false)
-            .on_open(on_open_callback.clone())
-            .on_close(on_close_callback.clone())
-            .on_error(on_error_callback.clone())
-            .on_message(on_message_callback.clone())
-            .on_message_bytes(on_message_bytes_callback.clone()),
+            .on_open(on_open_callback)
+            .on_close(on_close_callback)
+            .on_error(on_error_callback)
+            .on_message(on_message_callback)
+            .on_message_bytes(on_message_bytes_callback),
     );
 
     let open_connection = move |_| {
@@ -149,7 +149,7 @@ pub fn HomepageLoggedIn(user_data: APIUserData) -> impl IntoView {
         <button on:click=send_message disabled=move || {!connected() || disable_all_buttons()}>"Send"</button>
         <button on:click=send_byte_message disabled=move || {!connected() || disable_all_buttons()}>"Send bytes"</button>
         <button on:click=close_connection disabled=move || {!connected()|| disable_all_buttons()}>"Disconnect"</button>
-        <button on:click=move |_| set_history.set(vec![]) disabled=move || history.get().len() <= 0>"Clear"</button>
+        <button on:click=move |_| set_history.set(vec![]) disabled=move || history.get().is_empty()>"Clear"</button>
         <p>"Websocket history:"</p>
         //alternate method:
         //{ move || {
@@ -0,0 +1,136 @@
+use crate::app::components::csrf::CSRFField;
+use crate::defs::*;
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use leptos_router::hooks::use_params_map;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{issue_login_challenge_cookie, issue_session_cookie};
+    use crate::security::{
+        gen_128bit_base64,
+        magic_link::{login_with_magic_link, request_magic_link},
+        LoginOutcome,
+    };
+    use leptos_axum::redirect as axum_redirect;
+}}
+
+/// Renders the page asking for the account a login link should be sent to.
+#[component]
+pub fn EmailLogin() -> impl IntoView {
+    let action = ServerAction::<RequestLoginLink>::new();
+
+    let (link_result, set_link_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(val)) => set_link_result.set(val),
+        Some(Err(ServerFnError::ServerError(e))) => set_link_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h1>"Log in with an Email Link"</h1>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::RequestMagicLink/>
+            <div>
+                <label>"Username or Email: "
+                    <input type="text" name="identifier" required/>
+                </label>
+            </div>
+            <button type="submit">"Send Login Link"</button>
+            <div>
+                {link_result}
+            </div>
+        </ActionForm>
+        <p><a href="/login">"Return to login"</a></p>
+    }
+}
+
+#[server(RequestLoginLink, "/api")]
+pub async fn request_login_link(
+    csrf: String,
+    identifier: String,
+) -> Result<String, ServerFnError> {
+    request_magic_link(csrf, identifier).await?;
+    // please note this string is sent to the client,
+    //   it must be the same whether or not an account matched
+    Ok(String::from(
+        "If an account matches, a login link has been sent to its email address. Open it in \
+         this browser.",
+    ))
+}
+
+/// Renders the page a login link email links to.
+/// The link is only used when the button is pressed, so mail scanners that open links
+/// cannot use it up.
+#[component]
+pub fn MagicLinkLogin(
+    action: ServerAction<RedeemLoginLink>,
+    is_routing: ReadSignal<bool>,
+) -> impl IntoView {
+    let params = use_params_map();
+    let token = move || params.read().get("token").unwrap_or_default();
+
+    let (login_result, set_login_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(val)) => set_login_result.set(val),
+        Some(Err(ServerFnError::ServerError(e))) => set_login_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| {
+        is_routing.get();
+        set_login_result.set(String::from(" "));
+    });
+
+    view! {
+        <h1>"Log In"</h1>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::MagicLinkLogin/>
+            <input type="hidden" name="token" value=token/>
+            <div>
+                <label>
+                    <input type="checkbox" name="remember" value="true"/>
+                    "Remember me"
+                </label>
+            </div>
+            <button type="submit">"Log in"</button>
+            <div>
+                {login_result}
+            </div>
+        </ActionForm>
+        <p><a href="/login">"Return to login"</a></p>
+    }
+}
+
+#[server(RedeemLoginLink, "/api")]
+pub async fn redeem_login_link(
+    csrf: String,
+    token: String,
+    // unchecked checkboxes are not submitted at all
+    remember: Option<String>,
+) -> Result<String, ServerFnError> {
+    let remember = remember.is_some();
+    let user_id = match login_with_magic_link(csrf, token).await {
+        Ok(LoginOutcome::Complete(id)) => id,
+        Ok(LoginOutcome::SecondFactorRequired(id)) => {
+            issue_login_challenge_cookie(id, gen_128bit_base64(), remember).await?;
+            axum_redirect("/login/2fa");
+            return Ok(String::from("Please enter your two-factor code"));
+        }
+        Err(
+            e @ (AppError::MagicLink(_)
+            | AppError::Verification(VerificationError::Unverified)),
+        ) => {
+            return Ok(format!("{}", e));
+        }
+        Err(e) => {
+            log::trace!("login link attempt failed: {:?}", e);
+            return Ok(String::from("Login failed, please try again"));
+        }
+    };
+    let session_id = gen_128bit_base64();
+    issue_session_cookie(user_id, session_id, remember).await?;
+    axum_redirect("/");
+    Ok(String::from("Login Successful"))
+}
@@ -0,0 +1,145 @@
+use crate::{app::components::csrf::CSRFField, defs::*};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use leptos_router::hooks::{use_params_map, use_query_map};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{issue_login_challenge_cookie, issue_session_cookie};
+    use crate::security::{
+        gen_128bit_base64,
+        oidc::{complete_oidc_signup, finish_oidc_login, OidcOutcome},
+        verification::check_login_allowed,
+    };
+    use leptos_axum::redirect as axum_redirect;
+}}
+
+/// Renders the page a provider sends the browser back to. The login is finished while the
+/// page is rendered, so it redirects onwards before anything is shown unless it failed.
+#[component]
+pub fn OidcCallback() -> impl IntoView {
+    let params = use_params_map();
+    let query = use_query_map();
+    let callback = Resource::new(
+        move || {
+            (
+                params.read().get("provider").unwrap_or_default(),
+                query.read().get("code"),
+                query.read().get("state").unwrap_or_default(),
+                query.read().get("error"),
+            )
+        },
+        |(provider, code, state, error)| complete_oidc_login(provider, code, state, error),
+    );
+
+    view! {
+        <h1>"Signing In"</h1>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                callback.get().map(|result| match result {
+                    Ok(message) => message,
+                    Err(ServerFnError::ServerError(e)) => e,
+                    Err(e) => format!("{e}"),
+                })
+            }}
+        </Transition>
+        <p><a href="/login">"Return to login"</a></p>
+    }
+}
+
+#[server(CompleteOidcLogin, "/api")]
+pub async fn complete_oidc_login(
+    provider: String,
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+) -> Result<String, ServerFnError> {
+    let outcome = match finish_oidc_login(provider, code, state, error).await {
+        Ok(outcome) => outcome,
+        // only returned once the provider vouched for the user
+        Err(
+            e @ (AppError::Oidc(_) | AppError::Verification(VerificationError::Unverified)),
+        ) => {
+            log::trace!("oidc login attempt failed: {:?}", e);
+            return Ok(format!("{}", e));
+        }
+        Err(e) => {
+            log::trace!("oidc login attempt failed: {:?}", e);
+            return Ok(String::from("Login failed, please try again"));
+        }
+    };
+    match outcome {
+        OidcOutcome::Complete(user_id) => {
+            issue_session_cookie(user_id, gen_128bit_base64(), false).await?;
+            axum_redirect("/");
+            Ok(String::from("Login Successful"))
+        }
+        OidcOutcome::SecondFactorRequired(user_id) => {
+            issue_login_challenge_cookie(user_id, gen_128bit_base64(), false).await?;
+            axum_redirect("/login/2fa");
+            Ok(String::from("Please enter your two-factor code"))
+        }
+        OidcOutcome::DisplayNameRequired => {
+            axum_redirect("/signup/oidc");
+            Ok(String::from("Please choose a display name"))
+        }
+    }
+}
+
+/// Renders the last step of a first sign in through a provider, where the new account gets
+/// its display name.
+#[component]
+pub fn OidcSignup(
+    action: ServerAction<ChooseDisplayName>,
+    is_routing: ReadSignal<bool>,
+) -> impl IntoView {
+    let (signup_result, set_signup_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(res)) => set_signup_result.set(res),
+        Some(Err(ServerFnError::ServerError(e))) => set_signup_result.set(e),
+        Some(Err(e)) => set_signup_result.set(format!("Error processing request: {e}")),
+        None => {}
+    });
+
+    Effect::new(move |_| {
+        is_routing.get();
+        set_signup_result.set(String::from(" "));
+    });
+
+    view! {
+        <h2>"Choose a Display Name"</h2>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::OidcSignup/>
+            <div>
+                <label>"Display Name: "
+                    <input type="text" maxlength=DISPLAY_NAME_MAX_LEN minlength=DISPLAY_NAME_MIN_LEN name="display" required/>
+                </label>
+            </div>
+            <button type="submit">"Create Account"</button>
+            <div>
+                {signup_result}
+            </div>
+        </ActionForm>
+        <a href="/">"Go Back"</a>
+    }
+}
+
+#[server(ChooseDisplayName, "/api")]
+pub async fn choose_display_name(
+    csrf: String,
+    display: String,
+) -> Result<String, ServerFnError> {
+    let user_id = match complete_oidc_signup(csrf, display).await {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            log::trace!("oidc signup attempt failed: {:?}, {}", e, e);
+            return Ok(format!("{}", e));
+        }
+    };
+    if let Err(e) = check_login_allowed(user_id).await {
+        return Ok(format!("{}", e));
+    }
+    issue_session_cookie(user_id, gen_128bit_base64(), false).await?;
+    axum_redirect("/");
+    Ok(String::from("Registration Successful"))
+}
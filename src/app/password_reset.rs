@@ -0,0 +1,108 @@
+use crate::app::components::csrf::CSRFField;
+use crate::defs::*;
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use leptos_router::hooks::use_params_map;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::password_reset::{request_password_reset, reset_password};
+    use secrecy::SecretString;
+}}
+
+/// Renders the page asking for the account a reset link should be sent to.
+#[component]
+pub fn ForgotPassword() -> impl IntoView {
+    let action = ServerAction::<RequestReset>::new();
+
+    let (reset_result, set_reset_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(val)) => set_reset_result.set(val),
+        Some(Err(ServerFnError::ServerError(e))) => set_reset_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h1>"Forgot Password"</h1>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::ForgotPassword/>
+            <div>
+                <label>"Username or Email: "
+                    <input type="text" name="identifier" required/>
+                </label>
+            </div>
+            <button type="submit">"Send Reset Link"</button>
+            <div>
+                {reset_result}
+            </div>
+        </ActionForm>
+        <p><a href="/login">"Return to login"</a></p>
+    }
+}
+
+#[server(RequestReset, "/api")]
+pub async fn request_reset(csrf: String, identifier: String) -> Result<String, ServerFnError> {
+    request_password_reset(csrf, identifier).await?;
+    // please note this string is sent to the client,
+    //   it must be the same whether or not an account matched
+    Ok(String::from(
+        "If an account matches, a reset link has been sent to its email address.",
+    ))
+}
+
+/// Renders the page a password reset email links to.
+#[component]
+pub fn ResetPassword() -> impl IntoView {
+    let action = ServerAction::<SetNewPassword>::new();
+    let params = use_params_map();
+    let token = move || params.read().get("token").unwrap_or_default();
+
+    let (reset_result, set_reset_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(())) => set_reset_result.set(String::from(
+            "Your password has been changed, please log in with your new password.",
+        )),
+        Some(Err(ServerFnError::ServerError(e))) => set_reset_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h1>"Choose a New Password"</h1>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::ResetPassword/>
+            <input type="hidden" name="token" value=token/>
+            <div>
+                <label>"New Password: "
+                    <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password" required/>
+                </label>
+            </div>
+            <div>
+                <label>"Confirm New Password: "
+                    <input type="password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password_confirmation" required/>
+                </label>
+            </div>
+            <button type="submit">"Set Password"</button>
+            <div>
+                {reset_result}
+            </div>
+        </ActionForm>
+        <p><a href="/login">"Return to login"</a></p>
+    }
+}
+
+#[server(SetNewPassword, "/api")]
+pub async fn set_new_password(
+    csrf: String,
+    token: String,
+    password: String,
+    password_confirmation: String,
+) -> Result<(), ServerFnError> {
+    Ok(reset_password(
+        csrf,
+        token,
+        SecretString::from(password),
+        SecretString::from(password_confirmation),
+    )
+    .await?)
+}
@@ -0,0 +1,8 @@
+pub mod admin;
+pub mod api_tokens;
+pub mod email;
+pub mod passkeys;
+pub mod password;
+pub mod sessions;
+pub mod two_factor;
+pub mod username;
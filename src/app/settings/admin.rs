@@ -0,0 +1,68 @@
+use crate::{
+    app::components::csrf::CSRFField, defs::CsrfPurpose, session_cache::SessionCacheStats,
+};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::admin::{is_admin, rotate_csrf_key, session_cache_stats};
+}}
+
+/// Renders the settings section for administrators, nothing for everyone else.
+#[component]
+pub fn AdminSettings() -> impl IntoView {
+    let rotate = ServerAction::<RotateCsrfKey>::new();
+    let admin = Resource::new(|| (), |_| get_is_admin());
+    let cache_stats = Resource::new(|| (), |_| get_session_cache_stats());
+
+    let (admin_result, set_admin_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match rotate.value().get() {
+        Some(Ok(())) => set_admin_result.set(String::from(
+            "Form key rotated, forms opened before now keep working until the next rotation.",
+        )),
+        Some(Err(ServerFnError::ServerError(e))) => set_admin_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <Transition>
+            <Show when=move || matches!(admin.get(), Some(Ok(true)))>
+                <h2>"Administration"</h2>
+                <ActionForm action=rotate>
+                    <CSRFField purpose=CsrfPurpose::RotateCsrfKey/>
+                    <button type="submit">"Rotate Form Key"</button>
+                </ActionForm>
+                <div>
+                    {admin_result}
+                </div>
+                <p>
+                    {move || cache_stats.get().and_then(Result::ok).map(|stats| format!(
+                        "Session cache: {} of {} session lookups and {} of {} user lookups \
+                         answered from memory, {} entries.",
+                        stats.session_hits,
+                        stats.session_hits + stats.session_misses,
+                        stats.user_hits,
+                        stats.user_hits + stats.user_misses,
+                        stats.entries,
+                    ))}
+                </p>
+            </Show>
+        </Transition>
+    }
+}
+
+#[server(GetIsAdmin, "/api")]
+pub async fn get_is_admin() -> Result<bool, ServerFnError> {
+    Ok(is_admin().await?)
+}
+
+#[server(RotateCsrfKey, "/api")]
+pub async fn rotate_csrf_key_action(csrf: String) -> Result<(), ServerFnError> {
+    Ok(rotate_csrf_key(csrf).await?)
+}
+
+#[server(GetSessionCacheStats, "/api")]
+pub async fn get_session_cache_stats() -> Result<SessionCacheStats, ServerFnError> {
+    Ok(session_cache_stats().await?)
+}
@@ -0,0 +1,147 @@
+use crate::{
+    app::components::csrf::CSRFField,
+    defs::{ApiTokenScope, CsrfPurpose, API_TOKEN_LIFETIMES_DAYS, API_TOKEN_NAME_MAX_LEN_STR},
+    security::api_tokens::ApiTokenSummary,
+};
+use cfg_if::cfg_if;
+use leptos::{either::Either, prelude::*};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::api_tokens::{api_token_summaries, create_api_token, revoke_api_token};
+}}
+
+/// Renders the settings section listing, creating and revoking personal access tokens.
+#[component]
+pub fn ApiTokenSettings() -> impl IntoView {
+    let create = ServerAction::<CreateApiToken>::new();
+    let revoke = ServerAction::<DeleteApiToken>::new();
+    let tokens = Resource::new(
+        move || (create.version().get(), revoke.version().get()),
+        move |_| get_api_tokens(),
+    );
+
+    let (new_token, set_new_token) = signal(None::<String>);
+    let (token_result, set_token_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match create.value().get() {
+        Some(Ok(token)) => {
+            set_new_token.set(Some(token));
+            set_token_result.set(String::from(
+                "Token created. Copy it now, it will not be shown again.",
+            ))
+        }
+        Some(Err(ServerFnError::ServerError(e))) => {
+            set_new_token.set(None);
+            set_token_result.set(e)
+        }
+        _ => {}
+    });
+
+    Effect::new(move |_| match revoke.value().get() {
+        Some(Ok(())) => set_token_result.set(String::from("Token revoked.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_token_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h2>"Access Tokens"</h2>
+        <p>"Scripts can send a token in an " <code>"Authorization: Bearer"</code> " header."</p>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                tokens.get().map(|tokens| match tokens {
+                    Err(e) => Either::Left(view! {
+                        <span>{format!("Could not load access tokens: {e}")}</span>
+                    }),
+                    Ok(tokens) => Either::Right(view! {
+                        <ul>
+                            {tokens.into_iter().map(|token| view! {
+                                <ApiTokenEntry token action=revoke/>
+                            }).collect_view()}
+                        </ul>
+                    }),
+                })
+            }}
+        </Transition>
+        <ActionForm action=create>
+            <CSRFField purpose=CsrfPurpose::CreateApiToken/>
+            <div>
+                <label>"Token Name: "
+                    <input type="text" name="name" maxlength=API_TOKEN_NAME_MAX_LEN_STR required/>
+                </label>
+            </div>
+            <div>
+                <label>"Expires after: "
+                    <select name="lifetime_days">
+                        {API_TOKEN_LIFETIMES_DAYS.into_iter().map(|days| view! {
+                            <option value=days.to_string()>{format!("{days} days")}</option>
+                        }).collect_view()}
+                    </select>
+                </label>
+            </div>
+            <div>
+                <label><input type="checkbox" name="read" value="true" checked/>"Read"</label>
+                <label><input type="checkbox" name="write" value="true"/>"Write"</label>
+                <label><input type="checkbox" name="ws" value="true"/>"Websocket"</label>
+            </div>
+            <button type="submit">"Create Token"</button>
+        </ActionForm>
+        <div>
+            {token_result}
+        </div>
+        { move || new_token.get().map(|token| view! { <pre>{token}</pre> }) }
+    }
+}
+
+#[component]
+fn ApiTokenEntry(
+    token: ApiTokenSummary,
+    action: ServerAction<DeleteApiToken>,
+) -> impl IntoView {
+    let scopes = token
+        .scopes
+        .iter()
+        .map(ApiTokenScope::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    view! {
+        <li>
+            {token.name}" ("{scopes}", created "{token.created}", expires "{token.expiry}")"
+            <ActionForm action=action>
+                <CSRFField purpose=CsrfPurpose::RevokeApiToken/>
+                <input type="hidden" name="token_id" value=token.token_id/>
+                <button type="submit">"Revoke"</button>
+            </ActionForm>
+        </li>
+    }
+}
+
+#[server(GetApiTokens, "/api")]
+pub async fn get_api_tokens() -> Result<Vec<ApiTokenSummary>, ServerFnError> {
+    Ok(api_token_summaries().await?)
+}
+
+#[server(CreateApiToken, "/api")]
+pub async fn add_api_token(
+    csrf: String,
+    name: String,
+    lifetime_days: i64,
+    // unchecked checkboxes are not submitted at all
+    read: Option<String>,
+    write: Option<String>,
+    ws: Option<String>,
+) -> Result<String, ServerFnError> {
+    let scopes = [
+        (read, ApiTokenScope::Read),
+        (write, ApiTokenScope::Write),
+        (ws, ApiTokenScope::Websocket),
+    ]
+    .into_iter()
+    .filter_map(|(checked, scope)| checked.map(|_| scope))
+    .collect();
+    Ok(create_api_token(csrf, name, scopes, lifetime_days).await?)
+}
+
+#[server(DeleteApiToken, "/api")]
+pub async fn delete_api_token(csrf: String, token_id: String) -> Result<(), ServerFnError> {
+    Ok(revoke_api_token(csrf, token_id).await?)
+}
@@ -0,0 +1,63 @@
+use crate::{
+    app::components::csrf::CSRFField, defs::CsrfPurpose,
+    security::verification::EmailVerificationStatus,
+};
+use cfg_if::cfg_if;
+use leptos::{either::EitherOf3, prelude::*};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::verification::{resend_verification_email, verification_status};
+}}
+
+/// Renders the settings section showing the account's email address and whether it is verified.
+#[component]
+pub fn EmailSettings() -> impl IntoView {
+    let resend = ServerAction::<ResendVerification>::new();
+    let status = Resource::new(|| (), |_| get_email_status());
+
+    let (email_result, set_email_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match resend.value().get() {
+        Some(Ok(())) => set_email_result.set(String::from(
+            "A new verification link is on its way, earlier links no longer work.",
+        )),
+        Some(Err(ServerFnError::ServerError(e))) => set_email_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h2>"Email"</h2>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                status.get().map(|status| match status {
+                    Err(e) => EitherOf3::A(view! {
+                        <span>{format!("Could not load email status: {e}")}</span>
+                    }),
+                    Ok(EmailVerificationStatus { email, verified: true }) => EitherOf3::B(view! {
+                        <p>{email}" is verified."</p>
+                    }),
+                    Ok(EmailVerificationStatus { email, verified: false }) => EitherOf3::C(view! {
+                        <p>{email}" is not verified yet."</p>
+                        <ActionForm action=resend>
+                            <CSRFField purpose=CsrfPurpose::ResendVerification/>
+                            <button type="submit">"Resend Verification Email"</button>
+                        </ActionForm>
+                    }),
+                })
+            }}
+        </Transition>
+        <div>
+            {email_result}
+        </div>
+    }
+}
+
+#[server(GetEmailStatus, "/api")]
+pub async fn get_email_status() -> Result<EmailVerificationStatus, ServerFnError> {
+    Ok(verification_status().await?)
+}
+
+#[server(ResendVerification, "/api")]
+pub async fn resend_verification(csrf: String) -> Result<(), ServerFnError> {
+    Ok(resend_verification_email(csrf).await?)
+}
@@ -0,0 +1,157 @@
+use crate::{
+    app::components::{
+        csrf::{issue_csrf, CSRFField},
+        passkey::create_passkey,
+    },
+    defs::{CsrfPurpose, PASSKEY_NAME_MAX_LEN_STR},
+    security::passkeys::PasskeySummary,
+};
+use cfg_if::cfg_if;
+use leptos::{either::Either, prelude::*, server_fn::codec::Json, spawn::spawn_local};
+use webauthn_rs_proto::{CreationChallengeResponse, RegisterPublicKeyCredential};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::passkeys::{
+        begin_passkey_registration, complete_passkey_registration, passkey_summaries,
+        remove_passkey,
+    };
+}}
+
+/// Renders the settings section listing, adding and removing passkeys.
+#[component]
+pub fn PasskeySettings() -> impl IntoView {
+    let csrf_resource = Resource::new(|| CsrfPurpose::PasskeyRegistration, issue_csrf);
+    let register = ServerAction::<FinishPasskeyRegistration>::new();
+    let remove = ServerAction::<DeletePasskey>::new();
+    let passkeys = Resource::new(
+        move || (register.version().get(), remove.version().get()),
+        move |_| get_passkeys(),
+    );
+
+    let (passkey_name, set_passkey_name) = signal(String::default());
+    let (passkey_result, set_passkey_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match register.value().get() {
+        Some(Ok(())) => {
+            set_passkey_name.set(String::default());
+            set_passkey_result.set(String::from("Passkey added."))
+        }
+        Some(Err(ServerFnError::ServerError(e))) => set_passkey_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| match remove.value().get() {
+        Some(Ok(())) => set_passkey_result.set(String::from("Passkey removed.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_passkey_result.set(e),
+        _ => {}
+    });
+
+    let add_passkey = move |csrf: String| {
+        let name = passkey_name.get_untracked();
+        spawn_local(async move {
+            let challenge = match start_passkey_registration(csrf.clone()).await {
+                Ok(challenge) => challenge,
+                Err(ServerFnError::ServerError(e)) => return set_passkey_result.set(e),
+                Err(e) => return set_passkey_result.set(format!("{e}")),
+            };
+            match create_passkey(challenge).await {
+                Ok(credential) => {
+                    register.dispatch(FinishPasskeyRegistration {
+                        csrf,
+                        name,
+                        credential,
+                    });
+                }
+                Err(e) => set_passkey_result.set(e),
+            }
+        });
+    };
+
+    view! {
+        <h2>"Passkeys"</h2>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                passkeys.get().map(|passkeys| match passkeys {
+                    Err(e) => Either::Left(view! {
+                        <span>{format!("Could not load passkeys: {e}")}</span>
+                    }),
+                    Ok(passkeys) => Either::Right(view! {
+                        <ul>
+                            {passkeys.into_iter().map(|passkey| view! {
+                                <PasskeyEntry passkey action=remove/>
+                            }).collect_view()}
+                        </ul>
+                    }),
+                })
+            }}
+        </Transition>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                csrf_resource.get().map(|csrf| match csrf {
+                    Err(e) => Either::Left(view! {
+                        { format!("Page Load Failed: {e}. Please reload the page or try again later.") }
+                    }),
+                    Ok(csrf) => Either::Right(view! {
+                        <div>
+                            <label>"Passkey Name: "
+                                <input type="text" maxlength=PASSKEY_NAME_MAX_LEN_STR
+                                    prop:value=passkey_name
+                                    on:input=move |ev| set_passkey_name.set(event_target_value(&ev))/>
+                            </label>
+                        </div>
+                        <button type="button" on:click=move |_| add_passkey(csrf.clone())>
+                            "Add a Passkey"
+                        </button>
+                    }),
+                })
+            }}
+        </Transition>
+        <div>
+            {passkey_result}
+        </div>
+    }
+}
+
+#[component]
+fn PasskeyEntry(
+    passkey: PasskeySummary,
+    action: ServerAction<DeletePasskey>,
+) -> impl IntoView {
+    let last_used = passkey.last_used.unwrap_or(String::from("never"));
+    view! {
+        <li>
+            {passkey.name}" (added "{passkey.created}", last used "{last_used}")"
+            <ActionForm action=action>
+                <CSRFField purpose=CsrfPurpose::RemovePasskey/>
+                <input type="hidden" name="credential_id" value=passkey.credential_id/>
+                <button type="submit">"Remove"</button>
+            </ActionForm>
+        </li>
+    }
+}
+
+#[server(GetPasskeys, "/api")]
+pub async fn get_passkeys() -> Result<Vec<PasskeySummary>, ServerFnError> {
+    Ok(passkey_summaries().await?)
+}
+
+#[server(StartPasskeyRegistration, "/api")]
+pub async fn start_passkey_registration(
+    csrf: String,
+) -> Result<CreationChallengeResponse, ServerFnError> {
+    Ok(begin_passkey_registration(csrf).await?)
+}
+
+#[server(name = FinishPasskeyRegistration, prefix = "/api", input = Json)]
+pub async fn finish_passkey_registration(
+    csrf: String,
+    name: String,
+    credential: RegisterPublicKeyCredential,
+) -> Result<(), ServerFnError> {
+    Ok(complete_passkey_registration(csrf, name, credential).await?)
+}
+
+#[server(DeletePasskey, "/api")]
+pub async fn delete_passkey(csrf: String, credential_id: String) -> Result<(), ServerFnError> {
+    Ok(remove_passkey(csrf, credential_id).await?)
+}
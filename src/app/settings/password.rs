@@ -0,0 +1,73 @@
+use crate::{app::components::csrf::CSRFField, defs::*};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::change_password;
+    use secrecy::SecretString;
+}}
+
+/// Renders the settings section for replacing the account's password.
+#[component]
+pub fn PasswordSettings() -> impl IntoView {
+    let action = ServerAction::<ChangePassword>::new();
+
+    let (password_result, set_password_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(())) => set_password_result.set(String::from("Password changed.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_password_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h2>"Change Password"</h2>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::ChangePassword/>
+            <div>
+                <label>"Current Password: "
+                    <input type="password" autocomplete="current-password" maxlength=PASSWORD_MAX_LEN_STR name="current_password" required/>
+                </label>
+            </div>
+            <div>
+                <label>"New Password: "
+                    <input type="password" autocomplete="new-password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password" required/>
+                </label>
+            </div>
+            <div>
+                <label>"Confirm New Password: "
+                    <input type="password" autocomplete="new-password" maxlength=PASSWORD_MAX_LEN_STR minlength=PASSWORD_MIN_LEN_STR name="password_confirmation" required/>
+                </label>
+            </div>
+            <div>
+                <label>
+                    <input type="checkbox" name="revoke_others" value="true"/>
+                    "Log out all other sessions"
+                </label>
+            </div>
+            <button type="submit">"Change Password"</button>
+        </ActionForm>
+        <div>
+            {password_result}
+        </div>
+    }
+}
+
+#[server(ChangePassword, "/api")]
+pub async fn change_password_action(
+    csrf: String,
+    current_password: String,
+    password: String,
+    password_confirmation: String,
+    // unchecked checkboxes are not submitted at all
+    revoke_others: Option<String>,
+) -> Result<(), ServerFnError> {
+    Ok(change_password(
+        csrf,
+        SecretString::from(current_password),
+        SecretString::from(password),
+        SecretString::from(password_confirmation),
+        revoke_others.is_some(),
+    )
+    .await?)
+}
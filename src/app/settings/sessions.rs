@@ -0,0 +1,104 @@
+use crate::{
+    app::components::csrf::CSRFField, defs::CsrfPurpose, security::sessions::SessionSummary,
+};
+use cfg_if::cfg_if;
+use leptos::{either::Either, prelude::*};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::sessions::{revoke_other_sessions, revoke_session, session_summaries};
+}}
+
+/// Renders the settings section listing where the account is logged in.
+#[component]
+pub fn SessionSettings() -> impl IntoView {
+    let revoke = ServerAction::<RevokeSession>::new();
+    let revoke_others = ServerAction::<RevokeOtherSessions>::new();
+    let sessions = Resource::new(
+        move || (revoke.version().get(), revoke_others.version().get()),
+        move |_| get_sessions(),
+    );
+
+    let (session_result, set_session_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match revoke.value().get() {
+        Some(Ok(())) => set_session_result.set(String::from("Session logged out.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_session_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| match revoke_others.value().get() {
+        Some(Ok(())) => set_session_result.set(String::from("All other sessions logged out.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_session_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h2>"Sessions"</h2>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                sessions.get().map(|sessions| match sessions {
+                    Err(e) => Either::Left(view! {
+                        <span>{format!("Could not load sessions: {e}")}</span>
+                    }),
+                    Ok(sessions) => Either::Right(view! {
+                        <ul>
+                            {sessions.into_iter().map(|session| view! {
+                                <SessionEntry session action=revoke/>
+                            }).collect_view()}
+                        </ul>
+                    }),
+                })
+            }}
+        </Transition>
+        <ActionForm action=revoke_others>
+            <CSRFField purpose=CsrfPurpose::RevokeOtherSessions/>
+            <button type="submit">"Log Out Everywhere Else"</button>
+        </ActionForm>
+        <div>
+            {session_result}
+        </div>
+    }
+}
+
+#[component]
+fn SessionEntry(
+    session: SessionSummary,
+    action: ServerAction<RevokeSession>,
+) -> impl IntoView {
+    let created = session.created.unwrap_or(String::from("unknown"));
+    let last_seen = session.last_seen.unwrap_or(String::from("unknown"));
+    let ip = match session.ip.is_empty() {
+        true => String::from("unknown address"),
+        false => session.ip,
+    };
+    view! {
+        <li>
+            {session.device}" from "{ip}" (logged in "{created}", last seen "{last_seen}")"
+            { match session.current {
+                true => Either::Left(view! { <strong>" This session"</strong> }),
+                false => Either::Right(view! {
+                    <ActionForm action=action>
+                        <CSRFField purpose=CsrfPurpose::RevokeSession/>
+                        <input type="hidden" name="handle" value=session.handle/>
+                        <button type="submit">"Log Out"</button>
+                    </ActionForm>
+                }),
+            }}
+        </li>
+    }
+}
+
+#[server(GetSessions, "/api")]
+pub async fn get_sessions() -> Result<Vec<SessionSummary>, ServerFnError> {
+    Ok(session_summaries().await?)
+}
+
+#[server(RevokeSession, "/api")]
+pub async fn revoke_session_action(csrf: String, handle: String) -> Result<(), ServerFnError> {
+    Ok(revoke_session(csrf, handle).await?)
+}
+
+#[server(RevokeOtherSessions, "/api")]
+pub async fn revoke_other_sessions_action(csrf: String) -> Result<(), ServerFnError> {
+    Ok(revoke_other_sessions(csrf).await?)
+}
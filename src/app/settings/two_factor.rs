@@ -0,0 +1,204 @@
+use crate::{
+    app::components::csrf::CSRFField,
+    defs::{
+        CsrfPurpose, RECOVERY_CODE_COUNT, SECOND_FACTOR_CODE_MAX_LEN_STR, TOTP_CODE_LEN_STR,
+    },
+};
+use cfg_if::cfg_if;
+use leptos::{
+    either::{Either, EitherOf3},
+    prelude::*,
+};
+
+use crate::security::two_factor::{TotpEnrollment, TwoFactorStatus};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::require_session;
+    use crate::database::two_factor::totp_enabled;
+    use crate::security::recovery_codes::recovery_codes_remaining;
+    use crate::security::two_factor::{
+        begin_totp_enrollment, confirm_totp_enrollment, disable_totp, regenerate_recovery_codes,
+    };
+}}
+
+/// Renders the settings section for enabling and disabling TOTP two-factor authentication.
+#[component]
+pub fn TwoFactorSettings() -> impl IntoView {
+    let enroll = ServerAction::<EnrollTotp>::new();
+    let confirm = ServerAction::<ConfirmTotp>::new();
+    let remove = ServerAction::<RemoveTotp>::new();
+    let regenerate = ServerAction::<RegenerateRecoveryCodes>::new();
+    let status = Resource::new(
+        move || {
+            (
+                confirm.version().get(),
+                remove.version().get(),
+                regenerate.version().get(),
+            )
+        },
+        move |_| get_two_factor_status(),
+    );
+
+    let (two_factor_result, set_two_factor_result) = signal(String::from(" "));
+    // new recovery codes, shown until the page is left
+    let (recovery_codes, set_recovery_codes) = signal(None::<Vec<String>>);
+
+    Effect::new(move |_| match enroll.value().get() {
+        Some(Ok(_)) => set_two_factor_result.set(String::from(
+            "Scan the QR code with your authenticator app, then enter the code it shows.",
+        )),
+        Some(Err(ServerFnError::ServerError(e))) => set_two_factor_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| match confirm.value().get() {
+        Some(Ok(codes)) => {
+            set_two_factor_result.set(String::from("Two-factor authentication enabled."));
+            set_recovery_codes.set(Some(codes));
+        }
+        Some(Err(ServerFnError::ServerError(e))) => set_two_factor_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| match remove.value().get() {
+        Some(Ok(())) => {
+            set_two_factor_result.set(String::from("Two-factor authentication disabled."));
+            set_recovery_codes.set(None);
+        }
+        Some(Err(ServerFnError::ServerError(e))) => set_two_factor_result.set(e),
+        _ => {}
+    });
+
+    Effect::new(move |_| match regenerate.value().get() {
+        Some(Ok(codes)) => {
+            set_two_factor_result.set(String::from(
+                "New recovery codes created, the old ones no longer work.",
+            ));
+            set_recovery_codes.set(Some(codes));
+        }
+        Some(Err(ServerFnError::ServerError(e))) => set_two_factor_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h2>"Two-Factor Authentication"</h2>
+        <Transition fallback=move || view! { <p>"Loading..."</p> }>
+            { move || {
+                status.get().map(|enabled| match enabled {
+                    Err(e) => EitherOf3::A(view! {
+                        <span>{format!("Could not load two-factor status: {e}")}</span>
+                    }),
+                    Ok(TwoFactorStatus { enabled: true, recovery_codes_remaining }) => EitherOf3::B(view! {
+                        <p>"Two-factor authentication is enabled."</p>
+                        <p>{format!("Recovery codes left: {recovery_codes_remaining} of {RECOVERY_CODE_COUNT}")}</p>
+                        <ActionForm action=regenerate>
+                            <CSRFField purpose=CsrfPurpose::RegenerateRecoveryCodes/>
+                            <div>
+                                <label>"Authenticator or Recovery Code: "
+                                    <input type="text" autocomplete="one-time-code" maxlength=SECOND_FACTOR_CODE_MAX_LEN_STR minlength=TOTP_CODE_LEN_STR name="code" required/>
+                                </label>
+                            </div>
+                            <button type="submit">"Create New Recovery Codes"</button>
+                        </ActionForm>
+                        <ActionForm action=remove>
+                            <CSRFField purpose=CsrfPurpose::DisableTotp/>
+                            <div>
+                                <label>"Authenticator or Recovery Code: "
+                                    <input type="text" autocomplete="one-time-code" maxlength=SECOND_FACTOR_CODE_MAX_LEN_STR minlength=TOTP_CODE_LEN_STR name="code" required/>
+                                </label>
+                            </div>
+                            <button type="submit">"Disable Two-Factor Authentication"</button>
+                        </ActionForm>
+                    }),
+                    Ok(TwoFactorStatus { enabled: false, .. }) => EitherOf3::C(view! {
+                        <p>"Two-factor authentication is not enabled."</p>
+                        <ActionForm action=enroll>
+                            <CSRFField purpose=CsrfPurpose::EnrollTotp/>
+                            <button type="submit">"Set Up Authenticator App"</button>
+                        </ActionForm>
+                        { move || match enroll.value().get() {
+                            Some(Ok(enrollment)) => Either::Left(view! {
+                                <TotpConfirmation enrollment action=confirm/>
+                            }),
+                            _ => Either::Right(()),
+                        }}
+                    }),
+                })
+            }}
+        </Transition>
+        <div>
+            {two_factor_result}
+        </div>
+        { move || recovery_codes.get().map(|codes| view! { <RecoveryCodeList codes/> }) }
+    }
+}
+
+/// Shows new recovery codes. They are stored hashed, so this is the only time they are shown.
+#[component]
+fn RecoveryCodeList(codes: Vec<String>) -> impl IntoView {
+    view! {
+        <p>
+            "Save these recovery codes somewhere safe. Each one can be used once in place of \
+             an authenticator code, for example if you lose your phone. They will not be \
+             shown again."
+        </p>
+        <ul>
+            {codes.into_iter().map(|code| view! { <li><code>{code}</code></li> }).collect_view()}
+        </ul>
+    }
+}
+
+#[component]
+fn TotpConfirmation(
+    enrollment: TotpEnrollment,
+    action: ServerAction<ConfirmTotp>,
+) -> impl IntoView {
+    view! {
+        <div>
+            <img src=enrollment.qr_code alt="QR code for your authenticator app"/>
+        </div>
+        <p>"Or enter this secret manually: " <code>{enrollment.secret}</code></p>
+        <p><a href=enrollment.otpauth_uri>"Open in authenticator app"</a></p>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::ConfirmTotp/>
+            <div>
+                <label>"Authenticator Code: "
+                    <input type="text" inputmode="numeric" autocomplete="one-time-code" maxlength=TOTP_CODE_LEN_STR minlength=TOTP_CODE_LEN_STR name="code" required/>
+                </label>
+            </div>
+            <button type="submit">"Confirm"</button>
+        </ActionForm>
+    }
+}
+
+#[server(GetTwoFactorStatus, "/api")]
+pub async fn get_two_factor_status() -> Result<TwoFactorStatus, ServerFnError> {
+    let user_id = require_session().await?;
+    Ok(TwoFactorStatus {
+        enabled: totp_enabled(user_id).await?,
+        recovery_codes_remaining: recovery_codes_remaining(user_id).await?,
+    })
+}
+
+#[server(EnrollTotp, "/api")]
+pub async fn enroll_totp(csrf: String) -> Result<TotpEnrollment, ServerFnError> {
+    Ok(begin_totp_enrollment(csrf).await?)
+}
+
+#[server(ConfirmTotp, "/api")]
+pub async fn confirm_totp(csrf: String, code: String) -> Result<Vec<String>, ServerFnError> {
+    Ok(confirm_totp_enrollment(csrf, code).await?)
+}
+
+#[server(RemoveTotp, "/api")]
+pub async fn remove_totp(csrf: String, code: String) -> Result<(), ServerFnError> {
+    Ok(disable_totp(csrf, code).await?)
+}
+
+#[server(RegenerateRecoveryCodes, "/api")]
+pub async fn regenerate_recovery_codes_action(
+    csrf: String,
+    code: String,
+) -> Result<Vec<String>, ServerFnError> {
+    Ok(regenerate_recovery_codes(csrf, code).await?)
+}
@@ -0,0 +1,58 @@
+use crate::{app::components::csrf::CSRFField, database::APIUserData, defs::*};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::choose_username;
+}}
+
+/// Renders the form for choosing a new username, only shown to accounts whose username looks
+/// like another account's.
+#[component]
+pub fn UsernameSettings(
+    action: ServerAction<ChooseUsername>,
+    user_data: Resource<Result<Option<APIUserData>, ServerFnError>>,
+) -> impl IntoView {
+    let (username_result, set_username_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(())) => set_username_result.set(String::from("Username changed.")),
+        Some(Err(ServerFnError::ServerError(e))) => set_username_result.set(e),
+        _ => {}
+    });
+
+    let rename_required =
+        move || matches!(user_data.get(), Some(Ok(Some(user))) if user.rename_required);
+
+    view! {
+        <Transition>
+            <Show when=rename_required>
+                <h2>"Choose a New Username"</h2>
+                <p>
+                    "Your username looks too much like another account's. "
+                    "It keeps working until you choose a new one."
+                </p>
+                <ActionForm action=action>
+                    <CSRFField purpose=CsrfPurpose::ChooseUsername/>
+                    <div>
+                        <label>"New Username: "
+                            <input type="text" maxlength=USERNAME_MAX_LEN_STR minlength=USERNAME_MIN_LEN_STR name="username" required/>
+                        </label>
+                    </div>
+                    <button type="submit">"Choose Username"</button>
+                </ActionForm>
+            </Show>
+        </Transition>
+        <div>
+            {username_result}
+        </div>
+    }
+}
+
+#[server(ChooseUsername, "/api")]
+pub async fn choose_username_action(
+    csrf: String,
+    username: String,
+) -> Result<(), ServerFnError> {
+    Ok(choose_username(csrf, username).await?)
+}
@@ -0,0 +1,46 @@
+use crate::{app::components::csrf::CSRFField, defs::CsrfPurpose};
+use cfg_if::cfg_if;
+use leptos::prelude::*;
+use leptos_router::hooks::use_params_map;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::security::verification::verify_email;
+}}
+
+/// Renders the page a verification email links to.
+/// The token is only redeemed when the button is pressed, so mail scanners that open links
+/// cannot verify an address on their own.
+#[component]
+pub fn VerifyEmail() -> impl IntoView {
+    let action = ServerAction::<ConfirmEmail>::new();
+    let params = use_params_map();
+    let token = move || params.read().get("token").unwrap_or_default();
+
+    let (verify_result, set_verify_result) = signal(String::from(" "));
+
+    Effect::new(move |_| match action.value().get() {
+        Some(Ok(())) => {
+            set_verify_result.set(String::from("Your email address is verified, thank you."))
+        }
+        Some(Err(ServerFnError::ServerError(e))) => set_verify_result.set(e),
+        _ => {}
+    });
+
+    view! {
+        <h1>"Verify Email Address"</h1>
+        <ActionForm action=action>
+            <CSRFField purpose=CsrfPurpose::VerifyEmail/>
+            <input type="hidden" name="token" value=token/>
+            <button type="submit">"Verify my email address"</button>
+        </ActionForm>
+        <div>
+            {verify_result}
+        </div>
+        <p><a href="/">"Return to landing page"</a></p>
+    }
+}
+
+#[server(ConfirmEmail, "/api")]
+pub async fn confirm_email(csrf: String, token: String) -> Result<(), ServerFnError> {
+    Ok(verify_email(csrf, token).await?)
+}
@@ -1,12 +1,31 @@
 use cfg_if::cfg_if;
 
 cfg_if! { if #[cfg(feature = "ssr")] {
-    use crate::database::{associate_session, drop_session, validate_token};
-    use crate::defs::{DatabaseError, RouterError, AppError};
+    use crate::database::{
+        associate_session, drop_other_sessions, drop_session, drop_user_sessions,
+        record_session_seen, rotate_session_id,
+        session_cookie::{bump_session_generation, revoke_session_cookie, session_generation},
+        two_factor::create_login_challenge, validate_token, ValidSession,
+    };
+    use crate::defs::{
+        ApiTokenScope, AppError, DatabaseError, LoginError, RouterError, ServerVars,
+        SessionPolicy, SessionStore, ANTIBOT_COOKIE_MAX_AGE_SECS, LOGIN_CHALLENGE_DURATION_SECS,
+        OIDC_SIGNUP_DURATION_SECS, SESSION_USER_AGENT_MAX_LEN,
+    };
+    use crate::security::{
+        api_tokens::{authenticate_api_token, bearer_token},
+        gen_128bit_base64,
+        session_cookie::{validate_session_cookie_with_pool, SessionClaims, SessionCookieKeys},
+    };
+    use sqlx::SqlitePool;
+    use crate::session_cache::SessionCache;
+    use crate::websocket::SessionSockets;
     use axum::{
-        http::header::{COOKIE, SET_COOKIE},
+        extract::ConnectInfo,
+        http::header::{COOKIE, SET_COOKIE, USER_AGENT},
         http::HeaderValue,
     };
+    use std::net::SocketAddr;
     use chrono::prelude::*;
     use leptos::prelude::*;
     use http::request::Parts;
@@ -34,11 +53,135 @@ pub async fn destroy_session() {
     };
     // grab request's session
     let unverified_session_id = parse_session_req_parts_cookie(http_req);
-    let _ = drop_session(&unverified_session_id).await;
+    match session_store() {
+        SessionStore::Database => {
+            let _ = drop_session(&unverified_session_id).await;
+            close_session_sockets(&[unverified_session_id]);
+        }
+        SessionStore::Cookie => {
+            if let Some(claims) = open_session_cookie(&unverified_session_id) {
+                let _ =
+                    end_session(claims.user_id, &claims.session_id, claims.absolute_expiry())
+                        .await;
+            }
+        }
+    }
+}
+
+/// Logs out the session `session_id` of `user_id` and closes its websockets. An encrypted
+/// session keeps decrypting until it expires, so its id is remembered as revoked until
+/// `expiry`.
+#[cfg(feature = "ssr")]
+pub async fn end_session(
+    user_id: Uuid,
+    session_id: &String,
+    expiry: DateTime<Utc>,
+) -> Result<(), AppError> {
+    if session_store() == SessionStore::Cookie {
+        revoke_session_cookie(user_id, session_id, expiry).await?;
+    }
+    match drop_session(session_id).await {
+        // encrypted sessions issued before their details were stored have no row
+        Ok(()) | Err(DatabaseError::IncorrectRowsAffected) => {}
+        Err(e) => return Err(e.into()),
+    }
+    close_session_sockets(std::slice::from_ref(session_id));
+    Ok(())
+}
+
+/// Logs `user_id` out everywhere and closes their websockets.
+#[cfg(feature = "ssr")]
+pub async fn end_user_sessions(user_id: Uuid) -> Result<(), AppError> {
+    close_session_sockets(&drop_user_sessions(user_id).await?);
+    bump_session_generation(user_id).await?;
+    if let Some(sockets) = use_context::<SessionSockets>() {
+        sockets.revoke_user(user_id, None);
+    }
+    Ok(())
+}
+
+/// Logs `user_id` out everywhere except the session making this request. An encrypted
+/// session is kept by sending it a new cookie for the new session generation.
+#[cfg(feature = "ssr")]
+pub async fn end_other_sessions(user_id: Uuid) -> Result<(), AppError> {
+    let current_session_id = request_session_id();
+    close_session_sockets(&drop_other_sessions(user_id, &current_session_id).await?);
+    let generation = bump_session_generation(user_id).await?;
+    if session_store() != SessionStore::Cookie {
+        return Ok(());
+    }
+    if let Some(sockets) = use_context::<SessionSockets>() {
+        sockets.revoke_user(user_id, Some(&current_session_id));
+    }
+    let current = use_context::<Parts>()
+        .map(parse_session_req_parts_cookie)
+        .and_then(|sealed| open_session_cookie(&sealed));
+    if let Some(claims) = current {
+        let response = match use_context::<leptos_axum::ResponseOptions>() {
+            Some(ro) => Ok(ro),
+            None => {
+                log::error!("end_other_sessions: no response options available");
+                Err(RouterError::HTTPRequestMissing)
+            }
+        }?;
+        let claims = SessionClaims {
+            generation,
+            ..claims
+        };
+        append_session_cookie(
+            &response,
+            &seal_session_cookie(&claims)?,
+            claims.absolute_expiry(),
+            claims.remember,
+        );
+    }
+    Ok(())
+}
+
+/// The session store of the running server, the database when there is no request.
+#[cfg(feature = "ssr")]
+fn session_store() -> SessionStore {
+    use_context::<ServerVars>().map_or(SessionStore::Database, |vars| vars.session_store)
 }
 
+/// Decrypts an encrypted session cookie without checking whether it is still valid.
 #[cfg(feature = "ssr")]
-pub async fn issue_session_cookie(user_id: Uuid, session_id: String) -> Result<(), AppError> {
+fn open_session_cookie(sealed: &str) -> Option<SessionClaims> {
+    use_context::<SessionCookieKeys>()?.open(sealed)
+}
+
+#[cfg(feature = "ssr")]
+fn seal_session_cookie(claims: &SessionClaims) -> Result<String, AppError> {
+    match use_context::<SessionCookieKeys>() {
+        Some(keys) => keys.seal(claims),
+        None => {
+            log::error!("session cookie keys not available in context");
+            Err(AppError::SessionCookieFailure)
+        }
+    }
+}
+
+/// Closes the websockets opened with any of `session_ids` after they were dropped.
+#[cfg(feature = "ssr")]
+pub fn close_session_sockets(session_ids: &[String]) {
+    match use_context::<SessionSockets>() {
+        Some(sockets) => {
+            for session_id in session_ids {
+                sockets.revoke(session_id);
+            }
+        }
+        None => log::error!("session sockets not available in context"),
+    }
+}
+
+/// Starts a session for `user_id`. Sessions that are not remembered end with the browser
+/// session or after the policy's `short_max`, whichever comes first.
+#[cfg(feature = "ssr")]
+pub async fn issue_session_cookie(
+    user_id: Uuid,
+    session_id: String,
+    remember: bool,
+) -> Result<(), AppError> {
     let response = match use_context::<leptos_axum::ResponseOptions>() {
         Some(ro) => Ok(ro),
         None => {
@@ -46,18 +189,103 @@ pub async fn issue_session_cookie(user_id: Uuid, session_id: String) -> Result<(
             Err(RouterError::HTTPRequestMissing)
         }
     }?;
-    let expire_time: DateTime<Utc> = Utc::now() + chrono::Duration::days(30);
-    let date_string: String = expire_time.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
-    associate_session(user_id, &session_id, expire_time).await?;
+    let policy = match use_context::<ServerVars>() {
+        Some(vars) => Ok(vars.session_policy),
+        None => {
+            log::error!("issue_session_cookie: server vars not available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    let now = Utc::now();
+    let absolute_expiry = policy.absolute_expiry(now, remember);
+    let expire_time = policy.idle_expiry(now, absolute_expiry);
+    let (ip, user_agent) = request_client();
+    if session_store() == SessionStore::Cookie {
+        // the row only lists the session on the settings page, it is never checked to log in
+        associate_session(
+            user_id,
+            &session_id,
+            absolute_expiry,
+            absolute_expiry,
+            remember,
+            &ip,
+            &user_agent,
+        )
+        .await?;
+        let claims = SessionClaims {
+            session_id,
+            user_id,
+            generation: session_generation(user_id).await?,
+            remember,
+            absolute_expiry: absolute_expiry.timestamp(),
+            idle_expiry: expire_time.timestamp(),
+            refreshed: now.timestamp(),
+        };
+        append_session_cookie(
+            &response,
+            &seal_session_cookie(&claims)?,
+            absolute_expiry,
+            remember,
+        );
+        return Ok(());
+    }
+    associate_session(
+        user_id,
+        &session_id,
+        expire_time,
+        absolute_expiry,
+        remember,
+        &ip,
+        &user_agent,
+    )
+    .await?;
+    append_session_cookie(&response, &session_id, absolute_expiry, remember);
+    Ok(())
+}
+
+/// The address and user agent of the client making the current request, empty when unknown.
+#[cfg(feature = "ssr")]
+pub fn request_client() -> (String, String) {
+    let ip = match use_context::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => addr.ip().to_string(),
+        None => String::default(),
+    };
+    let user_agent: String = match use_context::<Parts>() {
+        Some(rp) => rp
+            .headers
+            .get(USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .unwrap_or_default()
+            .chars()
+            .take(SESSION_USER_AGENT_MAX_LEN)
+            .collect(),
+        None => String::default(),
+    };
+    (ip, user_agent)
+}
+
+#[cfg(feature = "ssr")]
+fn append_session_cookie(
+    response: &leptos_axum::ResponseOptions,
+    session_id: &str,
+    absolute_expiry: DateTime<Utc>,
+    remember: bool,
+) {
+    // without Expires the browser forgets the cookie when it is closed
+    let expires = match remember {
+        true => format!(
+            "Expires={}; ",
+            absolute_expiry.format("%a, %d %b %Y %H:%M:%S GMT")
+        ),
+        false => String::default(),
+    };
     response.append_header(
         SET_COOKIE,
         HeaderValue::from_str(&format!(
-            "SESSIONID={session_id}; Expires={date_string}; Secure; SameSite=Lax; HttpOnly; \
-             Path=/"
+            "SESSIONID={session_id}; {expires}Secure; SameSite=Lax; HttpOnly; Path=/"
         ))
         .expect("to create header value"),
     );
-    Ok(())
 }
 
 #[cfg(feature = "ssr")]
@@ -67,10 +295,275 @@ pub async fn validate_session() -> Result<Option<Uuid>, DatabaseError> {
         Some(rp) => rp,          // actual user request
         None => return Ok(None), // no request, building routes in main.rs
     };
+    let policy = match use_context::<ServerVars>() {
+        Some(vars) => vars.session_policy,
+        None => {
+            log::error!("validate_session: server vars not available");
+            return Ok(None);
+        }
+    };
+    // scripts authenticate every request with a personal access token instead of a session
+    if let Some(token) = bearer_token(&http_req.headers) {
+        return Ok(authenticate_api_token(&token, ApiTokenScope::Read)
+            .await?
+            .map(|grant| grant.user_id));
+    }
+    // only server function responses are guaranteed to still be able to set a cookie,
+    // page responses may already be streaming
+    let can_rotate = http_req.uri.path().starts_with("/api/");
     // grab request's session
     let unverified_session_id = parse_session_req_parts_cookie(http_req);
-    validate_token(unverified_session_id).await
-    // do not renew cookies every time, force logins every 30 days
+    if session_store() == SessionStore::Cookie {
+        return validate_sealed_session(&unverified_session_id, policy, can_rotate).await;
+    }
+    let session = match validate_token(unverified_session_id, policy).await? {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+    if session.rotation_due && can_rotate {
+        rotate_session(&session).await;
+    }
+    Ok(Some(session.user_id))
+}
+
+/// Validates an encrypted session cookie, sending a new one with a later idle expiry and
+/// noting when it was last seen every `SESSION_LAST_SEEN_INTERVAL_SECS` when `can_refresh`.
+#[cfg(feature = "ssr")]
+async fn validate_sealed_session(
+    sealed: &str,
+    policy: SessionPolicy,
+    can_refresh: bool,
+) -> Result<Option<Uuid>, DatabaseError> {
+    let keys = match use_context::<SessionCookieKeys>() {
+        Some(keys) => keys,
+        None => {
+            log::error!("validate_sealed_session: session cookie keys not available");
+            return Ok(None);
+        }
+    };
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in validate_sealed_session");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let cache = use_context::<SessionCache>().unwrap_or_else(SessionCache::disabled);
+    let claims = match validate_session_cookie_with_pool(sealed, &keys, &pool, &cache).await? {
+        Some(claims) => claims,
+        None => return Ok(None),
+    };
+    let now = Utc::now();
+    if can_refresh && claims.refresh_due(now) {
+        if let Some(response) = use_context::<leptos_axum::ResponseOptions>() {
+            // a failed refresh is logged by seal, the cookie stays valid until its idle expiry
+            if let Ok(refreshed) = keys.seal(&claims.refreshed(now, policy)) {
+                append_session_cookie(
+                    &response,
+                    &refreshed,
+                    claims.absolute_expiry(),
+                    claims.remember,
+                );
+                let _ = record_session_seen(&claims.session_id).await;
+            }
+        }
+    }
+    Ok(Some(claims.user_id))
+}
+
+/// Gives an active session a new id and sends it to the browser. A failed rotation is only
+/// logged, the session stays valid under its old id.
+#[cfg(feature = "ssr")]
+async fn rotate_session(session: &ValidSession) {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(ro) => ro,
+        None => return,
+    };
+    let policy = match use_context::<ServerVars>() {
+        Some(vars) => vars.session_policy,
+        None => return,
+    };
+    let new_session_id = gen_128bit_base64();
+    let expire_time = policy.idle_expiry(Utc::now(), session.absolute_expiry);
+    if let Err(e) = rotate_session_id(&session.session_id, &new_session_id, expire_time).await
+    {
+        // another request of the same session may have rotated it first
+        log::debug!("could not rotate session of {}: {e}", session.user_id);
+        return;
+    }
+    if let Some(sockets) = use_context::<SessionSockets>() {
+        sockets.rename(&session.session_id, &new_session_id);
+    }
+    append_session_cookie(
+        &response,
+        &new_session_id,
+        session.absolute_expiry,
+        session.remember,
+    );
+    log::trace!("session of {} rotated", session.user_id);
+}
+
+/// Like `validate_session`, but for server functions that only make sense while logged in.
+#[cfg(feature = "ssr")]
+pub async fn require_session() -> Result<Uuid, AppError> {
+    match validate_session().await? {
+        Some(id) => Ok(id),
+        None => Err(LoginError::NotLoggedIn.into()),
+    }
+}
+
+/// Marks a browser as having passed the password step of a login for `user_id`.
+/// The login is finished by a second factor within `LOGIN_CHALLENGE_DURATION_SECS`.
+#[cfg(feature = "ssr")]
+pub async fn issue_login_challenge_cookie(
+    user_id: Uuid,
+    challenge_id: String,
+    remember: bool,
+) -> Result<(), AppError> {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(ro) => Ok(ro),
+        None => {
+            log::error!("issue_login_challenge_cookie: no response options available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    let expire_time: DateTime<Utc> =
+        Utc::now() + chrono::Duration::seconds(LOGIN_CHALLENGE_DURATION_SECS);
+    create_login_challenge(&challenge_id, user_id, expire_time, remember).await?;
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&format!(
+            "__Host-login={challenge_id}; Max-Age={LOGIN_CHALLENGE_DURATION_SECS}; Secure; \
+             SameSite=Lax; HttpOnly; Path=/"
+        ))
+        .expect("to create header value"),
+    );
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub fn destroy_login_challenge_cookie() {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(rp) => rp,
+        None => return,
+    };
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(
+            "__Host-login=deleted; Expires=Thu, 01-Jan-1970 00:00:01 GMT; Max-Age=0; Secure; \
+             SameSite=Lax; HttpOnly; Path=/",
+        )
+        .expect("to create header value"),
+    );
+}
+
+/// Remembers a first time OpenID Connect user in the browser until they chose a display
+/// name, within `OIDC_SIGNUP_DURATION_SECS`.
+#[cfg(feature = "ssr")]
+pub fn issue_oidc_signup_cookie(token: &String) -> Result<(), AppError> {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(ro) => Ok(ro),
+        None => {
+            log::error!("issue_oidc_signup_cookie: no response options available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&format!(
+            "__Host-oidc-signup={token}; Max-Age={OIDC_SIGNUP_DURATION_SECS}; Secure; \
+             SameSite=Lax; HttpOnly; Path=/"
+        ))
+        .expect("to create header value"),
+    );
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub fn destroy_oidc_signup_cookie() {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(rp) => rp,
+        None => return,
+    };
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(
+            "__Host-oidc-signup=deleted; Expires=Thu, 01-Jan-1970 00:00:01 GMT; Max-Age=0; \
+             Secure; SameSite=Lax; HttpOnly; Path=/",
+        )
+        .expect("to create header value"),
+    );
+}
+
+/// The anti-bot id the current request was sent with, if any. It only tells browsers apart
+/// for rate limiting and proves nothing about the client.
+#[cfg(feature = "ssr")]
+pub fn request_antibot_id() -> Option<String> {
+    let http_req = use_context::<Parts>()?;
+    let antibot_id = parse_req_parts_cookie(http_req, "__Host-antibot");
+    match antibot_id.is_empty() {
+        true => None,
+        false => Some(antibot_id),
+    }
+}
+
+/// Gives the browser an anti-bot id unless it already has one, or one was issued while
+/// building this response.
+#[cfg(feature = "ssr")]
+pub fn ensure_antibot_cookie() {
+    let response = match use_context::<leptos_axum::ResponseOptions>() {
+        Some(rp) => rp,
+        None => return,
+    };
+    if request_antibot_id().is_some() {
+        return;
+    }
+    let already_issued =
+        response
+            .0
+            .read()
+            .headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .any(|set_cookie| {
+                set_cookie
+                    .to_str()
+                    .is_ok_and(|cookie| cookie.starts_with("__Host-antibot="))
+            });
+    if already_issued {
+        return;
+    }
+    response.append_header(
+        SET_COOKIE,
+        HeaderValue::from_str(&format!(
+            "__Host-antibot={}; Max-Age={ANTIBOT_COOKIE_MAX_AGE_SECS}; Secure; SameSite=Lax; \
+             HttpOnly; Path=/",
+            gen_128bit_base64()
+        ))
+        .expect("to create header value"),
+    );
+}
+
+/// The session id the current request was sent with, empty if there was none.
+#[cfg(feature = "ssr")]
+pub fn request_session_id() -> String {
+    match use_context::<Parts>() {
+        Some(rp) => parse_session_id_req_parts(rp),
+        None => String::default(),
+    }
+}
+
+/// The session id a request was sent with, empty if there was none. For an encrypted
+/// session this is the id sealed in the cookie, which stays the same when it is refreshed.
+#[cfg(feature = "ssr")]
+pub fn parse_session_id_req_parts(req: Parts) -> String {
+    let cookie = parse_session_req_parts_cookie(req);
+    match session_store() {
+        SessionStore::Database => cookie,
+        SessionStore::Cookie => open_session_cookie(&cookie)
+            .map(|claims| claims.session_id)
+            .unwrap_or_default(),
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -83,11 +576,26 @@ pub fn parse_session_header_cookie(cookies: &str) -> String {
 
 #[cfg(feature = "ssr")]
 pub fn parse_session_req_parts_cookie(req: Parts) -> String {
+    parse_req_parts_cookie(req, "SESSIONID")
+}
+
+#[cfg(feature = "ssr")]
+pub fn parse_login_challenge_req_parts_cookie(req: Parts) -> String {
+    parse_req_parts_cookie(req, "__Host-login")
+}
+
+#[cfg(feature = "ssr")]
+pub fn parse_oidc_signup_req_parts_cookie(req: Parts) -> String {
+    parse_req_parts_cookie(req, "__Host-oidc-signup")
+}
+
+#[cfg(feature = "ssr")]
+fn parse_req_parts_cookie(req: Parts, key: &str) -> String {
     for headercookie in req.headers.get_all(COOKIE).iter() {
         match headercookie.to_str() {
             Ok(cookie) => {
-                if let Some(session) = get_cookie_value(cookie, "SESSIONID") {
-                    return session;
+                if let Some(value) = get_cookie_value(cookie, key) {
+                    return value;
                 }
             }
             Err(_) => continue,
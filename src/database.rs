@@ -1,7 +1,13 @@
 use cfg_if::cfg_if;
 
 cfg_if! { if #[cfg(feature = "ssr")] {
-    use crate::defs::{AppError, RegistrationError, DatabaseError};
+    use crate::defs::{
+        AppError, RegistrationError, DatabaseError, EmailPolicy, SessionPolicy,
+        SESSION_LAST_SEEN_INTERVAL_SECS, SESSION_ROTATION_GRACE_SECS,
+    };
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::session_cache::SessionCache;
+    use crate::security::canonical::{canonical_email, canonical_name};
     use chrono::prelude::*;
     use leptos::prelude::*;
     use secrecy::SecretString;
@@ -10,10 +16,26 @@ cfg_if! { if #[cfg(feature = "ssr")] {
 }}
 use serde::{Deserialize, Serialize};
 
+pub mod api_tokens;
+pub mod audit;
+pub mod magic_link;
+pub mod oidc;
+pub mod passkeys;
+pub mod password_reset;
+pub mod proof_of_work;
+pub mod reaper;
+pub mod recovery_codes;
+pub mod session_cookie;
+pub mod throttle;
+pub mod two_factor;
+pub mod verification;
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct APIUserData {
     pub display_name: String,
     pub button_presses: i64,
+    /// the username looks like another account's, see `backfill_canonical_names_with_pool`
+    pub rename_required: bool,
 }
 
 #[cfg(feature = "ssr")]
@@ -21,6 +43,7 @@ pub struct APIUserData {
 struct UserDataForPage {
     display_name: String,
     button_presses: i64,
+    rename_required: bool,
 }
 
 #[cfg(feature = "ssr")]
@@ -32,23 +55,29 @@ pub async fn user_data(id: Uuid) -> Result<APIUserData, DatabaseError> {
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
-    user_data_with_pool(id, pool).await
+    let cache = use_context::<SessionCache>().unwrap_or_else(SessionCache::disabled);
+    user_data_with_pool(id, pool, &cache).await
 }
 
 #[cfg(feature = "ssr")]
 pub async fn user_data_with_pool(
     id: Uuid,
     pool: SqlitePool,
+    cache: &SessionCache,
 ) -> Result<APIUserData, DatabaseError> {
+    if let Some(data) = cache.user(id) {
+        return Ok(data);
+    }
+    let epoch = cache.epoch();
     let row = sqlx::query_as!(
         UserDataForPage,
-        r#"SELECT display_name, button_presses FROM users WHERE user_id = ?"#,
+        r#"SELECT display_name, button_presses, rename_required FROM users WHERE user_id = ?"#,
         id
     )
     .fetch_one(&pool)
     .await;
-    let (display_name, button_presses): (String, i64) = match row {
-        Ok(res) => Ok((res.display_name, res.button_presses)),
+    let row = match row {
+        Ok(res) => Ok(res),
         Err(e) => {
             match e {
                 sqlx::Error::RowNotFound => {
@@ -62,10 +91,40 @@ pub async fn user_data_with_pool(
             }
         }
     }?;
-    Ok(APIUserData {
-        display_name,
-        button_presses,
-    })
+    let data = APIUserData {
+        display_name: row.display_name,
+        button_presses: row.button_presses,
+        rename_required: row.rename_required,
+    };
+    cache.store_user(id, data.clone(), epoch);
+    Ok(data)
+}
+
+#[cfg(feature = "ssr")]
+pub async fn username_for_id(id: Uuid) -> Result<String, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("username_for_id unable to aquire sqlite pool");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query!("SELECT username FROM users WHERE user_id = ?", id)
+        .fetch_one(&pool)
+        .await;
+    match row {
+        Ok(res) => Ok(res.username),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                log::error!("database lookup for username on id {id} did not exist");
+                Err(DatabaseError::NoEntries)
+            }
+            _ => {
+                log::error!("database lookup for username on id {id} failed: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -74,6 +133,7 @@ pub async fn register_user(
     username: String,
     display_name: String,
     email: String,
+    email_normalized: String,
     password_hash: String,
 ) -> Result<Uuid, AppError> {
     let pool = match use_context::<SqlitePool>() {
@@ -84,9 +144,12 @@ pub async fn register_user(
         }
     }?;
     let id = Uuid::now_v7();
+    let username_canonical = canonical_name(&username);
+    let display_name_canonical = canonical_name(&display_name);
     let query_res = sqlx::query!(
-        "INSERT INTO users (user_id, username, display_name, email, verified, password_hash, button_presses) \
-         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        "INSERT INTO users (user_id, username, display_name, email, verified, password_hash, button_presses, \
+         username_canonical, display_name_canonical, email_normalized) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         id,
         username,
         display_name,
@@ -94,10 +157,13 @@ pub async fn register_user(
         false,
         password_hash,
         0,
+        username_canonical,
+        display_name_canonical,
+        email_normalized,
     )
     .execute(&pool)
     .await;
-    let _result = match query_res {
+    match query_res {
         Ok(val) => {
             if val.rows_affected() != 1 {
                 log::error!(
@@ -117,11 +183,175 @@ pub async fn register_user(
     Ok(id)
 }
 
+/// Fills in the canonical names of accounts created before they were stored. An account
+/// whose username looks like one that is already taken keeps no canonical name and is
+/// flagged with `rename_required`: it logs in with its exact name only, and is asked to
+/// choose a new one. A display name that looks like another one is only logged.
+#[cfg(feature = "ssr")]
+pub async fn backfill_canonical_names_with_pool(
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    let rows = sqlx::query!(
+        r#"SELECT user_id AS "user_id: Uuid", username, display_name, username_canonical,
+        display_name_canonical FROM users
+        WHERE username_canonical IS NULL OR display_name_canonical IS NULL"#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        log::error!("backfill_canonical_names_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })?;
+    for row in rows {
+        if row.username_canonical.is_none() {
+            let canonical = canonical_name(&row.username);
+            if let Err(e) = sqlx::query!(
+                "UPDATE users SET username_canonical = ? WHERE user_id = ?",
+                canonical,
+                row.user_id
+            )
+            .execute(pool)
+            .await
+            {
+                log::warn!(
+                    "username {} looks like another one, it must be changed: {e}",
+                    row.username
+                );
+                flag_rename_required(row.user_id, pool).await?;
+            }
+        }
+        if row.display_name_canonical.is_none() {
+            let canonical = canonical_name(&row.display_name);
+            if let Err(e) = sqlx::query!(
+                "UPDATE users SET display_name_canonical = ? WHERE user_id = ?",
+                canonical,
+                row.user_id
+            )
+            .execute(pool)
+            .await
+            {
+                log::warn!(
+                    "display name {} looks like another one: {e}",
+                    row.display_name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+async fn flag_rename_required(user_id: Uuid, pool: &SqlitePool) -> Result<(), DatabaseError> {
+    match sqlx::query!(
+        "UPDATE users SET rename_required = 1 WHERE user_id = ?",
+        user_id
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(_) => {
+            forget_cached_user(user_id);
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("database error when flagging username of {user_id}: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn rename_user(user_id: Uuid, username: &String) -> Result<(), AppError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in rename_user");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    rename_user_with_pool(user_id, username, &pool).await
+}
+
+/// Gives an account flagged with `rename_required` its new username, clearing the flag.
+/// Fails with `RegistrationError::UniqueUsername` when the name, or one that looks like it,
+/// is taken.
+#[cfg(feature = "ssr")]
+pub async fn rename_user_with_pool(
+    user_id: Uuid,
+    username: &String,
+    pool: &SqlitePool,
+) -> Result<(), AppError> {
+    let canonical = canonical_name(username);
+    let query_res = sqlx::query!(
+        "UPDATE users SET username = ?, username_canonical = ?, rename_required = 0 \
+         WHERE user_id = ? AND rename_required",
+        username,
+        canonical,
+        user_id
+    )
+    .execute(pool)
+    .await;
+    forget_cached_user(user_id);
+    match query_res {
+        Ok(val) if val.rows_affected() == 1 => Ok(()),
+        Ok(_) => Err(RegistrationError::RenameNotRequired.into()),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(RegistrationError::UniqueUsername.into())
+        }
+        Err(e) => {
+            log::error!("database error when renaming {user_id}: {e}");
+            Err(DatabaseError::QueryFailed.into())
+        }
+    }
+}
+
+/// Brings every stored normalized email in line with `policy`, which may have changed since
+/// the last start. An address that another account already holds is left as it was.
+#[cfg(feature = "ssr")]
+pub async fn normalize_emails_with_pool(
+    pool: &SqlitePool,
+    policy: EmailPolicy,
+) -> Result<(), DatabaseError> {
+    let rows = sqlx::query!(
+        r#"SELECT user_id AS "user_id: Uuid", email, email_normalized FROM users"#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        log::error!("normalize_emails_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })?;
+    for row in rows {
+        let normalized = canonical_email(&row.email, policy);
+        if row.email_normalized.as_ref() == Some(&normalized) {
+            continue;
+        }
+        if let Err(e) = sqlx::query!(
+            "UPDATE users SET email_normalized = ? WHERE user_id = ?",
+            normalized,
+            row.user_id
+        )
+        .execute(pool)
+        .await
+        {
+            log::warn!(
+                "account {} shares its email address with another one: {e}",
+                row.user_id
+            );
+        }
+    }
+    Ok(())
+}
+
 #[cfg(feature = "ssr")]
 pub async fn associate_session(
     user_id: Uuid,
     session_id: &String,
     expire_time: DateTime<Utc>,
+    absolute_expiry: DateTime<Utc>,
+    remember: bool,
+    ip: &String,
+    user_agent: &String,
 ) -> Result<(), AppError> {
     let pool = match use_context::<SqlitePool>() {
         Some(pool) => Ok(pool),
@@ -130,15 +360,24 @@ pub async fn associate_session(
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
+    let created = Utc::now();
     let query_res = sqlx::query!(
-        "INSERT INTO active_sesssions (session_id, user_id, expiry) VALUES (?, ?, ?)",
+        "INSERT INTO active_sesssions (session_id, user_id, expiry, created, last_seen, ip, \
+         user_agent, absolute_expiry, remember, rotated) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         session_id,
         user_id,
-        expire_time
+        expire_time,
+        created,
+        created,
+        ip,
+        user_agent,
+        absolute_expiry,
+        remember,
+        created
     )
     .execute(&pool)
     .await;
-    let _result = match query_res {
+    match query_res {
         Ok(val) => {
             if val.rows_affected() != 1 {
                 Err(DatabaseError::IncorrectRowsAffected)
@@ -154,6 +393,92 @@ pub async fn associate_session(
     Ok(())
 }
 
+/// Gives the session `session_id` the id `new_session_id` and the expiry `expire_time`.
+/// The old id keeps working for `SESSION_ROTATION_GRACE_SECS`.
+#[cfg(feature = "ssr")]
+pub async fn rotate_session_id(
+    session_id: &String,
+    new_session_id: &String,
+    expire_time: DateTime<Utc>,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in rotate_session_id");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let now = Utc::now();
+    let query_res = sqlx::query!(
+        "UPDATE active_sesssions SET session_id = ?, previous_session_id = ?, rotated = ?, \
+         expiry = ? WHERE session_id = ?",
+        new_session_id,
+        session_id,
+        now,
+        expire_time,
+        session_id
+    )
+    .execute(&pool)
+    .await;
+    forget_cached_sessions(std::slice::from_ref(session_id));
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when rotating session id: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Forgets cached copies of sessions that were just changed or dropped.
+#[cfg(feature = "ssr")]
+pub(crate) fn forget_cached_sessions(session_ids: &[String]) {
+    if let Some(cache) = use_context::<SessionCache>() {
+        cache.forget_sessions(session_ids);
+    }
+}
+
+/// Forgets the cached data of a user whose row was just changed.
+#[cfg(feature = "ssr")]
+pub(crate) fn forget_cached_user(user_id: Uuid) {
+    if let Some(cache) = use_context::<SessionCache>() {
+        cache.forget_user(user_id);
+    }
+}
+
+/// The id `session_id` had before its last rotation, if it was ever rotated.
+#[cfg(feature = "ssr")]
+pub async fn previous_session_id(
+    session_id: &String,
+) -> Result<Option<String>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in previous_session_id");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query_scalar!(
+        "SELECT previous_session_id FROM active_sesssions WHERE session_id = ?",
+        session_id
+    )
+    .fetch_optional(&pool)
+    .await;
+    match query_res {
+        Ok(previous) => Ok(previous.flatten()),
+        Err(e) => {
+            log::error!("database error when looking up a previous session id: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
 #[cfg(feature = "ssr")]
 pub async fn drop_session(session_id: &String) -> Result<(), DatabaseError> {
     let pool = match use_context::<SqlitePool>() {
@@ -169,6 +494,7 @@ pub async fn drop_session(session_id: &String) -> Result<(), DatabaseError> {
     )
     .execute(&pool)
     .await;
+    forget_cached_sessions(std::slice::from_ref(session_id));
     match remove_res {
         Ok(val) => {
             if val.rows_affected() != 1 {
@@ -179,26 +505,177 @@ pub async fn drop_session(session_id: &String) -> Result<(), DatabaseError> {
                 return Err(DatabaseError::IncorrectRowsAffected);
             }
             log::trace!("session_id: {session_id} logged out: {:#?}", val);
-            return Ok(());
+            Ok(())
         }
         Err(e) => {
             log::error!("removal of session from database failed: {e}");
-            return Err(DatabaseError::QueryFailed);
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Logs a user out everywhere, returning the dropped session ids.
+#[cfg(feature = "ssr")]
+pub async fn drop_user_sessions(user_id: Uuid) -> Result<Vec<String>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => pool,
+        None => {
+            log::error!("sql pool not available in drop_user_sessions, could not drop");
+            return Err(DatabaseError::CouldNotFindPool);
         }
     };
+    let remove_res = sqlx::query_scalar!(
+        "DELETE FROM active_sesssions WHERE user_id = ? RETURNING session_id",
+        user_id
+    )
+    .fetch_all(&pool)
+    .await;
+    match remove_res {
+        Ok(session_ids) => {
+            log::trace!("{} sessions dropped for {user_id}", session_ids.len());
+            forget_cached_sessions(&session_ids);
+            Ok(session_ids)
+        }
+        Err(e) => {
+            log::error!("removal of sessions for {user_id} from database failed: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
 }
 
+/// Logs a user out everywhere except the session `keep_session_id`, returning the dropped
+/// session ids.
 #[cfg(feature = "ssr")]
-#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
-struct ValidateSession {
+pub async fn drop_other_sessions(
     user_id: Uuid,
-    expiry: DateTime<Utc>,
+    keep_session_id: &String,
+) -> Result<Vec<String>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => pool,
+        None => {
+            log::error!("sql pool not available in drop_other_sessions, could not drop");
+            return Err(DatabaseError::CouldNotFindPool);
+        }
+    };
+    let remove_res = sqlx::query_scalar!(
+        "DELETE FROM active_sesssions WHERE user_id = ? AND session_id != ? \
+         AND (previous_session_id IS NULL OR previous_session_id != ?) RETURNING session_id",
+        user_id,
+        keep_session_id,
+        keep_session_id
+    )
+    .fetch_all(&pool)
+    .await;
+    match remove_res {
+        Ok(session_ids) => {
+            log::trace!("{} other sessions dropped for {user_id}", session_ids.len());
+            forget_cached_sessions(&session_ids);
+            Ok(session_ids)
+        }
+        Err(e) => {
+            log::error!("removal of other sessions for {user_id} from database failed: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Notes that the encrypted session `session_id` was just used, its cookie is what keeps it
+/// alive so only the time shown on the settings page changes.
+#[cfg(feature = "ssr")]
+pub async fn record_session_seen(session_id: &String) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in record_session_seen");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let now = Utc::now();
+    match sqlx::query!(
+        "UPDATE active_sesssions SET last_seen = ? WHERE session_id = ?",
+        now,
+        session_id
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("database error when recording a session was seen: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct StoredSession {
+    pub session_id: String,
+    pub expiry: DateTime<Utc>,
+    pub created: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Every unexpired session of a user, most recently seen first.
+#[cfg(feature = "ssr")]
+pub async fn retrieve_sessions(user_id: Uuid) -> Result<Vec<StoredSession>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_sessions");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let now = Utc::now();
+    sqlx::query_as!(
+        StoredSession,
+        r#"SELECT session_id, expiry AS "expiry: DateTime<Utc>", created AS "created: DateTime<Utc>",
+        last_seen AS "last_seen: DateTime<Utc>", ip, user_agent FROM active_sesssions
+        WHERE user_id = ? AND expiry > ? ORDER BY last_seen DESC"#,
+        user_id,
+        now
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("retrieve_sessions: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+/// The row of a session as `validate_token` reads it, and as `SessionCache` keeps it.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct ValidateSession {
+    pub session_id: String,
+    pub user_id: Uuid,
+    pub expiry: DateTime<Utc>,
+    pub absolute_expiry: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub rotated: Option<DateTime<Utc>>,
+    pub remember: bool,
+}
+
+/// A session that passed `validate_token`.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidSession {
+    /// the current id, differs from the one validated if that was rotated away recently
+    pub session_id: String,
+    pub user_id: Uuid,
+    pub absolute_expiry: DateTime<Utc>,
+    pub remember: bool,
+    /// the session is due a new id under the session policy
+    pub rotation_due: bool,
 }
 
 #[cfg(feature = "ssr")]
 pub async fn validate_token(
     untrusted_session: String,
-) -> Result<Option<uuid::Uuid>, DatabaseError> {
+    policy: SessionPolicy,
+) -> Result<Option<ValidSession>, DatabaseError> {
     let pool = match use_context::<SqlitePool>() {
         Some(pool) => Ok(pool),
         None => {
@@ -206,42 +683,185 @@ pub async fn validate_token(
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
-    validate_token_with_pool(untrusted_session, pool).await
+    let cache = use_context::<SessionCache>().unwrap_or_else(SessionCache::disabled);
+    validate_token_with_pool(untrusted_session, pool, policy, &cache).await
 }
 
 #[cfg(feature = "ssr")]
 pub async fn validate_token_with_pool(
     untrusted_session: String,
     pool: SqlitePool,
-) -> Result<Option<uuid::Uuid>, DatabaseError> {
+    policy: SessionPolicy,
+    cache: &SessionCache,
+) -> Result<Option<ValidSession>, DatabaseError> {
     if untrusted_session.is_empty() {
         return Ok(None);
     }
+    let now = Utc::now();
+    let grace_start = now - chrono::Duration::seconds(SESSION_ROTATION_GRACE_SECS);
+    let session = match cache.session(&untrusted_session) {
+        // a previous id only works for the grace period after the rotation
+        Some(session)
+            if session.session_id == untrusted_session
+                || session.rotated.is_some_and(|rotated| rotated > grace_start) =>
+        {
+            session
+        }
+        Some(_) => {
+            cache.forget_session(&untrusted_session);
+            return Ok(None);
+        }
+        None => {
+            let epoch = cache.epoch();
+            let session =
+                match read_session_with_pool(&untrusted_session, grace_start, &pool).await? {
+                    Some(session) => session,
+                    None => return Ok(None),
+                };
+            cache.store_session(&untrusted_session, session.clone(), epoch);
+            session
+        }
+    };
+    let absolute_expiry = session.absolute_expiry.unwrap_or(session.expiry);
+    //validate NOT expired
+    if session.expiry < now || absolute_expiry < now {
+        let _ = drop_session(&session.session_id).await;
+        cache.forget_session(&session.session_id);
+        return Ok(None);
+    }
+    // only write the last seen time and extend the idle expiry every few minutes,
+    // not on every request
+    if session.last_seen.is_none_or(|last_seen| {
+        now - last_seen > chrono::Duration::seconds(SESSION_LAST_SEEN_INTERVAL_SECS)
+    }) {
+        let epoch = cache.epoch();
+        let expire_time = policy.idle_expiry(now, absolute_expiry);
+        match sqlx::query!(
+            "UPDATE active_sesssions SET last_seen = ?, expiry = ? WHERE session_id = ?",
+            now,
+            expire_time,
+            session.session_id
+        )
+        .execute(&pool)
+        .await
+        {
+            Ok(_) => cache.store_session(
+                &untrusted_session,
+                ValidateSession {
+                    last_seen: Some(now),
+                    expiry: expire_time,
+                    ..session.clone()
+                },
+                epoch,
+            ),
+            Err(e) => log::error!("could not update last seen time of a session: {e}"),
+        }
+    }
+    // a request still using the previous id must not rotate the session a second time
+    let rotation_due = session.session_id == untrusted_session
+        && session
+            .rotated
+            .is_none_or(|rotated| now - rotated > policy.rotate_interval);
+    Ok(Some(ValidSession {
+        session_id: session.session_id,
+        user_id: session.user_id,
+        absolute_expiry,
+        remember: session.remember,
+        rotation_due,
+    }))
+}
+
+/// The session `untrusted_session` is the id of, or was the id of until a rotation after
+/// `grace_start`.
+#[cfg(feature = "ssr")]
+async fn read_session_with_pool(
+    untrusted_session: &String,
+    grace_start: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Option<ValidateSession>, DatabaseError> {
     let row = sqlx::query_as!(
         ValidateSession,
-        r#"SELECT user_id AS "user_id: Uuid", expiry AS "expiry: DateTime<Utc>" FROM active_sesssions WHERE session_id = ?"#,
-        untrusted_session
+        r#"SELECT session_id, user_id AS "user_id: Uuid", expiry AS "expiry: DateTime<Utc>",
+        absolute_expiry AS "absolute_expiry: DateTime<Utc>", last_seen AS "last_seen: DateTime<Utc>",
+        rotated AS "rotated: DateTime<Utc>", remember FROM active_sesssions
+        WHERE session_id = ? OR (previous_session_id = ? AND rotated > ?)"#,
+        untrusted_session,
+        untrusted_session,
+        grace_start
     )
-    .fetch_one(&pool)
+    .fetch_one(pool)
     .await;
-    let (true_uuid, expiry): (Uuid, DateTime<Utc>) = match row {
-        Ok(cred) => (cred.user_id, cred.expiry),
+    match row {
+        Ok(session) => Ok(Some(session)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("validate_token: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_password_hash(user_id: Uuid) -> Result<SecretString, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_password_hash");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query!("SELECT password_hash FROM users WHERE user_id = ?", user_id)
+        .fetch_one(&pool)
+        .await;
+    match row {
+        Ok(res) => Ok(SecretString::from(res.password_hash)),
         Err(e) => match e {
             sqlx::Error::RowNotFound => {
-                return Ok(None);
+                log::error!("database lookup for password on id {user_id} did not exist");
+                Err(DatabaseError::NoEntries)
             }
             _ => {
-                log::error!("validate_token: sqlx error: {e}");
-                return Err(DatabaseError::QueryFailed);
+                log::error!("database lookup for password on id {user_id} failed: {e}");
+                Err(DatabaseError::QueryFailed)
             }
         },
-    };
-    //validate NOT expired
-    if expiry < Utc::now() {
-        let _ = drop_session(&untrusted_session).await;
-        Ok(None)
-    } else {
-        Ok(Some(true_uuid))
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn update_password_hash(
+    user_id: Uuid,
+    password_hash: String,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in update_password_hash");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE user_id = ?",
+        password_hash,
+        user_id,
+    )
+    .execute(&pool)
+    .await;
+    forget_cached_user(user_id);
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when updating password for {user_id}: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
     }
 }
 
@@ -263,22 +883,36 @@ pub async fn retrieve_credentials(
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
+    retrieve_credentials_with_pool(username, &pool).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_credentials_with_pool(
+    username: &String,
+    pool: &SqlitePool,
+) -> Result<Option<(Uuid, SecretString)>, AppError> {
+    let canonical = canonical_name(username);
+    // an account flagged with rename_required has no canonical name and logs in by its
+    // exact name, which wins over the look-alike account holding the canonical name
     let row = sqlx::query_as!(
         ValidateCredential,
-        r#"SELECT user_id AS "user_id: Uuid", password_hash FROM users WHERE username = ?"#,
+        r#"SELECT user_id AS "user_id: Uuid", password_hash FROM users
+        WHERE username_canonical = ? OR username = ?
+        ORDER BY username = ? DESC LIMIT 1"#,
+        canonical,
+        username,
         username
     )
-    .fetch_one(&pool)
+    .fetch_optional(pool)
     .await;
     Ok(match row {
-        Ok(cred) => Ok(Some((cred.user_id, SecretString::from(cred.password_hash)))),
-        Err(e) => match e {
-            sqlx::Error::RowNotFound => Ok(None),
-            _ => {
-                log::trace!("failed login on username: {username} with error {e}");
-                Err(DatabaseError::QueryFailed)
-            }
-        },
+        Ok(cred) => {
+            Ok(cred.map(|cred| (cred.user_id, SecretString::from(cred.password_hash))))
+        }
+        Err(e) => {
+            log::trace!("failed login on username: {username} with error {e}");
+            Err(DatabaseError::QueryFailed)
+        }
     }?)
 }
 
@@ -287,7 +921,6 @@ pub async fn retrieve_credentials(
 pub enum UniqueCredential {
     Username(String),
     DisplayName(String),
-    //Email(String),
 }
 
 #[cfg(feature = "ssr")]
@@ -298,15 +931,17 @@ pub async fn unique_cred_check(input: UniqueCredential) -> Result<(), AppError>
     // but we should also restrict how many times people can check for unique
     // usernames to prevent user enumeration
     //
-    // the anti-bot id could be restricted to 10 username lookups/25 min?
-    // TODO: Actually implement anti-bot and anti-"username-enumeration"
+    // username lookups are rate limited per anti-bot id and per address,
+    // by default 10 lookups/25 min for each browser
     //
     // display name enumeration should be fine since you can see those while signed in
     // and display names are not used for sign in, only for displaying to other users
     match input {
-        UniqueCredential::Username(username) => username_check(username).await,
+        UniqueCredential::Username(username) => {
+            rate_limit(LimitedAction::UsernameCheck)?;
+            username_check(username).await
+        }
         UniqueCredential::DisplayName(display_name) => display_name_check(display_name).await,
-        /* UniqueCredential::Email(email) => email_check(email).await, */
     }
 }
 
@@ -319,25 +954,30 @@ async fn username_check(username: String) -> Result<(), AppError> {
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
-    let user_exists =
-        match sqlx::query!("SELECT username FROM users WHERE username = ?", username)
-            .fetch_one(&pool)
-            .await
-        {
-            Ok(_) => Ok(true), //username.eq(&row.username)
-            Err(e) => match e {
-                // row not found is returned as error, but it is not actually an error
-                sqlx::Error::RowNotFound => Ok(false),
-                _ => {
-                    log::error!("possible database error: {e}");
-                    Err(DatabaseError::QueryFailed)
-                }
-            },
-        }?;
+    let canonical = canonical_name(&username);
+    // the same rows retrieve_credentials would log in to
+    let user_exists = match sqlx::query!(
+        "SELECT username FROM users WHERE username_canonical = ? OR username = ?",
+        canonical,
+        username
+    )
+    .fetch_one(&pool)
+    .await
+    {
+        Ok(_) => Ok(true), //username.eq(&row.username)
+        Err(e) => match e {
+            // row not found is returned as error, but it is not actually an error
+            sqlx::Error::RowNotFound => Ok(false),
+            _ => {
+                log::error!("possible database error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }?;
     match user_exists {
         //TODO prevent user enumeration
-        true => return Err(RegistrationError::UniqueUsername.into()),
-        false => return Ok(()),
+        true => Err(RegistrationError::UniqueUsername.into()),
+        false => Ok(()),
     }
 }
 
@@ -350,8 +990,10 @@ async fn display_name_check(display_name: String) -> Result<(), AppError> {
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
+    let canonical = canonical_name(&display_name);
     let display_exists = match sqlx::query!(
-        "SELECT display_name FROM users WHERE display_name = ?",
+        "SELECT display_name FROM users WHERE display_name_canonical = ? OR display_name = ?",
+        canonical,
         display_name
     )
     .fetch_one(&pool)
@@ -374,35 +1016,155 @@ async fn display_name_check(display_name: String) -> Result<(), AppError> {
     }?)
 }
 
-/*#[cfg(feature = "ssr")]
-async fn email_check(email: String) -> Result<(), AppError> {
+/// The account that holds the normalized address `email_normalized`, if any. Emails are not
+/// a `UniqueCredential`, whether an address is taken must never reach the client.
+#[cfg(feature = "ssr")]
+pub async fn email_owner(email_normalized: &String) -> Result<Option<Uuid>, AppError> {
     let pool = match use_context::<SqlitePool>() {
         Some(pool) => Ok(pool),
         None => {
-            log::error!("sql pool not available in email_check");
+            log::error!("sql pool not available in email_owner");
             Err(DatabaseError::CouldNotFindPool)
         }
     }?;
-    let display_exists = match sqlx::query!(
-        "SELECT email FROM users WHERE email = ?",
-        email
+    Ok(sqlx::query_scalar!(
+        r#"SELECT user_id AS "user_id: Uuid" FROM users WHERE email_normalized = ?"#,
+        email_normalized
     )
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
-    {
-        Ok(_) => Ok(true), //email.eq(&row.email)
-        Err(e) => match e {
-            // row not found is returned as error, but it is not actually an error
-            sqlx::Error::RowNotFound => Ok(false),
-            _ => {
-                log::error!("possible database error: {e}");
-                Err(DatabaseError::QueryFailed)
-            }
-        },
-    }?;
-    Ok(if display_exists {
-        Err(RegistrationError::UniqueDisplayName)
-    } else {
-        Ok(())
-    }?)
-}*/
+    .map_err(|e| {
+        log::error!("email_owner: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })?)
+}
+
+/// A fresh in-memory database with every migration applied, for tests.
+#[cfg(all(test, feature = "ssr"))]
+pub(crate) async fn memory_pool() -> SqlitePool {
+    // every connection to :memory: is its own database, so the pool keeps exactly one
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("an in-memory database to open");
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("the migrations to apply");
+    pool
+}
+
+/// Adds an account named `username` for tests, returning its id.
+#[cfg(all(test, feature = "ssr"))]
+pub(crate) async fn insert_test_user(username: &str, pool: &SqlitePool) -> Uuid {
+    let id = Uuid::now_v7();
+    let canonical = canonical_name(username);
+    let email = format!("{username}@example.com");
+    sqlx::query!(
+        "INSERT INTO users (user_id, username, display_name, email, verified, password_hash, \
+         button_presses, username_canonical, display_name_canonical, email_normalized) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        id,
+        username,
+        username,
+        email,
+        true,
+        "",
+        0,
+        canonical,
+        canonical,
+        email,
+    )
+    .execute(pool)
+    .await
+    .expect("the test user to be inserted");
+    id
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    /// An account from before canonical names were stored.
+    async fn insert_legacy_user(username: &str, pool: &SqlitePool) -> Uuid {
+        let id = insert_test_user(username, pool).await;
+        sqlx::query!(
+            "UPDATE users SET username_canonical = NULL, display_name_canonical = NULL \
+             WHERE user_id = ?",
+            id
+        )
+        .execute(pool)
+        .await
+        .expect("the canonical names to be cleared");
+        id
+    }
+
+    async fn rename_required(user_id: Uuid, pool: &SqlitePool) -> bool {
+        sqlx::query_scalar!(
+            "SELECT rename_required FROM users WHERE user_id = ?",
+            user_id
+        )
+        .fetch_one(pool)
+        .await
+        .expect("the user to exist")
+    }
+
+    async fn login_id(username: &str, pool: &SqlitePool) -> Option<Uuid> {
+        retrieve_credentials_with_pool(&username.to_string(), pool)
+            .await
+            .expect("the lookup to succeed")
+            .map(|(id, _)| id)
+    }
+
+    #[tokio::test]
+    async fn colliding_legacy_username_is_flagged() {
+        let pool = memory_pool().await;
+        let legacy = insert_legacy_user("b0b", &pool).await;
+        let holder = insert_test_user("Bob", &pool).await;
+        let other = insert_legacy_user("carol", &pool).await;
+        backfill_canonical_names_with_pool(&pool).await.unwrap();
+        assert!(rename_required(legacy, &pool).await);
+        assert!(!rename_required(holder, &pool).await);
+        assert!(!rename_required(other, &pool).await);
+        assert_eq!(login_id("CAROL", &pool).await, Some(other));
+    }
+
+    #[tokio::test]
+    async fn colliding_legacy_username_logs_in_by_its_exact_name_only() {
+        let pool = memory_pool().await;
+        let legacy = insert_legacy_user("b0b", &pool).await;
+        let holder = insert_test_user("Bob", &pool).await;
+        backfill_canonical_names_with_pool(&pool).await.unwrap();
+        assert_eq!(login_id("b0b", &pool).await, Some(legacy));
+        assert_eq!(login_id("Bob", &pool).await, Some(holder));
+        assert_eq!(login_id("B0B", &pool).await, Some(holder));
+        assert_eq!(login_id("nobody", &pool).await, None);
+    }
+
+    #[tokio::test]
+    async fn flagged_account_chooses_a_free_username_once() {
+        let pool = memory_pool().await;
+        let legacy = insert_legacy_user("b0b", &pool).await;
+        let holder = insert_test_user("Bob", &pool).await;
+        backfill_canonical_names_with_pool(&pool).await.unwrap();
+        assert!(matches!(
+            rename_user_with_pool(legacy, &"BOB".to_string(), &pool).await,
+            Err(AppError::Registration(RegistrationError::UniqueUsername))
+        ));
+        rename_user_with_pool(legacy, &"robert".to_string(), &pool)
+            .await
+            .unwrap();
+        assert!(!rename_required(legacy, &pool).await);
+        assert_eq!(login_id("Robert", &pool).await, Some(legacy));
+        assert_eq!(login_id("b0b", &pool).await, Some(holder));
+        for user_id in [legacy, holder] {
+            assert!(matches!(
+                rename_user_with_pool(user_id, &"bobby".to_string(), &pool).await,
+                Err(AppError::Registration(RegistrationError::RenameNotRequired))
+            ));
+        }
+    }
+}
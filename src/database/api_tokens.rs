@@ -0,0 +1,154 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::{ApiTokenError, AppError, DatabaseError};
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct StoredApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: String,
+    pub created: DateTime<Utc>,
+    pub expiry: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_api_tokens(user_id: Uuid) -> Result<Vec<StoredApiToken>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_api_tokens");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_as!(
+        StoredApiToken,
+        r#"SELECT token_id AS "token_id: Uuid", name, scopes,
+        created AS "created: DateTime<Utc>", expiry AS "expiry: DateTime<Utc>"
+        FROM api_tokens WHERE user_id = ? ORDER BY created"#,
+        user_id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("retrieve_api_tokens: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+/// Stores a new token, as long as the account has fewer than `max_tokens` of them.
+#[cfg(feature = "ssr")]
+pub async fn insert_api_token(
+    user_id: Uuid,
+    name: &String,
+    token_hash: &String,
+    scopes: &String,
+    expiry: DateTime<Utc>,
+    max_tokens: i64,
+) -> Result<Uuid, AppError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in insert_api_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let token_id = Uuid::new_v4();
+    let created = Utc::now();
+    // the count and insert are one statement, so parallel requests cannot pass the limit
+    let query_res = sqlx::query!(
+        "INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created, expiry) \
+         SELECT ?, ?, ?, ?, ?, ?, ? \
+         WHERE (SELECT COUNT(*) FROM api_tokens WHERE user_id = ?) < ?",
+        token_id,
+        user_id,
+        name,
+        token_hash,
+        scopes,
+        created,
+        expiry,
+        user_id,
+        max_tokens,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(ApiTokenError::TooMany.into())
+            } else {
+                Ok(token_id)
+            }
+        }
+        Err(e) => {
+            log::error!("database error when inserting api token: {e}");
+            Err(DatabaseError::QueryFailed.into())
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn delete_api_token(user_id: Uuid, token_id: Uuid) -> Result<(), AppError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in delete_api_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "DELETE FROM api_tokens WHERE user_id = ? AND token_id = ?",
+        user_id,
+        token_id,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(ApiTokenError::NotFound.into())
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when deleting api token: {e}");
+            Err(DatabaseError::QueryFailed.into())
+        }
+    }
+}
+
+/// The account and scopes a presented token stands for
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct ApiTokenGrant {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: String,
+    pub expiry: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn api_token_grant_with_pool(
+    token_hash: &String,
+    pool: &SqlitePool,
+) -> Result<Option<ApiTokenGrant>, DatabaseError> {
+    sqlx::query_as!(
+        ApiTokenGrant,
+        r#"SELECT token_id AS "token_id: Uuid", user_id AS "user_id: Uuid", scopes,
+        expiry AS "expiry: DateTime<Utc>" FROM api_tokens WHERE token_hash = ?"#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        log::error!("api_token_grant_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
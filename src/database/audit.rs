@@ -0,0 +1,53 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::{AuditEvent, DatabaseError};
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+pub async fn insert_audit_event(
+    user_id: Uuid,
+    event: AuditEvent,
+    ip: &String,
+    user_agent: &String,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in insert_audit_event");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let event_id = Uuid::now_v7();
+    let event = event.as_str();
+    let created = Utc::now();
+    let query_res = sqlx::query!(
+        "INSERT INTO audit_log (event_id, user_id, event, ip, user_agent, created) \
+         VALUES (?, ?, ?, ?, ?, ?)",
+        event_id,
+        user_id,
+        event,
+        ip,
+        user_agent,
+        created,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when writing audit event: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
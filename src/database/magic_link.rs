@@ -0,0 +1,93 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+/// Stores a login link token bound to the browser that asked for it, replacing any earlier
+/// token so only the most recently sent link works.
+#[cfg(feature = "ssr")]
+pub async fn replace_magic_link_token_with_pool(
+    user_id: Uuid,
+    token_hash: &String,
+    csrf_cookie: &String,
+    expire_time: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    if let Err(e) = sqlx::query!("DELETE FROM magic_link_tokens WHERE user_id = ?", user_id)
+        .execute(pool)
+        .await
+    {
+        log::error!("database error when dropping old login link tokens: {e}");
+        return Err(DatabaseError::QueryFailed);
+    }
+    let query_res = sqlx::query!(
+        "INSERT INTO magic_link_tokens (token_hash, user_id, csrf_cookie, expiry) \
+         VALUES (?, ?, ?, ?)",
+        token_hash,
+        user_id,
+        csrf_cookie,
+        expire_time,
+    )
+    .execute(pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing login link token: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct MagicLinkToken {
+    pub user_id: Uuid,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Removes and returns a login link token so it can only be used once. A token opened in
+/// another browser is left in place for the browser that asked for it.
+#[cfg(feature = "ssr")]
+pub async fn take_magic_link_token(
+    token_hash: &String,
+    csrf_cookie: &String,
+) -> Result<Option<MagicLinkToken>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in take_magic_link_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        MagicLinkToken,
+        r#"DELETE FROM magic_link_tokens WHERE token_hash = ? AND csrf_cookie = ?
+        RETURNING user_id AS "user_id: Uuid", expiry AS "expiry: DateTime<Utc>""#,
+        token_hash,
+        csrf_cookie
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(token) => Ok(Some(token)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("take_magic_link_token: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
@@ -0,0 +1,254 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct OidcFlow {
+    pub provider: String,
+    pub csrf_cookie: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Stores a login that is leaving for a provider, found again by its `state` when the
+/// browser comes back.
+#[cfg(feature = "ssr")]
+pub async fn store_oidc_flow(state: &String, flow: &OidcFlow) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in store_oidc_flow");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "INSERT INTO oidc_flows (state, provider, csrf_cookie, pkce_verifier, nonce, expiry) \
+         VALUES (?, ?, ?, ?, ?, ?)",
+        state,
+        flow.provider,
+        flow.csrf_cookie,
+        flow.pkce_verifier,
+        flow.nonce,
+        flow.expiry,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing oidc flow: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Removes and returns a stored login so the browser can only come back with it once.
+#[cfg(feature = "ssr")]
+pub async fn take_oidc_flow(state: &String) -> Result<Option<OidcFlow>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in take_oidc_flow");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        OidcFlow,
+        r#"DELETE FROM oidc_flows WHERE state = ?
+        RETURNING provider, csrf_cookie, pkce_verifier, nonce, expiry AS "expiry: DateTime<Utc>""#,
+        state
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(flow) => Ok(Some(flow)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("take_oidc_flow: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+/// The account a provider's user signs in to, if they signed in before.
+#[cfg(feature = "ssr")]
+pub async fn linked_user(
+    issuer: &String,
+    subject: &String,
+) -> Result<Option<Uuid>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in linked_user");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_scalar!(
+        r#"SELECT user_id AS "user_id: Uuid" FROM linked_identities WHERE issuer = ? AND subject = ?"#,
+        issuer,
+        subject
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("linked_user: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub async fn link_identity(
+    issuer: &String,
+    subject: &String,
+    provider: &String,
+    user_id: Uuid,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in link_identity");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let created = Utc::now();
+    let query_res = sqlx::query!(
+        "INSERT INTO linked_identities (issuer, subject, user_id, provider, created) \
+         VALUES (?, ?, ?, ?, ?)",
+        issuer,
+        subject,
+        user_id,
+        provider,
+        created,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when linking identity: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct PendingOidcSignup {
+    pub provider: String,
+    pub issuer: String,
+    pub subject: String,
+    pub email: String,
+    pub expiry: DateTime<Utc>,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn store_pending_oidc_signup(
+    token_hash: &String,
+    signup: &PendingOidcSignup,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in store_pending_oidc_signup");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "INSERT INTO oidc_pending_signups (token_hash, provider, issuer, subject, email, expiry) \
+         VALUES (?, ?, ?, ?, ?, ?)",
+        token_hash,
+        signup.provider,
+        signup.issuer,
+        signup.subject,
+        signup.email,
+        signup.expiry,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing pending oidc signup: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Looks up a pending signup without removing it, a taken display name can be retried.
+#[cfg(feature = "ssr")]
+pub async fn retrieve_pending_oidc_signup(
+    token_hash: &String,
+) -> Result<Option<PendingOidcSignup>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_pending_oidc_signup");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_as!(
+        PendingOidcSignup,
+        r#"SELECT provider, issuer, subject, email,
+        expiry AS "expiry: DateTime<Utc>" FROM oidc_pending_signups WHERE token_hash = ?"#,
+        token_hash
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("retrieve_pending_oidc_signup: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+/// Removes a pending signup, returning whether it was still there to remove.
+#[cfg(feature = "ssr")]
+pub async fn drop_pending_oidc_signup(token_hash: &String) -> Result<bool, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in drop_pending_oidc_signup");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    match sqlx::query!(
+        "DELETE FROM oidc_pending_signups WHERE token_hash = ?",
+        token_hash
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(val) => Ok(val.rows_affected() == 1),
+        Err(e) => {
+            log::error!("database error when dropping pending oidc signup: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
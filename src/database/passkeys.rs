@@ -0,0 +1,230 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::{AppError, DatabaseError, PasskeyError};
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct PasskeyChallenge {
+    pub user_id: Option<Uuid>,
+    pub state: String,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Stores the server half of a passkey ceremony against the browser's csrf cookie.
+/// A browser only ever has one ceremony in flight, starting a new one replaces the old one.
+#[cfg(feature = "ssr")]
+pub async fn store_passkey_challenge_with_pool(
+    csrf_cookie: &String,
+    ceremony: &str,
+    user_id: Option<Uuid>,
+    state: String,
+    expire_time: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    let query_res = sqlx::query!(
+        "INSERT INTO passkey_challenges (csrf_cookie, ceremony, user_id, state, expiry) \
+         VALUES (?, ?, ?, ?, ?) ON CONFLICT(csrf_cookie) DO UPDATE SET \
+         ceremony = excluded.ceremony, user_id = excluded.user_id, state = excluded.state, \
+         expiry = excluded.expiry",
+        csrf_cookie,
+        ceremony,
+        user_id,
+        state,
+        expire_time,
+    )
+    .execute(pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing passkey challenge: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Removes and returns a stored passkey ceremony so it can only be completed once.
+#[cfg(feature = "ssr")]
+pub async fn take_passkey_challenge_with_pool(
+    csrf_cookie: &String,
+    ceremony: &str,
+    pool: &SqlitePool,
+) -> Result<Option<PasskeyChallenge>, DatabaseError> {
+    let row = sqlx::query_as!(
+        PasskeyChallenge,
+        r#"DELETE FROM passkey_challenges WHERE csrf_cookie = ? AND ceremony = ?
+        RETURNING user_id AS "user_id?: Uuid", state, expiry AS "expiry: DateTime<Utc>""#,
+        csrf_cookie,
+        ceremony
+    )
+    .fetch_one(pool)
+    .await;
+    match row {
+        Ok(challenge) => Ok(Some(challenge)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("take_passkey_challenge: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct StoredPasskey {
+    pub credential_id: String,
+    pub name: String,
+    pub passkey: String,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_passkeys(user_id: Uuid) -> Result<Vec<StoredPasskey>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_passkeys");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_as!(
+        StoredPasskey,
+        r#"SELECT credential_id, name, passkey, created AS "created: DateTime<Utc>",
+        last_used AS "last_used: DateTime<Utc>" FROM passkey_credentials WHERE user_id = ?
+        ORDER BY created"#,
+        user_id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("retrieve_passkeys: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub async fn insert_passkey(
+    credential_id: &String,
+    user_id: Uuid,
+    name: &String,
+    passkey: String,
+) -> Result<(), AppError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in insert_passkey");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let created = Utc::now();
+    let query_res = sqlx::query!(
+        "INSERT INTO passkey_credentials (credential_id, user_id, name, passkey, created) \
+         VALUES (?, ?, ?, ?, ?)",
+        credential_id,
+        user_id,
+        name,
+        passkey,
+        created,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected.into())
+            } else {
+                Ok(())
+            }
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            // the credential id is already registered, possibly to another account
+            Err(PasskeyError::AlreadyRegistered.into())
+        }
+        Err(e) => {
+            log::error!("database error when inserting passkey: {e}");
+            Err(DatabaseError::QueryFailed.into())
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn update_passkey(
+    credential_id: &String,
+    passkey: String,
+    last_used: DateTime<Utc>,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in update_passkey");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE passkey_credentials SET passkey = ?, last_used = ? WHERE credential_id = ?",
+        passkey,
+        last_used,
+        credential_id,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when updating passkey: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn delete_passkey(user_id: Uuid, credential_id: &String) -> Result<(), AppError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in delete_passkey");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "DELETE FROM passkey_credentials WHERE user_id = ? AND credential_id = ?",
+        user_id,
+        credential_id,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(PasskeyError::NotFound.into())
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when deleting passkey: {e}");
+            Err(DatabaseError::QueryFailed.into())
+        }
+    }
+}
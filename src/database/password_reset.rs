@@ -0,0 +1,124 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use crate::security::canonical::canonical_name;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct ResetCandidate {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+}
+
+/// Every account whose username or email address is `identifier`, `email_normalized` being
+/// the normalized form of `identifier` read as an address.
+#[cfg(feature = "ssr")]
+pub async fn reset_candidates_with_pool(
+    identifier: &String,
+    email_normalized: &String,
+    pool: &SqlitePool,
+) -> Result<Vec<ResetCandidate>, DatabaseError> {
+    let canonical = canonical_name(identifier);
+    sqlx::query_as!(
+        ResetCandidate,
+        r#"SELECT user_id AS "user_id: Uuid", username, email FROM users
+        WHERE username_canonical = ? OR username = ? OR email = ? OR email_normalized = ?"#,
+        canonical,
+        identifier,
+        identifier,
+        email_normalized
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        log::error!("reset_candidates_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+/// Stores a reset token, replacing any earlier token so only the most recently sent link works.
+#[cfg(feature = "ssr")]
+pub async fn replace_reset_token_with_pool(
+    user_id: Uuid,
+    token_hash: &String,
+    expire_time: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    if let Err(e) = sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = ?",
+        user_id
+    )
+    .execute(pool)
+    .await
+    {
+        log::error!("database error when dropping old reset tokens: {e}");
+        return Err(DatabaseError::QueryFailed);
+    }
+    let query_res = sqlx::query!(
+        "INSERT INTO password_reset_tokens (token_hash, user_id, expiry) VALUES (?, ?, ?)",
+        token_hash,
+        user_id,
+        expire_time,
+    )
+    .execute(pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing reset token: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct ResetToken {
+    pub user_id: Uuid,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Removes and returns a reset token so it can only be used once.
+#[cfg(feature = "ssr")]
+pub async fn take_reset_token(
+    token_hash: &String,
+) -> Result<Option<ResetToken>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in take_reset_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        ResetToken,
+        r#"DELETE FROM password_reset_tokens WHERE token_hash = ?
+        RETURNING user_id AS "user_id: Uuid", expiry AS "expiry: DateTime<Utc>""#,
+        token_hash
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(token) => Ok(Some(token)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("take_reset_token: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
@@ -0,0 +1,62 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+}}
+
+/// Marks a solved challenge as used. Returns false if it was used before.
+#[cfg(feature = "ssr")]
+pub async fn redeem_challenge(
+    challenge_hash: &String,
+    now: DateTime<Utc>,
+    expire_time: DateTime<Utc>,
+) -> Result<bool, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in redeem_challenge");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "INSERT OR IGNORE INTO pow_redemptions (challenge_hash, redeemed, expiry) \
+         VALUES (?, ?, ?)",
+        challenge_hash,
+        now,
+        expire_time
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => Ok(val.rows_affected() == 1),
+        Err(e) => {
+            log::error!("database error when redeeming proof of work challenge: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// How many challenges were solved since `since`, a measure of recent signup attempts.
+#[cfg(feature = "ssr")]
+pub async fn redemptions_since(since: DateTime<Utc>) -> Result<i64, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in redemptions_since");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM pow_redemptions WHERE redeemed > ?",
+        since
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("redemptions_since: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
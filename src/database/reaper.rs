@@ -0,0 +1,213 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use sqlx::SqlitePool;
+}}
+
+/// Tables whose rows stop being useful at their `expiry`.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpiringTable {
+    LoginChallenges,
+    PasskeyChallenges,
+    EmailVerificationTokens,
+    PasswordResetTokens,
+    MagicLinkTokens,
+    LoginThrottle,
+    PowRedemptions,
+    OidcFlows,
+    OidcPendingSignups,
+    ApiTokens,
+    RevokedSessionCookies,
+}
+
+#[cfg(feature = "ssr")]
+impl ExpiringTable {
+    pub const ALL: [ExpiringTable; 11] = [
+        ExpiringTable::LoginChallenges,
+        ExpiringTable::PasskeyChallenges,
+        ExpiringTable::EmailVerificationTokens,
+        ExpiringTable::PasswordResetTokens,
+        ExpiringTable::MagicLinkTokens,
+        ExpiringTable::LoginThrottle,
+        ExpiringTable::PowRedemptions,
+        ExpiringTable::OidcFlows,
+        ExpiringTable::OidcPendingSignups,
+        ExpiringTable::ApiTokens,
+        ExpiringTable::RevokedSessionCookies,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpiringTable::LoginChallenges => "login_challenges",
+            ExpiringTable::PasskeyChallenges => "passkey_challenges",
+            ExpiringTable::EmailVerificationTokens => "email_verification_tokens",
+            ExpiringTable::PasswordResetTokens => "password_reset_tokens",
+            ExpiringTable::MagicLinkTokens => "magic_link_tokens",
+            ExpiringTable::LoginThrottle => "login_throttle",
+            ExpiringTable::PowRedemptions => "pow_redemptions",
+            ExpiringTable::OidcFlows => "oidc_flows",
+            ExpiringTable::OidcPendingSignups => "oidc_pending_signups",
+            ExpiringTable::ApiTokens => "api_tokens",
+            ExpiringTable::RevokedSessionCookies => "revoked_session_cookies",
+        }
+    }
+}
+
+/// Deletes up to `batch_size` sessions that expired before `now`, returning their ids.
+#[cfg(feature = "ssr")]
+pub async fn purge_expired_sessions_with_pool(
+    pool: &SqlitePool,
+    now: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<Vec<String>, DatabaseError> {
+    sqlx::query_scalar!(
+        "DELETE FROM active_sesssions WHERE rowid IN (SELECT rowid FROM active_sesssions \
+         WHERE expiry < ? OR absolute_expiry < ? LIMIT ?) RETURNING session_id",
+        now,
+        now,
+        batch_size
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        log::error!("purge_expired_sessions_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+/// Deletes up to `batch_size` rows of `table` that expired before `now`, returning how many
+/// were deleted.
+#[cfg(feature = "ssr")]
+pub async fn purge_expired_with_pool(
+    pool: &SqlitePool,
+    table: ExpiringTable,
+    now: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<u64, DatabaseError> {
+    // table names cannot be bound, so each table gets its own checked query
+    let query_res = match table {
+        ExpiringTable::LoginChallenges => {
+            sqlx::query!(
+                "DELETE FROM login_challenges WHERE rowid IN (SELECT rowid FROM \
+                 login_challenges WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::PasskeyChallenges => {
+            sqlx::query!(
+                "DELETE FROM passkey_challenges WHERE rowid IN (SELECT rowid FROM \
+                 passkey_challenges WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::EmailVerificationTokens => {
+            sqlx::query!(
+                "DELETE FROM email_verification_tokens WHERE rowid IN (SELECT rowid FROM \
+                 email_verification_tokens WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::PasswordResetTokens => {
+            sqlx::query!(
+                "DELETE FROM password_reset_tokens WHERE rowid IN (SELECT rowid FROM \
+                 password_reset_tokens WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::MagicLinkTokens => {
+            sqlx::query!(
+                "DELETE FROM magic_link_tokens WHERE rowid IN (SELECT rowid FROM \
+                 magic_link_tokens WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::LoginThrottle => {
+            sqlx::query!(
+                "DELETE FROM login_throttle WHERE rowid IN (SELECT rowid FROM \
+                 login_throttle WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::PowRedemptions => {
+            sqlx::query!(
+                "DELETE FROM pow_redemptions WHERE rowid IN (SELECT rowid FROM \
+                 pow_redemptions WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::OidcFlows => {
+            sqlx::query!(
+                "DELETE FROM oidc_flows WHERE rowid IN (SELECT rowid FROM \
+                 oidc_flows WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::OidcPendingSignups => {
+            sqlx::query!(
+                "DELETE FROM oidc_pending_signups WHERE rowid IN (SELECT rowid FROM \
+                 oidc_pending_signups WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::ApiTokens => {
+            sqlx::query!(
+                "DELETE FROM api_tokens WHERE rowid IN (SELECT rowid FROM \
+                 api_tokens WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+        ExpiringTable::RevokedSessionCookies => {
+            sqlx::query!(
+                "DELETE FROM revoked_session_cookies WHERE rowid IN (SELECT rowid FROM \
+                 revoked_session_cookies WHERE expiry < ? LIMIT ?)",
+                now,
+                batch_size
+            )
+            .execute(pool)
+            .await
+        }
+    };
+    match query_res {
+        Ok(val) => Ok(val.rows_affected()),
+        Err(e) => {
+            log::error!(
+                "purge_expired_with_pool: sqlx error on {}: {e}",
+                table.name()
+            );
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
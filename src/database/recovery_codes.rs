@@ -0,0 +1,156 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use secrecy::SecretString;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct RecoveryCodeRow {
+    code_id: Uuid,
+    code_hash: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug)]
+pub struct StoredRecoveryCode {
+    pub code_id: Uuid,
+    pub code_hash: SecretString,
+}
+
+/// Replaces every recovery code of the account, used or not, with `code_hashes`.
+#[cfg(feature = "ssr")]
+pub async fn replace_recovery_codes(
+    user_id: Uuid,
+    code_hashes: &[String],
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in replace_recovery_codes");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let mut tx = pool.begin().await.map_err(|e| {
+        log::error!("could not start transaction in replace_recovery_codes: {e}");
+        DatabaseError::QueryFailed
+    })?;
+    if let Err(e) = sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await
+    {
+        log::error!("database error when dropping old recovery codes: {e}");
+        return Err(DatabaseError::QueryFailed);
+    }
+    for code_hash in code_hashes {
+        let code_id = Uuid::now_v7();
+        if let Err(e) = sqlx::query!(
+            "INSERT INTO recovery_codes (code_id, user_id, code_hash, used) VALUES (?, ?, ?, NULL)",
+            code_id,
+            user_id,
+            code_hash,
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            log::error!("database error when storing recovery code: {e}");
+            return Err(DatabaseError::QueryFailed);
+        }
+    }
+    tx.commit().await.map_err(|e| {
+        log::error!("could not commit recovery codes: {e}");
+        DatabaseError::QueryFailed
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub async fn unused_recovery_codes(
+    user_id: Uuid,
+) -> Result<Vec<StoredRecoveryCode>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in unused_recovery_codes");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let rows = sqlx::query_as!(
+        RecoveryCodeRow,
+        r#"SELECT code_id AS "code_id: Uuid", code_hash FROM recovery_codes
+        WHERE user_id = ? AND used IS NULL"#,
+        user_id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("unused_recovery_codes: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })?;
+    Ok(rows
+        .into_iter()
+        .map(|row| StoredRecoveryCode {
+            code_id: row.code_id,
+            code_hash: SecretString::from(row.code_hash),
+        })
+        .collect())
+}
+
+/// Marks a code as used. Only succeeds for a code that was still unused, so a code can never
+/// be accepted twice.
+#[cfg(feature = "ssr")]
+pub async fn consume_recovery_code(code_id: Uuid) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in consume_recovery_code");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let used = Utc::now();
+    let query_res = sqlx::query!(
+        "UPDATE recovery_codes SET used = ? WHERE code_id = ? AND used IS NULL",
+        used,
+        code_id,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when consuming recovery code: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn delete_recovery_codes(user_id: Uuid) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in delete_recovery_codes");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    match sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&pool)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("database error when deleting recovery codes: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
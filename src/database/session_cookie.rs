@@ -0,0 +1,129 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::{forget_cached_sessions, forget_cached_user};
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+/// The session generation of `user_id`, which every encrypted session cookie of the user has
+/// to carry to stay valid.
+#[cfg(feature = "ssr")]
+pub async fn session_generation(user_id: Uuid) -> Result<i64, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in session_generation");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    sqlx::query_scalar!(
+        r#"SELECT session_generation AS "session_generation: i64" FROM users WHERE user_id = ?"#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => DatabaseError::NoEntries,
+        _ => {
+            log::error!("session_generation: sqlx error: {e}");
+            DatabaseError::QueryFailed
+        }
+    })
+}
+
+/// Ends every encrypted session of `user_id` at once, returning the new generation.
+#[cfg(feature = "ssr")]
+pub async fn bump_session_generation(user_id: Uuid) -> Result<i64, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in bump_session_generation");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let generation = sqlx::query_scalar!(
+        r#"UPDATE users SET session_generation = session_generation + 1 WHERE user_id = ?
+        RETURNING session_generation AS "session_generation: i64""#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        log::error!("bump_session_generation: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    });
+    forget_cached_user(user_id);
+    generation
+}
+
+/// Remembers that the encrypted session `session_id` was logged out, until its cookie would
+/// have expired anyway.
+#[cfg(feature = "ssr")]
+pub async fn revoke_session_cookie(
+    user_id: Uuid,
+    session_id: &String,
+    expiry: DateTime<Utc>,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in revoke_session_cookie");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    match sqlx::query!(
+        "INSERT OR IGNORE INTO revoked_session_cookies (session_id, user_id, expiry) \
+         VALUES (?, ?, ?)",
+        session_id,
+        user_id,
+        expiry
+    )
+    .execute(&pool)
+    .await
+    {
+        Ok(_) => {
+            forget_cached_sessions(std::slice::from_ref(session_id));
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("database error when revoking a session cookie: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// What an encrypted session cookie is checked against
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct SessionCookieStanding {
+    pub session_generation: i64,
+    pub revoked: bool,
+}
+
+/// The current session generation of `user_id` and whether `session_id` was logged out, in
+/// one read. `None` when the user no longer exists.
+#[cfg(feature = "ssr")]
+pub async fn session_cookie_standing_with_pool(
+    user_id: Uuid,
+    session_id: &String,
+    pool: &SqlitePool,
+) -> Result<Option<SessionCookieStanding>, DatabaseError> {
+    sqlx::query_as!(
+        SessionCookieStanding,
+        r#"SELECT session_generation AS "session_generation!: i64",
+        EXISTS(SELECT 1 FROM revoked_session_cookies WHERE session_id = ?) AS "revoked!: bool"
+        FROM users WHERE user_id = ?"#,
+        session_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        log::error!("session_cookie_standing_with_pool: sqlx error: {e}");
+        DatabaseError::QueryFailed
+    })
+}
@@ -0,0 +1,116 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use sqlx::SqlitePool;
+}}
+
+/// Counts a login attempt against `throttle_key` in one statement, returning the number of
+/// attempts since the counter last expired, or `None` without counting while the key is
+/// blocked. The counter expires at `expire_time` unless another attempt follows.
+#[cfg(feature = "ssr")]
+pub async fn count_login_attempt_with_pool(
+    throttle_key: &String,
+    now: DateTime<Utc>,
+    expire_time: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<Option<i64>, DatabaseError> {
+    let row = sqlx::query_scalar!(
+        "INSERT INTO login_throttle (throttle_key, failures, blocked_until, expiry) \
+         VALUES (?, 1, NULL, ?) ON CONFLICT(throttle_key) DO UPDATE SET \
+         failures = CASE WHEN expiry > ? THEN failures + 1 ELSE 1 END, \
+         blocked_until = NULL, expiry = excluded.expiry \
+         WHERE blocked_until IS NULL OR blocked_until <= ? RETURNING failures",
+        throttle_key,
+        expire_time,
+        now,
+        now
+    )
+    .fetch_optional(pool)
+    .await;
+    match row {
+        Ok(failures) => Ok(failures),
+        Err(e) => {
+            log::error!("database error when counting login attempt: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Takes back an attempt counted against `throttle_key` that turned out not to fail.
+#[cfg(feature = "ssr")]
+pub async fn uncount_login_attempt_with_pool(
+    throttle_key: &String,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    match sqlx::query!(
+        "UPDATE login_throttle SET failures = MAX(failures - 1, 0) WHERE throttle_key = ?",
+        throttle_key
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("database error when uncounting login attempt: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Blocks logins for `throttle_key` until `blocked_until`, unless they are already blocked
+/// for longer, keeping the counter at least that long and no higher than `max_failures`.
+#[cfg(feature = "ssr")]
+pub async fn block_throttle_key_with_pool(
+    throttle_key: &String,
+    blocked_until: DateTime<Utc>,
+    max_failures: Option<i64>,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    let query_res = sqlx::query!(
+        "UPDATE login_throttle SET blocked_until = MAX(COALESCE(blocked_until, ?), ?), \
+         expiry = MAX(expiry, ?), failures = MIN(failures, COALESCE(?, failures)) \
+         WHERE throttle_key = ?",
+        blocked_until,
+        blocked_until,
+        blocked_until,
+        max_failures,
+        throttle_key
+    )
+    .execute(pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when blocking login throttle key: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn clear_throttle_key_with_pool(
+    throttle_key: &String,
+    pool: &SqlitePool,
+) -> Result<(), DatabaseError> {
+    match sqlx::query!(
+        "DELETE FROM login_throttle WHERE throttle_key = ?",
+        throttle_key
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("database error when clearing login throttle key: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use secrecy::SecretString;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+struct TotpRow {
+    secret: String,
+    confirmed: bool,
+    last_used_step: i64,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug)]
+pub struct StoredTotp {
+    pub secret: SecretString,
+    pub confirmed: bool,
+    pub last_used_step: i64,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_totp(user_id: Uuid) -> Result<Option<StoredTotp>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_totp");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        TotpRow,
+        r#"SELECT secret, confirmed AS "confirmed: bool", last_used_step FROM user_totp WHERE user_id = ?"#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(row) => Ok(Some(StoredTotp {
+            secret: SecretString::from(row.secret),
+            confirmed: row.confirmed,
+            last_used_step: row.last_used_step,
+        })),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("retrieve_totp: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn totp_enabled(user_id: Uuid) -> Result<bool, DatabaseError> {
+    Ok(matches!(
+        retrieve_totp(user_id).await?,
+        Some(StoredTotp {
+            confirmed: true,
+            ..
+        })
+    ))
+}
+
+#[cfg(feature = "ssr")]
+pub async fn store_pending_totp(user_id: Uuid, secret: &String) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in store_pending_totp");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    // a confirmed secret is never replaced here, it has to be removed first
+    let query_res = sqlx::query!(
+        "INSERT INTO user_totp (user_id, secret, confirmed, last_used_step) VALUES (?, ?, ?, ?) \
+         ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, last_used_step = 0 \
+         WHERE confirmed = false",
+        user_id,
+        secret,
+        false,
+        0,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                log::debug!(
+                    "store_pending_totp: rows_affected: {} for {user_id}",
+                    val.rows_affected()
+                );
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing pending totp secret: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn confirm_totp(user_id: Uuid, step: i64) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in confirm_totp");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE user_totp SET confirmed = true, last_used_step = ? \
+         WHERE user_id = ? AND confirmed = false",
+        step,
+        user_id,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when confirming totp: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+/// Records the time step of an accepted code. The update only succeeds when the step is newer
+/// than the last one used, so a code can never be accepted twice.
+#[cfg(feature = "ssr")]
+pub async fn consume_totp_step(user_id: Uuid, step: i64) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in consume_totp_step");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE user_totp SET last_used_step = ? WHERE user_id = ? AND last_used_step < ?",
+        step,
+        user_id,
+        step,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when consuming totp step: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn delete_totp(user_id: Uuid) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in delete_totp");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!("DELETE FROM user_totp WHERE user_id = ?", user_id)
+        .execute(&pool)
+        .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when deleting totp: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn create_login_challenge(
+    challenge_id: &String,
+    user_id: Uuid,
+    expire_time: DateTime<Utc>,
+    remember: bool,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in create_login_challenge");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "INSERT INTO login_challenges (challenge_id, user_id, expiry, attempts, remember) \
+         VALUES (?, ?, ?, ?, ?)",
+        challenge_id,
+        user_id,
+        expire_time,
+        0,
+        remember,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when creating login challenge: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct LoginChallenge {
+    pub user_id: Uuid,
+    pub expiry: DateTime<Utc>,
+    /// whether the session started once the challenge is passed is remembered
+    pub remember: bool,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn retrieve_login_challenge(
+    challenge_id: &String,
+) -> Result<Option<LoginChallenge>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in retrieve_login_challenge");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    if challenge_id.is_empty() {
+        return Ok(None);
+    }
+    let row = sqlx::query_as!(
+        LoginChallenge,
+        r#"SELECT user_id AS "user_id: Uuid", expiry AS "expiry: DateTime<Utc>", remember FROM login_challenges WHERE challenge_id = ?"#,
+        challenge_id
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(challenge) => Ok(Some(challenge)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("retrieve_login_challenge: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+/// Counts an attempt at a login challenge before its code is checked, so concurrent guesses
+/// can't all slip in under `max_attempts`. Returns false without counting once the attempts
+/// are used up.
+#[cfg(feature = "ssr")]
+pub async fn count_challenge_attempt(
+    challenge_id: &String,
+    max_attempts: i64,
+) -> Result<bool, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in count_challenge_attempt");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE login_challenges SET attempts = attempts + 1 \
+         WHERE challenge_id = ? AND attempts < ?",
+        challenge_id,
+        max_attempts
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => Ok(val.rows_affected() == 1),
+        Err(e) => {
+            log::error!("database error when counting challenge attempt: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn drop_login_challenge(challenge_id: &String) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in drop_login_challenge");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let remove_res = sqlx::query!(
+        "DELETE FROM login_challenges WHERE challenge_id = ?",
+        challenge_id
+    )
+    .execute(&pool)
+    .await;
+    match remove_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                log::debug!(
+                    "removal of login challenge failed, rows_affected: {}",
+                    val.rows_affected()
+                );
+                return Err(DatabaseError::IncorrectRowsAffected);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("removal of login challenge from database failed: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
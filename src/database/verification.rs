@@ -0,0 +1,189 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::forget_cached_user;
+    use crate::defs::DatabaseError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct EmailStatus {
+    pub email: String,
+    pub verified: bool,
+}
+
+#[cfg(feature = "ssr")]
+pub async fn email_status(user_id: Uuid) -> Result<EmailStatus, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in email_status");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        EmailStatus,
+        "SELECT email, verified FROM users WHERE user_id = ?",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(status) => Ok(status),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => {
+                log::error!("database lookup for email on id {user_id} did not exist");
+                Err(DatabaseError::NoEntries)
+            }
+            _ => {
+                log::error!("database lookup for email on id {user_id} failed: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn email_verified_with_pool(
+    user_id: Uuid,
+    pool: SqlitePool,
+) -> Result<bool, DatabaseError> {
+    let row = sqlx::query!("SELECT verified FROM users WHERE user_id = ?", user_id)
+        .fetch_one(&pool)
+        .await;
+    match row {
+        Ok(res) => Ok(res.verified),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Err(DatabaseError::NoEntries),
+            _ => {
+                log::error!("email_verified_with_pool: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+/// Stores a verification token for `email`, replacing any earlier token so only the most
+/// recently sent link works.
+#[cfg(feature = "ssr")]
+pub async fn replace_verification_token(
+    user_id: Uuid,
+    token_hash: &String,
+    email: &String,
+    expire_time: DateTime<Utc>,
+) -> Result<(), DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in replace_verification_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    if let Err(e) = sqlx::query!(
+        "DELETE FROM email_verification_tokens WHERE user_id = ?",
+        user_id
+    )
+    .execute(&pool)
+    .await
+    {
+        log::error!("database error when dropping old verification tokens: {e}");
+        return Err(DatabaseError::QueryFailed);
+    }
+    let query_res = sqlx::query!(
+        "INSERT INTO email_verification_tokens (token_hash, user_id, email, expiry) \
+         VALUES (?, ?, ?, ?)",
+        token_hash,
+        user_id,
+        email,
+        expire_time,
+    )
+    .execute(&pool)
+    .await;
+    match query_res {
+        Ok(val) => {
+            if val.rows_affected() != 1 {
+                Err(DatabaseError::IncorrectRowsAffected)
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => {
+            log::error!("database error when storing verification token: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct VerificationToken {
+    pub user_id: Uuid,
+    pub email: String,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Removes and returns a verification token so it can only be used once.
+#[cfg(feature = "ssr")]
+pub async fn take_verification_token(
+    token_hash: &String,
+) -> Result<Option<VerificationToken>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in take_verification_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let row = sqlx::query_as!(
+        VerificationToken,
+        r#"DELETE FROM email_verification_tokens WHERE token_hash = ?
+        RETURNING user_id AS "user_id: Uuid", email, expiry AS "expiry: DateTime<Utc>""#,
+        token_hash
+    )
+    .fetch_one(&pool)
+    .await;
+    match row {
+        Ok(token) => Ok(Some(token)),
+        Err(e) => match e {
+            sqlx::Error::RowNotFound => Ok(None),
+            _ => {
+                log::error!("take_verification_token: sqlx error: {e}");
+                Err(DatabaseError::QueryFailed)
+            }
+        },
+    }
+}
+
+/// Marks the account verified, as long as `email` is still the address on the account.
+#[cfg(feature = "ssr")]
+pub async fn mark_email_verified(
+    user_id: Uuid,
+    email: &String,
+) -> Result<bool, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in mark_email_verified");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let query_res = sqlx::query!(
+        "UPDATE users SET verified = TRUE WHERE user_id = ? AND email = ?",
+        user_id,
+        email,
+    )
+    .execute(&pool)
+    .await;
+    forget_cached_user(user_id);
+    match query_res {
+        Ok(val) => Ok(val.rows_affected() == 1),
+        Err(e) => {
+            log::error!("database error when marking email verified: {e}");
+            Err(DatabaseError::QueryFailed)
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use const_format::formatcp;
 //use std::fmt;
 
+// site name shown to users by authenticators
+pub const SITE_NAME: &str = "Auth-Sessions-Example";
 // site domain name
 pub const SITE_DOMAIN: &str = dotenvy_macro::dotenv!("SITE_DOMAIN");
 // site websocket location
@@ -37,6 +39,296 @@ pub const PASSWORD_MAX_LEN_STR: &str = formatcp!("{PASSWORD_MAX_LEN}");
 pub const PASSWORD_MIN_LEN: usize = 15;
 pub const PASSWORD_MIN_LEN_STR: &str = formatcp!("{PASSWORD_MIN_LEN}");
 
+/// Lowest zxcvbn strength score a new password must reach, out of 4
+pub const PASSWORD_MIN_SCORE: u8 = 3;
+
+/// Issuer shown by authenticator apps for TOTP entries
+pub const TOTP_ISSUER: &str = SITE_NAME;
+
+/// Number of digits in a TOTP code
+pub const TOTP_CODE_LEN: usize = 6;
+pub const TOTP_CODE_LEN_STR: &str = formatcp!("{TOTP_CODE_LEN}");
+
+/// Seconds a login may wait between the password and the second factor
+pub const LOGIN_CHALLENGE_DURATION_SECS: i64 = 300;
+
+/// Wrong second factor codes allowed before the login has to start over
+pub const LOGIN_CHALLENGE_MAX_ATTEMPTS: i64 = 5;
+
+/// Single-use recovery codes issued alongside a second factor
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Characters in a recovery code, not counting the dash in the middle
+pub const RECOVERY_CODE_LEN: usize = 10;
+
+/// Characters recovery codes are made of, without look-alikes such as 0, o, 1 and l
+pub const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Argon2 memory cost of recovery code hashes. A code is random and long enough to need far
+/// less than a password, and each one given is checked against every unused code.
+pub const RECOVERY_CODE_HASH_MEMORY_KIB: u32 = 1_024;
+
+/// Argon2 iterations of recovery code hashes
+pub const RECOVERY_CODE_HASH_ITERATIONS: u32 = 1;
+
+/// Longest code the second factor form accepts, a recovery code with its dash
+pub const SECOND_FACTOR_CODE_MAX_LEN_STR: &str = formatcp!("{}", RECOVERY_CODE_LEN + 1);
+
+/// Longest id an OpenID Connect provider can be configured with, it prefixes generated usernames
+pub const OIDC_PROVIDER_ID_MAX_LEN: usize = 16;
+
+/// Seconds a browser has to come back from an OpenID Connect provider
+pub const OIDC_FLOW_DURATION_SECS: i64 = 600;
+
+/// Seconds a first time OpenID Connect user has to choose a display name
+pub const OIDC_SIGNUP_DURATION_SECS: i64 = 1_800;
+
+/// Seconds a provider's discovery document and signing keys are used before being fetched again
+pub const OIDC_METADATA_REFRESH_SECS: u64 = 3_600;
+
+/// Seconds a browser has to answer a passkey registration or login challenge
+pub const PASSKEY_CHALLENGE_DURATION_SECS: i64 = 300;
+
+/// Passkey name max length limit
+pub const PASSKEY_NAME_MAX_LEN: usize = 32;
+pub const PASSKEY_NAME_MAX_LEN_STR: &str = formatcp!("{PASSKEY_NAME_MAX_LEN}");
+
+/// Personal access token name max length limit
+pub const API_TOKEN_NAME_MAX_LEN: usize = 32;
+pub const API_TOKEN_NAME_MAX_LEN_STR: &str = formatcp!("{API_TOKEN_NAME_MAX_LEN}");
+
+/// Most personal access tokens an account can have at once
+pub const API_TOKEN_MAX_PER_USER: i64 = 20;
+
+/// Days a new personal access token can be made to last
+pub const API_TOKEN_LIFETIMES_DAYS: [i64; 4] = [7, 30, 90, 365];
+
+/// Start of every personal access token, so secret scanners can recognize leaked ones
+pub const API_TOKEN_PREFIX: &str = "pat_";
+
+/// Seconds an email verification link stays valid
+pub const EMAIL_VERIFICATION_DURATION_SECS: i64 = 86_400;
+
+/// Seconds a password reset link stays valid
+pub const PASSWORD_RESET_DURATION_SECS: i64 = 3_600;
+
+/// Seconds an emailed login link stays valid
+pub const MAGIC_LINK_DURATION_SECS: i64 = 900;
+
+/// Seconds between updates of a session's last seen time
+pub const SESSION_LAST_SEEN_INTERVAL_SECS: i64 = 300;
+
+/// Longest user agent stored for a session
+pub const SESSION_USER_AGENT_MAX_LEN: usize = 256;
+
+/// Seconds the previous id of a rotated session keeps working, for requests already in flight
+pub const SESSION_ROTATION_GRACE_SECS: i64 = 60;
+
+/// Failed logins allowed for a username from one IP address before each further attempt
+/// from there is delayed
+pub const LOGIN_THROTTLE_USERNAME_FREE_FAILURES: i64 = 3;
+
+/// Failed logins allowed for a username from one IP address before that address is locked
+/// out of it
+pub const LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES: i64 = 10;
+
+/// Failed logins allowed for a username from anywhere before each further attempt is
+/// delayed. A username is never locked out, so nobody can keep an account locked.
+pub const LOGIN_THROTTLE_ACCOUNT_FREE_FAILURES: i64 = 20;
+
+/// Longest delay in seconds for a username from anywhere
+pub const LOGIN_THROTTLE_ACCOUNT_MAX_DELAY_SECS: i64 = 60;
+
+/// Failed logins allowed from an IP address before each further attempt is delayed
+pub const LOGIN_THROTTLE_IP_FREE_FAILURES: i64 = 10;
+
+/// Failed logins allowed from an IP address before it is locked out
+pub const LOGIN_THROTTLE_IP_LOCKOUT_FAILURES: i64 = 50;
+
+/// Seconds of the first delay after the free failures, doubling with every further failure
+pub const LOGIN_THROTTLE_BASE_DELAY_SECS: i64 = 1;
+
+/// Longest delay in seconds before a lockout
+pub const LOGIN_THROTTLE_MAX_DELAY_SECS: i64 = 300;
+
+/// Seconds a lockout lasts
+pub const LOGIN_LOCKOUT_DURATION_SECS: i64 = 900;
+
+/// Seconds without a failed login after which the failures are forgotten
+pub const LOGIN_THROTTLE_WINDOW_SECS: i64 = 86_400;
+
+/// Seconds a browser keeps the anti-bot id used for rate limiting
+pub const ANTIBOT_COOKIE_MAX_AGE_SECS: i64 = 31_536_000;
+
+/// Most rate limiter buckets kept in memory at once
+pub const RATE_LIMIT_MAX_BUCKETS: usize = 100_000;
+
+/// Seconds a signup proof-of-work challenge can be solved in
+pub const POW_CHALLENGE_DURATION_SECS: i64 = 600;
+
+/// Leading zero bits a solution needs while signups are quiet
+pub const POW_BASE_DIFFICULTY: u32 = 16;
+
+/// Most leading zero bits a solution ever needs
+pub const POW_MAX_DIFFICULTY: u32 = 24;
+
+/// Nonces the browser tries between letting the page handle events
+pub const POW_SOLVE_CHUNK: u64 = 4_096;
+
+/// Seconds of signup attempts the difficulty is based on
+pub const POW_VOLUME_WINDOW_SECS: i64 = 3_600;
+
+/// Signup attempts within the window before every doubling adds a bit of difficulty
+pub const POW_VOLUME_THRESHOLD: i64 = 20;
+
+/// Seconds between purges of expired sessions and tokens
+pub const REAPER_INTERVAL_SECS: u64 = 600;
+
+/// Sessions and users the session cache holds at most of each, by default
+pub const SESSION_CACHE_DEFAULT_CAPACITY: usize = 10_000;
+
+/// Seconds a cached session or user is trusted, by default
+pub const SESSION_CACHE_DEFAULT_TTL_SECS: u64 = 30;
+
+/// Most rows the reaper deletes from a table in one statement
+pub const REAPER_BATCH_SIZE: i64 = 500;
+
+/// Previous CSRF keys that tokens are still accepted with after a rotation
+pub const CSRF_PREVIOUS_KEYS_KEPT: usize = 1;
+
+/// Seconds between checks of the CSRF key file for a rotation by another instance
+pub const CSRF_KEY_FILE_CHECK_SECS: u64 = 10;
+
+/// Seconds a CSRF token is accepted after it was issued, shorter than the default session
+/// rotation interval so a session bound token outlives at most one rotation
+pub const CSRF_TOKEN_MAX_AGE_SECS: i64 = 43_200;
+
+/// Seconds a CSRF token may appear to be issued in the future, for clock differences
+/// between instances
+pub const CSRF_TOKEN_CLOCK_SKEW_SECS: i64 = 60;
+
+/// The form a CSRF token was issued for. A token is only accepted by the server function
+/// of its own form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CsrfPurpose {
+    Signup,
+    Login,
+    SecondFactor,
+    ForgotPassword,
+    ResetPassword,
+    /// asking for an emailed login link
+    RequestMagicLink,
+    /// logging in with an emailed login link
+    MagicLinkLogin,
+    VerifyEmail,
+    ResendVerification,
+    ChangePassword,
+    RevokeSession,
+    RevokeOtherSessions,
+    EnrollTotp,
+    ConfirmTotp,
+    DisableTotp,
+    RegenerateRecoveryCodes,
+    /// starting and finishing a passkey login
+    PasskeyLogin,
+    /// starting and finishing the registration of a passkey
+    PasskeyRegistration,
+    RemovePasskey,
+    RotateCsrfKey,
+    CreateApiToken,
+    RevokeApiToken,
+    /// leaving for an OpenID Connect provider
+    OidcLogin,
+    /// choosing a display name after the first OpenID Connect login
+    OidcSignup,
+    /// replacing a username that looks like another account's
+    ChooseUsername,
+}
+
+impl CsrfPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CsrfPurpose::Signup => "signup",
+            CsrfPurpose::Login => "login",
+            CsrfPurpose::SecondFactor => "second-factor",
+            CsrfPurpose::ForgotPassword => "forgot-password",
+            CsrfPurpose::ResetPassword => "reset-password",
+            CsrfPurpose::RequestMagicLink => "request-magic-link",
+            CsrfPurpose::MagicLinkLogin => "magic-link-login",
+            CsrfPurpose::VerifyEmail => "verify-email",
+            CsrfPurpose::ResendVerification => "resend-verification",
+            CsrfPurpose::ChangePassword => "change-password",
+            CsrfPurpose::RevokeSession => "revoke-session",
+            CsrfPurpose::RevokeOtherSessions => "revoke-other-sessions",
+            CsrfPurpose::EnrollTotp => "enroll-totp",
+            CsrfPurpose::ConfirmTotp => "confirm-totp",
+            CsrfPurpose::DisableTotp => "disable-totp",
+            CsrfPurpose::RegenerateRecoveryCodes => "regenerate-recovery-codes",
+            CsrfPurpose::PasskeyLogin => "passkey-login",
+            CsrfPurpose::PasskeyRegistration => "passkey-registration",
+            CsrfPurpose::RemovePasskey => "remove-passkey",
+            CsrfPurpose::RotateCsrfKey => "rotate-csrf-key",
+            CsrfPurpose::CreateApiToken => "create-api-token",
+            CsrfPurpose::RevokeApiToken => "revoke-api-token",
+            CsrfPurpose::OidcLogin => "oidc-login",
+            CsrfPurpose::OidcSignup => "oidc-signup",
+            CsrfPurpose::ChooseUsername => "choose-username",
+        }
+    }
+
+    /// Whether a request authenticated by a personal access token may skip the CSRF check for
+    /// this purpose. Signing in and managing credentials or sessions always needs a browser.
+    pub fn accepts_bearer(&self) -> bool {
+        matches!(
+            self,
+            CsrfPurpose::VerifyEmail | CsrfPurpose::ResendVerification
+        )
+    }
+}
+
+/// What a personal access token may be used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ApiTokenScope {
+    /// server functions that read the account, through `validate_session`
+    Read,
+    /// server functions that change the account and accept a token instead of a CSRF token,
+    /// see `CsrfPurpose::accepts_bearer`
+    Write,
+    /// the `/ws` websocket
+    Websocket,
+}
+
+impl ApiTokenScope {
+    pub const ALL: [ApiTokenScope; 3] = [
+        ApiTokenScope::Read,
+        ApiTokenScope::Write,
+        ApiTokenScope::Websocket,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiTokenScope::Read => "read",
+            ApiTokenScope::Write => "write",
+            ApiTokenScope::Websocket => "ws",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        ApiTokenScope::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == scope)
+    }
+}
+
+/// Names reserved when no NAME_POLICY_FILE is set
+pub const DEFAULT_RESERVED_NAMES: [&str; 4] = ["admin", "administrator", "root", "system"];
+
+/// Seconds between checks of the name policy file for changes
+pub const NAME_POLICY_CHECK_SECS: u64 = 10;
+
+/// Shortest password pepper key accepted
+pub const PEPPER_MIN_BYTES: usize = 16;
+
 use cfg_if::cfg_if;
 
 cfg_if! {
@@ -45,10 +337,207 @@ cfg_if! {
         use sqlx::SqlitePool;
         use axum::extract::FromRef;
         use leptos_axum::AxumRouteListing;
+        use std::sync::Arc;
+        use webauthn_rs::Webauthn;
+        use crate::mail::Mailer;
+        use crate::websocket::SessionSockets;
+        use crate::rate_limit::RateLimiter;
+        use crate::security::csrf_keys::CsrfKeys;
+        use crate::security::session_cookie::SessionCookieKeys;
+        use crate::session_cache::SessionCache;
+        use crate::security::name_policy::NamePolicy;
+        use crate::security::oidc::OidcProviders;
 
         #[derive(Debug, Clone, Copy)]
         pub struct ServerVars {
-            pub csrf_server: u128,
+            pub unverified_policy: UnverifiedPolicy,
+            pub session_policy: SessionPolicy,
+            pub session_store: SessionStore,
+            pub email_policy: EmailPolicy,
+            pub pow_secret: u128,
+        }
+
+        /// How email addresses are compared when checking that each is used only once
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct EmailPolicy {
+            /// treat "alice+news@example.com" as "alice@example.com", set with
+            /// EMAIL_STRIP_PLUS_TAGS
+            pub strip_plus_tags: bool,
+        }
+
+        /// How long sessions last, set with the SESSION_* variables
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct SessionPolicy {
+            /// longest a "remember me" session lasts no matter how active it is
+            pub remember_max: chrono::Duration,
+            /// longest any other session lasts
+            pub short_max: chrono::Duration,
+            /// a session that is not used for this long ends
+            pub idle_timeout: chrono::Duration,
+            /// how often an active session is given a new id
+            pub rotate_interval: chrono::Duration,
+        }
+
+        impl Default for SessionPolicy {
+            fn default() -> Self {
+                SessionPolicy {
+                    remember_max: chrono::Duration::days(30),
+                    short_max: chrono::Duration::hours(12),
+                    idle_timeout: chrono::Duration::days(7),
+                    rotate_interval: chrono::Duration::days(1),
+                }
+            }
+        }
+
+        impl SessionPolicy {
+            /// When a session created at `created` ends, no matter how active it is.
+            pub fn absolute_expiry(
+                &self,
+                created: chrono::DateTime<chrono::Utc>,
+                remember: bool,
+            ) -> chrono::DateTime<chrono::Utc> {
+                match remember {
+                    true => created + self.remember_max,
+                    false => created + self.short_max,
+                }
+            }
+
+            /// When a session used at `now` ends if it is not used again.
+            pub fn idle_expiry(
+                &self,
+                now: chrono::DateTime<chrono::Utc>,
+                absolute_expiry: chrono::DateTime<chrono::Utc>,
+            ) -> chrono::DateTime<chrono::Utc> {
+                std::cmp::min(now + self.idle_timeout, absolute_expiry)
+            }
+        }
+
+        /// Where sessions are kept, set with SESSION_STORE
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum SessionStore {
+            /// "database": the cookie holds a random id looked up in `active_sesssions`
+            Database,
+            /// "cookie": the cookie holds the encrypted session itself, only the user's
+            /// session generation and the revoked cookies are looked up
+            Cookie,
+        }
+
+        impl std::str::FromStr for SessionStore {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "database" => Ok(SessionStore::Database),
+                    "cookie" => Ok(SessionStore::Cookie),
+                    _ => Err(format!("{s} is not one of \"database\" or \"cookie\"")),
+                }
+            }
+        }
+
+        /// What an account may do before its email address is verified,
+        /// set with UNVERIFIED_ACCOUNTS
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum UnverifiedPolicy {
+            /// "allow": unverified accounts can do everything
+            Allow,
+            /// "no-websocket": unverified accounts can log in but cannot open /ws
+            NoWebsocket,
+            /// "deny": unverified accounts cannot log in
+            Deny,
+        }
+
+        impl std::str::FromStr for UnverifiedPolicy {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "allow" => Ok(UnverifiedPolicy::Allow),
+                    "no-websocket" => Ok(UnverifiedPolicy::NoWebsocket),
+                    "deny" => Ok(UnverifiedPolicy::Deny),
+                    _ => Err(format!(
+                        "{s} is not one of \"allow\", \"no-websocket\" or \"deny\""
+                    )),
+                }
+            }
+        }
+
+        /// Cost of new password hashes, set with the PASSWORD_HASH_* variables.
+        /// Stored hashes with other parameters are replaced at the next login.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct PasswordHashParams {
+            pub algorithm: argon2::Algorithm,
+            pub memory_kib: u32,
+            pub iterations: u32,
+            pub parallelism: u32,
+        }
+
+        impl Default for PasswordHashParams {
+            fn default() -> Self {
+                PasswordHashParams {
+                    algorithm: argon2::Algorithm::Argon2id,
+                    memory_kib: argon2::Params::DEFAULT_M_COST,
+                    iterations: argon2::Params::DEFAULT_T_COST,
+                    parallelism: argon2::Params::DEFAULT_P_COST,
+                }
+            }
+        }
+
+        /// A secret mixed into password hashes that is never stored in the database, set with
+        /// PASSWORD_PEPPERS or PASSWORD_PEPPER_FILE. Hashes record the version they were made
+        /// with, so old versions stay usable until every user has logged in again.
+        #[derive(Debug)]
+        pub struct Pepper {
+            pub version: u32,
+            pub key: secrecy::SecretSlice<u8>,
+        }
+
+        impl Pepper {
+            /// Parses peppers written as "version:base64key", separated by commas or lines.
+            pub fn parse_list(s: &str) -> Result<Vec<Pepper>, String> {
+                s.split([',', '\n'])
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(str::parse)
+                    .collect()
+            }
+        }
+
+        impl std::str::FromStr for Pepper {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let (version, key) = s
+                    .split_once(':')
+                    .ok_or("a pepper is not written as version:base64key".to_string())?;
+                let version = version
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("{version} is not a valid pepper version: {e}"))?;
+                let key = base64::Engine::decode(
+                    &base64::engine::general_purpose::STANDARD,
+                    key.trim(),
+                )
+                .map_err(|e| format!("pepper {version} is not valid base64: {e}"))?;
+                if key.len() < PEPPER_MIN_BYTES {
+                    return Err(format!(
+                        "pepper {version} is shorter than {PEPPER_MIN_BYTES} bytes"
+                    ));
+                }
+                Ok(Pepper {
+                    version,
+                    key: key.into(),
+                })
+            }
+        }
+
+        /// Usernames allowed to perform admin actions, set with ADMIN_USERNAMES
+        #[derive(Debug, Clone, Default)]
+        pub struct AdminUsers(pub Arc<Vec<String>>);
+
+        impl AdminUsers {
+            pub fn contains(&self, username: &str) -> bool {
+                self.0.iter().any(|admin| admin == username)
+            }
         }
 
         #[derive(FromRef, Debug, Clone)]
@@ -57,6 +546,16 @@ cfg_if! {
             pub pool: SqlitePool,
             pub routes: Vec<AxumRouteListing>,
             pub vars: ServerVars,
+            pub webauthn: Arc<Webauthn>,
+            pub mailer: Mailer,
+            pub sockets: SessionSockets,
+            pub limiter: RateLimiter,
+            pub csrf_keys: CsrfKeys,
+            pub session_keys: SessionCookieKeys,
+            pub session_cache: SessionCache,
+            pub admins: AdminUsers,
+            pub name_policy: NamePolicy,
+            pub oidc: OidcProviders,
         }
     }
 }
@@ -69,8 +568,20 @@ pub enum AppError {
     Login(LoginError),
     Database(DatabaseError),
     CSRF(CsrfError),
+    TwoFactor(TwoFactorError),
+    Passkey(PasskeyError),
+    ApiToken(ApiTokenError),
+    Verification(VerificationError),
+    Mail(MailError),
+    PasswordReset(PasswordResetError),
+    MagicLink(MagicLinkError),
+    RateLimit(RateLimitError),
+    ProofOfWork(ProofOfWorkError),
+    Admin(AdminError),
+    Oidc(OidcError),
     Argon2Failure,
     TokioFailure,
+    SessionCookieFailure,
 }
 
 #[cfg(feature = "ssr")]
@@ -112,6 +623,18 @@ pub enum RegistrationError {
     DisplayNameInvalidCharacters,
     UniqueUsername,
     UniqueDisplayName,
+    /// the password is on the breached password list
+    BreachedPassword,
+    /// the password is too easy to guess, with advice on how to improve it
+    WeakPassword(String),
+    /// the name is reserved by the name policy
+    ReservedName,
+    /// the name matches a pattern denied by the name policy
+    DeniedName,
+    /// the name contains a word on the profanity list of the name policy
+    ProfaneName,
+    /// a new username was chosen for an account that was not asked to choose one
+    RenameNotRequired,
 }
 
 #[cfg(feature = "ssr")]
@@ -134,6 +657,14 @@ pub enum CsrfError {
     MultipleCookies,
     NoMatchingCookie,
     ServerValMissing,
+    /// the token is older than `CSRF_TOKEN_MAX_AGE_SECS`
+    Expired,
+    /// the request came with a personal access token that is invalid or lacks the write scope
+    BearerTokenRejected,
+    /// the request came with a personal access token, which cannot be used for this purpose
+    BearerTokenNotAllowed,
+    /// the request came with a personal access token that could not be looked up
+    BearerTokenUnchecked,
 }
 
 #[cfg(feature = "ssr")]
@@ -177,6 +708,10 @@ impl From<DatabaseError> for ServerFnError {
 #[derive(Debug)]
 pub enum LoginError {
     IncorrectCredentials,
+    IncorrectPassword,
+    NotLoggedIn,
+    SessionNotFound,
+    Throttled,
 }
 
 #[cfg(feature = "ssr")]
@@ -193,6 +728,280 @@ impl From<LoginError> for ServerFnError {
     }
 }
 
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum TwoFactorError {
+    InvalidCode,
+    NotEnrolled,
+    AlreadyEnrolled,
+    ChallengeMissing,
+    SecretInvalid,
+    /// too many wrong codes were given to change two-factor settings
+    TooManyAttempts,
+}
+
+#[cfg(feature = "ssr")]
+impl From<TwoFactorError> for AppError {
+    fn from(item: TwoFactorError) -> Self {
+        AppError::TwoFactor(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<TwoFactorError> for ServerFnError {
+    fn from(item: TwoFactorError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+/// Account events that are written to the audit log
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditEvent {
+    RecoveryCodesGenerated,
+    RecoveryCodeUsed,
+}
+
+#[cfg(feature = "ssr")]
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::RecoveryCodesGenerated => "recovery-codes-generated",
+            AuditEvent::RecoveryCodeUsed => "recovery-code-used",
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum PasskeyError {
+    WebauthnMissing,
+    ChallengeMissing,
+    VerificationFailed,
+    AlreadyRegistered,
+    NameLength,
+    NotFound,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum ApiTokenError {
+    NameLength,
+    InvalidLifetime,
+    NoScopes,
+    TooMany,
+    NotFound,
+    /// tokens are only managed from a logged in browser, not with another token
+    SessionRequired,
+}
+
+#[cfg(feature = "ssr")]
+impl From<ApiTokenError> for AppError {
+    fn from(item: ApiTokenError) -> Self {
+        AppError::ApiToken(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<ApiTokenError> for ServerFnError {
+    fn from(item: ApiTokenError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<PasskeyError> for AppError {
+    fn from(item: PasskeyError) -> Self {
+        AppError::Passkey(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<PasskeyError> for ServerFnError {
+    fn from(item: PasskeyError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum VerificationError {
+    InvalidToken,
+    AlreadyVerified,
+    Unverified,
+}
+
+#[cfg(feature = "ssr")]
+impl From<VerificationError> for AppError {
+    fn from(item: VerificationError) -> Self {
+        AppError::Verification(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<VerificationError> for ServerFnError {
+    fn from(item: VerificationError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum MailError {
+    SenderMissing,
+    DeliveryFailed,
+}
+
+#[cfg(feature = "ssr")]
+impl From<MailError> for AppError {
+    fn from(item: MailError) -> Self {
+        AppError::Mail(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<MailError> for ServerFnError {
+    fn from(item: MailError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum PasswordResetError {
+    InvalidToken,
+}
+
+#[cfg(feature = "ssr")]
+impl From<PasswordResetError> for AppError {
+    fn from(item: PasswordResetError) -> Self {
+        AppError::PasswordReset(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<PasswordResetError> for ServerFnError {
+    fn from(item: PasswordResetError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum MagicLinkError {
+    /// unknown, used, expired or requested from another browser
+    InvalidLink,
+}
+
+#[cfg(feature = "ssr")]
+impl From<MagicLinkError> for AppError {
+    fn from(item: MagicLinkError) -> Self {
+        AppError::MagicLink(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<MagicLinkError> for ServerFnError {
+    fn from(item: MagicLinkError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum RateLimitError {
+    /// seconds until the request would be allowed
+    TooManyRequests(u64),
+}
+
+#[cfg(feature = "ssr")]
+impl From<RateLimitError> for AppError {
+    fn from(item: RateLimitError) -> Self {
+        AppError::RateLimit(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<RateLimitError> for ServerFnError {
+    fn from(item: RateLimitError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum ProofOfWorkError {
+    InvalidSolution,
+    Expired,
+}
+
+#[cfg(feature = "ssr")]
+impl From<ProofOfWorkError> for AppError {
+    fn from(item: ProofOfWorkError) -> Self {
+        AppError::ProofOfWork(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<ProofOfWorkError> for ServerFnError {
+    fn from(item: ProofOfWorkError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider,
+    ProviderUnavailable,
+    /// the login was not started by this browser, already finished or expired
+    FlowMissing,
+    /// the provider sent the browser back with an error instead of a code
+    Denied,
+    ExchangeFailed,
+    InvalidIdToken,
+    EmailMissing,
+    EmailTaken,
+    SignupMissing,
+}
+
+#[cfg(feature = "ssr")]
+impl From<OidcError> for AppError {
+    fn from(item: OidcError) -> Self {
+        AppError::Oidc(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<OidcError> for ServerFnError {
+    fn from(item: OidcError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub enum AdminError {
+    NotAdmin,
+    CsrfKeyRotation,
+    /// the csrf keys come from the environment or only live as long as the process
+    CsrfKeyFileMissing,
+}
+
+#[cfg(feature = "ssr")]
+impl From<AdminError> for AppError {
+    fn from(item: AdminError) -> Self {
+        AppError::Admin(item)
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<AdminError> for ServerFnError {
+    fn from(item: AdminError) -> Self {
+        ServerFnError::ServerError(format!("{}", item))
+    }
+}
+
 #[cfg(feature = "ssr")]
 impl std::fmt::Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -212,12 +1021,48 @@ impl std::fmt::Display for AppError {
             AppError::Database(x) => {
                 write!(f, "{}", x)
             }
+            AppError::TwoFactor(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::Passkey(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::ApiToken(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::Verification(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::Mail(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::PasswordReset(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::MagicLink(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::RateLimit(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::ProofOfWork(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::Admin(x) => {
+                write!(f, "{}", x)
+            }
+            AppError::Oidc(x) => {
+                write!(f, "{}", x)
+            }
             AppError::Argon2Failure => {
                 write!(f, "Internal Server Error")
             }
             AppError::TokioFailure => {
                 write!(f, "Internal Server Error")
             }
+            AppError::SessionCookieFailure => {
+                write!(f, "Internal Server Error")
+            }
         }
     }
 }
@@ -264,6 +1109,30 @@ impl std::fmt::Display for RegistrationError {
             RegistrationError::UniqueDisplayName => {
                 write!(f, "Display name is already taken.")
             }
+            RegistrationError::BreachedPassword => {
+                write!(
+                    f,
+                    "This password has appeared in a data breach, please choose another."
+                )
+            }
+            RegistrationError::WeakPassword(feedback) if feedback.is_empty() => {
+                write!(f, "Password is too easy to guess.")
+            }
+            RegistrationError::WeakPassword(feedback) => {
+                write!(f, "Password is too easy to guess. {feedback}")
+            }
+            RegistrationError::ReservedName => {
+                write!(f, "This name is reserved, please choose another.")
+            }
+            RegistrationError::DeniedName => {
+                write!(f, "This name is not allowed, please choose another.")
+            }
+            RegistrationError::ProfaneName => {
+                write!(f, "This name contains a word that is not allowed.")
+            }
+            RegistrationError::RenameNotRequired => {
+                write!(f, "This account does not need a new username.")
+            }
         }
     }
 }
@@ -281,6 +1150,27 @@ impl std::fmt::Display for CsrfError {
             CsrfError::ServerValMissing => {
                 write!(f, "Internal Server Error.")
             }
+            CsrfError::Expired => {
+                write!(
+                    f,
+                    "This form has expired, please reload the page and try again."
+                )
+            }
+            CsrfError::BearerTokenRejected => {
+                write!(
+                    f,
+                    "The access token is invalid, has expired or does not have the write scope."
+                )
+            }
+            CsrfError::BearerTokenNotAllowed => {
+                write!(f, "This can only be done from a logged in browser.")
+            }
+            CsrfError::BearerTokenUnchecked => {
+                write!(
+                    f,
+                    "The access token could not be checked, please try again in a few minutes."
+                )
+            }
         }
     }
 }
@@ -290,6 +1180,42 @@ impl std::fmt::Display for LoginError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LoginError::IncorrectCredentials => write!(f, "Login Request was invalid."),
+            LoginError::IncorrectPassword => write!(f, "The current password was incorrect."),
+            LoginError::NotLoggedIn => write!(f, "Please log in first."),
+            LoginError::SessionNotFound => write!(f, "That session has already ended."),
+            LoginError::Throttled => write!(
+                f,
+                "Too many failed login attempts, please wait before trying again."
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for TwoFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TwoFactorError::InvalidCode => {
+                write!(f, "The code was not valid, please try again.")
+            }
+            TwoFactorError::NotEnrolled => {
+                write!(
+                    f,
+                    "Two-factor authentication is not set up for this account."
+                )
+            }
+            TwoFactorError::AlreadyEnrolled => {
+                write!(f, "Two-factor authentication is already enabled.")
+            }
+            TwoFactorError::ChallengeMissing => {
+                write!(f, "Your login has expired, please log in again.")
+            }
+            TwoFactorError::SecretInvalid => {
+                write!(f, "Internal Server Error")
+            }
+            TwoFactorError::TooManyAttempts => {
+                write!(f, "Too many wrong codes, please wait before trying again.")
+            }
         }
     }
 }
@@ -313,3 +1239,209 @@ impl std::fmt::Display for DatabaseError {
         }
     }
 }
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for ApiTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiTokenError::NameLength => {
+                write!(
+                    f,
+                    "Token names must be 1 to {API_TOKEN_NAME_MAX_LEN} characters long."
+                )
+            }
+            ApiTokenError::InvalidLifetime => {
+                write!(f, "Please choose one of the offered token lifetimes.")
+            }
+            ApiTokenError::NoScopes => {
+                write!(f, "Please choose at least one scope for the token.")
+            }
+            ApiTokenError::TooMany => {
+                write!(
+                    f,
+                    "An account can have at most {API_TOKEN_MAX_PER_USER} access tokens, \
+                     please revoke one first."
+                )
+            }
+            ApiTokenError::NotFound => {
+                write!(f, "This access token no longer exists.")
+            }
+            ApiTokenError::SessionRequired => {
+                write!(
+                    f,
+                    "Access tokens can only be managed from a logged in browser."
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for PasskeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasskeyError::WebauthnMissing => {
+                write!(f, "Internal Server Error")
+            }
+            PasskeyError::ChallengeMissing => {
+                write!(f, "The passkey request expired, please try again.")
+            }
+            PasskeyError::VerificationFailed => {
+                write!(f, "The passkey could not be verified.")
+            }
+            PasskeyError::AlreadyRegistered => {
+                write!(f, "This passkey is already registered.")
+            }
+            PasskeyError::NameLength => {
+                write!(f, "Passkey name does not meet the length requirement.")
+            }
+            PasskeyError::NotFound => {
+                write!(f, "That passkey does not exist.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::InvalidToken => {
+                write!(f, "This verification link is invalid or has expired.")
+            }
+            VerificationError::AlreadyVerified => {
+                write!(f, "Your email address is already verified.")
+            }
+            VerificationError::Unverified => {
+                write!(f, "Please verify your email address first.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::SenderMissing => {
+                write!(f, "Internal Server Error")
+            }
+            MailError::DeliveryFailed => {
+                write!(f, "The email could not be sent, please try again later.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordResetError::InvalidToken => {
+                write!(f, "This reset link is invalid or has expired.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for MagicLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MagicLinkError::InvalidLink => {
+                write!(
+                    f,
+                    "This login link is invalid or has expired. Links only work in the browser \
+                     they were requested from."
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for ProofOfWorkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProofOfWorkError::InvalidSolution => {
+                write!(f, "The browser check failed, please try again.")
+            }
+            ProofOfWorkError::Expired => {
+                write!(f, "The browser check expired, please try again.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OidcError::UnknownProvider => {
+                write!(f, "This sign in provider is not available.")
+            }
+            OidcError::ProviderUnavailable => {
+                write!(
+                    f,
+                    "The sign in provider could not be reached, please try again later."
+                )
+            }
+            OidcError::FlowMissing => {
+                write!(f, "Your sign in has expired, please start again.")
+            }
+            OidcError::Denied => {
+                write!(f, "The sign in was cancelled or refused by the provider.")
+            }
+            OidcError::ExchangeFailed | OidcError::InvalidIdToken => {
+                write!(f, "The sign in could not be completed, please start again.")
+            }
+            OidcError::EmailMissing => {
+                write!(
+                    f,
+                    "The sign in provider did not share a verified email address."
+                )
+            }
+            OidcError::EmailTaken => {
+                write!(
+                    f,
+                    "An account with this email address already exists, please log in to it \
+                     with your password."
+                )
+            }
+            OidcError::SignupMissing => {
+                write!(f, "Your sign up has expired, please sign in again.")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::NotAdmin => {
+                write!(f, "Only administrators can do this.")
+            }
+            AdminError::CsrfKeyRotation => {
+                write!(f, "The form key could not be rotated, see the server log.")
+            }
+            AdminError::CsrfKeyFileMissing => {
+                write!(
+                    f,
+                    "The form key can only be rotated when it is kept in a CSRF_KEY_FILE."
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimitError::TooManyRequests(secs) => {
+                write!(f, "Too many requests, please try again in {secs} seconds.")
+            }
+        }
+    }
+}
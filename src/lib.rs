@@ -3,7 +3,11 @@ pub mod cookies;
 pub mod database;
 pub mod defs;
 pub mod fileserv;
+pub mod mail;
+pub mod rate_limit;
+pub mod reaper;
 pub mod security;
+pub mod session_cache;
 pub mod websocket;
 
 use cfg_if::cfg_if;
@@ -0,0 +1,111 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::MailError;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use std::{fs, io, path::PathBuf, sync::Arc};
+    use uuid::Uuid;
+}}
+
+/// A plain text email ready to be handed to a `MailSender`
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers mail on behalf of the server. Implementations may block, they are always called
+/// from a blocking task.
+#[cfg(feature = "ssr")]
+pub trait MailSender: std::fmt::Debug + Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+/// The mail sender shared through `AppState` and provided as context
+#[cfg(feature = "ssr")]
+pub type Mailer = Arc<dyn MailSender>;
+
+/// Writes every mail into a maildir on disk instead of sending it, so accounts can be
+/// verified without a mail server. Any maildir aware mail client can read the outbox.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct FileOutbox {
+    dir: PathBuf,
+    from: String,
+}
+
+#[cfg(feature = "ssr")]
+impl FileOutbox {
+    pub fn new(dir: PathBuf, from: String) -> Result<Self, io::Error> {
+        for sub_dir in ["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub_dir))?;
+        }
+        Ok(FileOutbox { dir, from })
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl MailSender for FileOutbox {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        // stop header injection through user supplied addresses
+        if [&mail.to, &mail.subject]
+            .iter()
+            .any(|header| header.contains(['\r', '\n']))
+        {
+            log::error!("refusing to write mail with a line break in its headers");
+            return Err(MailError::DeliveryFailed);
+        }
+        let now = Utc::now();
+        let id = Uuid::new_v4().simple();
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{id}@outbox>\r\n\
+             MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            self.from,
+            mail.to,
+            mail.subject,
+            now.to_rfc2822(),
+            mail.body.replace('\n', "\r\n"),
+        );
+        // maildir delivery: write into tmp then move into new, readers never see partial mail
+        let file_name = format!("{}.{id}.eml", now.timestamp());
+        let tmp_path = self.dir.join("tmp").join(&file_name);
+        let write = fs::write(&tmp_path, message)
+            .and_then(|_| fs::rename(&tmp_path, self.dir.join("new").join(&file_name)));
+        match write {
+            Ok(()) => {
+                log::trace!("mail to {} written to outbox as {file_name}", mail.to);
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("could not write mail to outbox: {e}");
+                Err(MailError::DeliveryFailed)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub async fn send_mail(mail: Mail) -> Result<(), MailError> {
+    let mailer = match use_context::<Mailer>() {
+        Some(mailer) => mailer,
+        None => {
+            log::error!("mailer not available in context");
+            return Err(MailError::SenderMissing);
+        }
+    };
+    send_mail_with_mailer(mail, mailer).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn send_mail_with_mailer(mail: Mail, mailer: Mailer) -> Result<(), MailError> {
+    match tokio::task::spawn_blocking(move || mailer.send(&mail)).await {
+        Ok(res) => res,
+        Err(tokio_err) => {
+            log::error!("failed to spawn blocking tokio task: {tokio_err}");
+            Err(MailError::DeliveryFailed)
+        }
+    }
+}
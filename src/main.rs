@@ -7,11 +7,25 @@ struct Ports {
 
 cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
     use auth_sessions_example::{
-        defs::{AppState, ServerVars},
+        defs::{
+            AdminUsers, AppState, EmailPolicy, PasswordHashParams, Pepper, ServerVars, SessionPolicy, SessionStore, UnverifiedPolicy, SITE_DOMAIN,
+            SESSION_CACHE_DEFAULT_CAPACITY, SESSION_CACHE_DEFAULT_TTL_SECS,
+        },
+        mail::FileOutbox,
         fileserv::file_and_error_handler,
         app::{App, shell},
-        websocket::axum_ws_handler,
-        security::gen_128bit,
+        database::{backfill_canonical_names_with_pool, normalize_emails_with_pool},
+        websocket::{axum_ws_handler, SessionSockets},
+        reaper::run_reaper,
+        rate_limit::{Limit, RateLimiter, RateLimits},
+        session_cache::SessionCache,
+        security::{
+            csrf_keys::{parse_key, CsrfKeys}, gen_128bit, name_policy::NamePolicy, init_password_hashing, passkeys::build_webauthn,
+            proof_of_work::pow_secret_from_file,
+            session_cookie::SessionCookieKeys,
+            oidc::{OidcProvider, OidcProviderConfig, OidcProviders},
+            password_policy::load_breached_passwords,
+        },
     };
     use axum::{
         extract::{Host, Path, ConnectInfo, State},
@@ -25,7 +39,8 @@ cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
     use axum_server::tls_rustls::RustlsConfig;
     use leptos::prelude::*;
     use leptos_axum::{handle_server_fns_with_context, generate_route_list, LeptosRoutes};
-    use std::{env, net::SocketAddr, path::PathBuf};
+    use std::{env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+    use tokio::sync::watch;
     use sqlx::sqlite::SqlitePoolOptions;
     use tower_http::compression::CompressionLayer;
 }}
@@ -122,20 +137,106 @@ async fn main() {
         .run(&pool)
         .await
         .expect("could not run SQLx migrations");
+    backfill_canonical_names_with_pool(&pool)
+        .await
+        .expect("could not fill in canonical names");
+    let email_policy = EmailPolicy {
+        strip_plus_tags: match env::var("EMAIL_STRIP_PLUS_TAGS") {
+            Ok(strip) => strip.parse().expect("verify EMAIL_STRIP_PLUS_TAGS value"),
+            Err(_) => false,
+        },
+    };
+    normalize_emails_with_pool(&pool, email_policy)
+        .await
+        .expect("could not normalize email addresses");
     println!("sqlite up");
 
     log::info!("Server process starting");
     log::info!("Server {:#?}", leptos_options);
 
+    println!("setting up mail outbox");
+    let mail_outbox = env::var("MAIL_OUTBOX").unwrap_or(String::from("outbox"));
+    let mail_from = env::var("MAIL_FROM").unwrap_or(format!(
+        "no-reply@{}",
+        SITE_DOMAIN.split(':').next().unwrap_or(SITE_DOMAIN)
+    ));
+    let mailer = FileOutbox::new(PathBuf::from(&mail_outbox), mail_from)
+        .expect("verify MAIL_OUTBOX value");
+    println!("mail will be written to the {mail_outbox} maildir");
+
+    let unverified_policy: UnverifiedPolicy = match env::var("UNVERIFIED_ACCOUNTS") {
+        Ok(policy) => policy.parse().expect("verify UNVERIFIED_ACCOUNTS value"),
+        Err(_) => UnverifiedPolicy::Allow,
+    };
+
+    let session_policy = session_policy_from_env();
+    let session_store: SessionStore = match env::var("SESSION_STORE") {
+        Ok(store) => store.parse().expect("verify SESSION_STORE value"),
+        Err(_) => SessionStore::Database,
+    };
+    let session_keys = session_cookie_keys_from_env(session_store);
+
+    println!("preparing password hashing");
+    let password_hash_params = password_hash_params_from_env();
+    init_password_hashing(password_hash_params, peppers_from_env())
+        .expect("verify PASSWORD_HASH_* and pepper values");
+    println!("password hashes use {password_hash_params:?}");
+    match env::var("BREACHED_PASSWORDS_FILE") {
+        Ok(path) => {
+            let count = load_breached_passwords(&PathBuf::from(&path))
+                .expect("verify BREACHED_PASSWORDS_FILE value");
+            println!("loaded {count} breached password hashes from {path}");
+        }
+        Err(_) => {
+            println!("BREACHED_PASSWORDS_FILE not set, breached passwords are not rejected")
+        }
+    }
+
     let app_state = AppState {
         leptos_options,
         pool,
         routes: routes.clone(),
         vars: ServerVars {
-            csrf_server: gen_128bit(),
+            pow_secret: pow_secret_from_env(),
+            unverified_policy,
+            session_policy,
+            session_store,
+            email_policy,
+        },
+        webauthn: Arc::new(build_webauthn()),
+        mailer: Arc::new(mailer),
+        sockets: SessionSockets::default(),
+        limiter: RateLimiter::new(rate_limits_from_env()),
+        csrf_keys: csrf_keys_from_env(),
+        session_keys,
+        session_cache: session_cache_from_env(),
+        name_policy: match env::var("NAME_POLICY_FILE") {
+            Ok(path) => NamePolicy::from_file(PathBuf::from(path))
+                .unwrap_or_else(|e| panic!("verify NAME_POLICY_FILE value: {e}")),
+            Err(_) => NamePolicy::defaults(),
         },
+        admins: AdminUsers(Arc::new(
+            env::var("ADMIN_USERNAMES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|username| !username.is_empty())
+                .map(String::from)
+                .collect(),
+        )),
+        oidc: oidc_providers_from_env(),
     };
 
+    // spawn the reaper of expired sessions and tokens
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let reaper = tokio::spawn(run_reaper(
+        app_state.pool.clone(),
+        app_state.sockets.clone(),
+        app_state.limiter.clone(),
+        app_state.session_cache.clone(),
+        shutdown_rx,
+    ));
+
     // build our application with a route
     let app = Router::new()
         .route("/api/*fn_name", post(server_fn_handler))
@@ -148,13 +249,24 @@ async fn main() {
     // spawn a redirect http to https
     tokio::spawn(redirect_http_to_https(ports));
 
+    // stop accepting connections on ctrl-c or SIGTERM
+    let handle = axum_server::Handle::new();
+    tokio::spawn(shutdown_signal(handle.clone()));
+
     // run app with axum_server::bind_rustls for TLS
     log::info!("listening on https://{}", &addr_https);
     axum_server::bind_rustls(addr_https, rustls_config)
+        .handle(handle)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 
+    let _ = shutdown_tx.send(true);
+    if let Err(e) = reaper.await {
+        log::error!("reaper task failed: {e}");
+    }
+    log::info!("server shut down");
+
     //axum::serve(
     //    tokio::net::TcpListener::bind(addr).await.unwrap(),
     //    redirect.into_make_service(),
@@ -163,6 +275,249 @@ async fn main() {
     //.unwrap();
 }
 
+/// Waits for ctrl-c or SIGTERM, then lets open connections finish before the server stops.
+#[cfg(feature = "ssr")]
+async fn shutdown_signal(handle: axum_server::Handle) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    log::info!("shutdown signal received");
+    handle.graceful_shutdown(Some(Duration::from_secs(10)));
+}
+
+/// Reads the SESSION_* variables in seconds, keeping the default for any that are unset.
+#[cfg(feature = "ssr")]
+fn session_policy_from_env() -> SessionPolicy {
+    let secs = |name: &str, default: chrono::Duration| match env::var(name) {
+        Ok(secs) => chrono::Duration::seconds(
+            secs.parse()
+                .unwrap_or_else(|_| panic!("verify {name} value")),
+        ),
+        Err(_) => default,
+    };
+    let default = SessionPolicy::default();
+    SessionPolicy {
+        remember_max: secs("SESSION_REMEMBER_MAX_SECS", default.remember_max),
+        short_max: secs("SESSION_SHORT_MAX_SECS", default.short_max),
+        idle_timeout: secs("SESSION_IDLE_TIMEOUT_SECS", default.idle_timeout),
+        rotate_interval: secs("SESSION_ROTATE_INTERVAL_SECS", default.rotate_interval),
+    }
+}
+
+/// Reads the PASSWORD_HASH_* variables, keeping the default for any that are unset.
+#[cfg(feature = "ssr")]
+fn password_hash_params_from_env() -> PasswordHashParams {
+    let cost = |name: &str, default: u32| match env::var(name) {
+        Ok(cost) => cost
+            .parse()
+            .unwrap_or_else(|e| panic!("verify {name} value: {e}")),
+        Err(_) => default,
+    };
+    let default = PasswordHashParams::default();
+    PasswordHashParams {
+        algorithm: match env::var("PASSWORD_HASH_ALGORITHM") {
+            Ok(algorithm) => algorithm
+                .parse()
+                .unwrap_or_else(|e| panic!("verify PASSWORD_HASH_ALGORITHM value: {e}")),
+            Err(_) => default.algorithm,
+        },
+        memory_kib: cost("PASSWORD_HASH_MEMORY_KIB", default.memory_kib),
+        iterations: cost("PASSWORD_HASH_ITERATIONS", default.iterations),
+        parallelism: cost("PASSWORD_HASH_PARALLELISM", default.parallelism),
+    }
+}
+
+/// Reads the password peppers from PASSWORD_PEPPERS, or else from the file named by
+/// PASSWORD_PEPPER_FILE. Without either passwords are hashed without a pepper.
+#[cfg(feature = "ssr")]
+fn peppers_from_env() -> Vec<Pepper> {
+    let peppers = match (
+        env::var("PASSWORD_PEPPERS"),
+        env::var("PASSWORD_PEPPER_FILE"),
+    ) {
+        (Ok(peppers), _) => peppers,
+        (Err(_), Ok(path)) => std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("could not read PASSWORD_PEPPER_FILE {path}: {e}")),
+        (Err(_), Err(_)) => return Vec::new(),
+    };
+    Pepper::parse_list(&peppers).unwrap_or_else(|e| panic!("verify password peppers: {e}"))
+}
+
+/// Reads the CSRF keys from CSRF_KEY_FILE, or else from CSRF_SECRET and
+/// CSRF_PREVIOUS_SECRETS. Without either the keys change at every start.
+#[cfg(feature = "ssr")]
+fn csrf_keys_from_env() -> CsrfKeys {
+    if let Ok(path) = env::var("CSRF_KEY_FILE") {
+        return CsrfKeys::from_file(PathBuf::from(path))
+            .unwrap_or_else(|e| panic!("verify CSRF_KEY_FILE value: {e}"));
+    }
+    match env::var("CSRF_SECRET") {
+        Ok(current) => {
+            let previous = env::var("CSRF_PREVIOUS_SECRETS").unwrap_or_default();
+            let previous: Vec<&str> = previous
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .collect();
+            CsrfKeys::from_secrets(&current, &previous)
+                .unwrap_or_else(|e| panic!("verify CSRF_SECRET values: {e}"))
+        }
+        Err(_) => {
+            println!("CSRF_KEY_FILE and CSRF_SECRET not set, forms break at every restart");
+            CsrfKeys::ephemeral()
+        }
+    }
+}
+
+/// Reads the key signup challenges are signed with from POW_KEY_FILE or POW_SECRET.
+#[cfg(feature = "ssr")]
+fn pow_secret_from_env() -> u128 {
+    if let Ok(path) = env::var("POW_KEY_FILE") {
+        return pow_secret_from_file(&PathBuf::from(path))
+            .unwrap_or_else(|e| panic!("verify POW_KEY_FILE value: {e}"));
+    }
+    match env::var("POW_SECRET") {
+        Ok(secret) => {
+            parse_key(&secret).unwrap_or_else(|e| panic!("verify POW_SECRET value: {e}"))
+        }
+        Err(_) => {
+            println!(
+                "POW_KEY_FILE and POW_SECRET not set, signup challenges break at every restart"
+            );
+            gen_128bit()
+        }
+    }
+}
+
+/// Reads SESSION_CACHE_CAPACITY and SESSION_CACHE_TTL_SECS, keeping the default for any that
+/// are unset.
+#[cfg(feature = "ssr")]
+fn session_cache_from_env() -> SessionCache {
+    let capacity = match env::var("SESSION_CACHE_CAPACITY") {
+        Ok(capacity) => capacity
+            .parse()
+            .expect("verify SESSION_CACHE_CAPACITY value"),
+        Err(_) => SESSION_CACHE_DEFAULT_CAPACITY,
+    };
+    let ttl_secs = match env::var("SESSION_CACHE_TTL_SECS") {
+        Ok(secs) => secs.parse().expect("verify SESSION_CACHE_TTL_SECS value"),
+        Err(_) => SESSION_CACHE_DEFAULT_TTL_SECS,
+    };
+    SessionCache::new(capacity, Duration::from_secs(ttl_secs))
+}
+
+/// Reads the keys encrypted session cookies are sealed with from SESSION_COOKIE_SECRET and
+/// SESSION_COOKIE_PREVIOUS_SECRETS.
+#[cfg(feature = "ssr")]
+fn session_cookie_keys_from_env(store: SessionStore) -> SessionCookieKeys {
+    match env::var("SESSION_COOKIE_SECRET") {
+        Ok(current) => {
+            let previous = env::var("SESSION_COOKIE_PREVIOUS_SECRETS").unwrap_or_default();
+            let previous: Vec<&str> = previous
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .collect();
+            SessionCookieKeys::from_secrets(&current, &previous)
+                .unwrap_or_else(|e| panic!("verify SESSION_COOKIE_SECRET values: {e}"))
+        }
+        Err(_) => {
+            if store == SessionStore::Cookie {
+                println!(
+                    "SESSION_COOKIE_SECRET not set, everyone is logged out at every restart"
+                );
+            }
+            SessionCookieKeys::ephemeral()
+        }
+    }
+}
+
+/// Reads the providers listed in OIDC_PROVIDERS, each set with OIDC_<ID>_NAME, _ISSUER,
+/// _CLIENT_ID and _CLIENT_SECRET. Providers send the browser back to /oidc/<id>/callback.
+#[cfg(feature = "ssr")]
+fn oidc_providers_from_env() -> OidcProviders {
+    let ids = env::var("OIDC_PROVIDERS").unwrap_or_default();
+    let providers = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            let var = |field: &str| {
+                let name = format!("OIDC_{}_{field}", id.to_ascii_uppercase());
+                env::var(&name).unwrap_or_else(|_| panic!("{name} not set"))
+            };
+            OidcProvider::new(OidcProviderConfig {
+                id: id.to_string(),
+                name: var("NAME"),
+                issuer: var("ISSUER"),
+                client_id: var("CLIENT_ID"),
+                client_secret: var("CLIENT_SECRET").into(),
+                redirect_url: format!("https://{SITE_DOMAIN}/oidc/{id}/callback"),
+            })
+            .unwrap_or_else(|e| panic!("verify OIDC_PROVIDERS values: {e}"))
+        })
+        .collect();
+    OidcProviders(Arc::new(providers))
+}
+
+/// Reads the RATE_LIMIT_* variables written as "requests/seconds", keeping the default for any
+/// that are unset.
+#[cfg(feature = "ssr")]
+fn rate_limits_from_env() -> RateLimits {
+    let limit = |name: &str, default: Limit| match env::var(name) {
+        Ok(limit) => limit
+            .parse()
+            .unwrap_or_else(|e| panic!("verify {name} value: {e}")),
+        Err(_) => default,
+    };
+    let mut limits = RateLimits::default();
+    limits.csrf.per_browser = limit("RATE_LIMIT_CSRF_BROWSER", limits.csrf.per_browser);
+    limits.csrf.per_ip = limit("RATE_LIMIT_CSRF_IP", limits.csrf.per_ip);
+    limits.signup.per_browser = limit("RATE_LIMIT_SIGNUP_BROWSER", limits.signup.per_browser);
+    limits.signup.per_ip = limit("RATE_LIMIT_SIGNUP_IP", limits.signup.per_ip);
+    limits.username_check.per_browser = limit(
+        "RATE_LIMIT_USERNAME_CHECK_BROWSER",
+        limits.username_check.per_browser,
+    );
+    limits.username_check.per_ip =
+        limit("RATE_LIMIT_USERNAME_CHECK_IP", limits.username_check.per_ip);
+    limits.password_reset.per_browser = limit(
+        "RATE_LIMIT_PASSWORD_RESET_BROWSER",
+        limits.password_reset.per_browser,
+    );
+    limits.password_reset.per_ip =
+        limit("RATE_LIMIT_PASSWORD_RESET_IP", limits.password_reset.per_ip);
+    limits.magic_link.per_browser = limit(
+        "RATE_LIMIT_MAGIC_LINK_BROWSER",
+        limits.magic_link.per_browser,
+    );
+    limits.magic_link.per_ip = limit("RATE_LIMIT_MAGIC_LINK_IP", limits.magic_link.per_ip);
+    limits.resend_verification.per_browser = limit(
+        "RATE_LIMIT_RESEND_VERIFICATION_BROWSER",
+        limits.resend_verification.per_browser,
+    );
+    limits.resend_verification.per_ip = limit(
+        "RATE_LIMIT_RESEND_VERIFICATION_IP",
+        limits.resend_verification.per_ip,
+    );
+    limits
+}
+
 #[cfg(feature = "ssr")]
 async fn leptos_routes_handler(
     State(app_state): State<AppState>,
@@ -177,6 +532,16 @@ async fn leptos_routes_handler(
         move || {
             provide_context(cloned_app_state.pool.clone());
             provide_context(cloned_app_state.vars);
+            provide_context(cloned_app_state.webauthn.clone());
+            provide_context(cloned_app_state.mailer.clone());
+            provide_context(cloned_app_state.sockets.clone());
+            provide_context(cloned_app_state.limiter.clone());
+            provide_context(cloned_app_state.csrf_keys.clone());
+            provide_context(cloned_app_state.session_keys.clone());
+            provide_context(cloned_app_state.session_cache.clone());
+            provide_context(cloned_app_state.admins.clone());
+            provide_context(cloned_app_state.name_policy.clone());
+            provide_context(cloned_app_state.oidc.clone());
             provide_context(connect_info);
             provide_context(cloned_app_state.leptos_options.clone());
         },
@@ -211,7 +576,17 @@ async fn server_fn_handler(
     handle_server_fns_with_context(
         move || {
             provide_context(app_state.pool.clone());
-            provide_context(app_state.vars.clone());
+            provide_context(app_state.vars);
+            provide_context(app_state.webauthn.clone());
+            provide_context(app_state.mailer.clone());
+            provide_context(app_state.sockets.clone());
+            provide_context(app_state.limiter.clone());
+            provide_context(app_state.csrf_keys.clone());
+            provide_context(app_state.session_keys.clone());
+            provide_context(app_state.session_cache.clone());
+            provide_context(app_state.admins.clone());
+            provide_context(app_state.name_policy.clone());
+            provide_context(app_state.oidc.clone());
             provide_context(connect_info);
             provide_context(app_state.leptos_options.clone());
         },
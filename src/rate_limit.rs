@@ -0,0 +1,446 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::request_antibot_id;
+    use crate::defs::{AppError, RateLimitError, RATE_LIMIT_MAX_BUCKETS};
+    use axum::extract::ConnectInfo;
+    use leptos::prelude::*;
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+}}
+
+/// Requests that are limited to slow down bots and username enumeration
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LimitedAction {
+    /// loading a form, every page with a form issues a csrf token
+    Csrf,
+    Signup,
+    /// checking whether a username is taken
+    UsernameCheck,
+    /// asking for a password reset mail
+    PasswordReset,
+    /// asking for a login link mail
+    MagicLink,
+    /// sending the verification mail again
+    ResendVerification,
+}
+
+/// At most `requests` requests every `per`, refilled evenly over that time.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+#[cfg(feature = "ssr")]
+impl Limit {
+    pub const fn new(requests: u32, per_secs: u64) -> Self {
+        Limit {
+            requests,
+            per: Duration::from_secs(per_secs),
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.requests) / self.per.as_secs_f64().max(1.0)
+    }
+}
+
+/// Parses limits written as "requests/seconds", such as "10/1500".
+#[cfg(feature = "ssr")]
+impl std::str::FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, per_secs) = s
+            .split_once('/')
+            .ok_or(format!("{s} is not written as requests/seconds"))?;
+        let requests = requests
+            .trim()
+            .parse()
+            .map_err(|e| format!("{s} has an invalid request count: {e}"))?;
+        let per_secs = per_secs
+            .trim()
+            .parse()
+            .map_err(|e| format!("{s} has an invalid number of seconds: {e}"))?;
+        Ok(Limit::new(requests, per_secs))
+    }
+}
+
+/// The limits of one action. A request is only allowed while both its browser and its
+/// address are within their limit.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionLimits {
+    pub per_browser: Limit,
+    /// looser than `per_browser`, many browsers can share an address
+    pub per_ip: Limit,
+}
+
+/// Limits of every `LimitedAction`, set with the RATE_LIMIT_* variables
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    pub csrf: ActionLimits,
+    pub signup: ActionLimits,
+    pub username_check: ActionLimits,
+    pub password_reset: ActionLimits,
+    pub magic_link: ActionLimits,
+    pub resend_verification: ActionLimits,
+}
+
+#[cfg(feature = "ssr")]
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            csrf: ActionLimits {
+                // a page issues one token for every form on it
+                per_browser: Limit::new(300, 60),
+                per_ip: Limit::new(1_200, 60),
+            },
+            signup: ActionLimits {
+                per_browser: Limit::new(5, 3_600),
+                per_ip: Limit::new(20, 3_600),
+            },
+            username_check: ActionLimits {
+                per_browser: Limit::new(10, 1_500),
+                per_ip: Limit::new(50, 1_500),
+            },
+            password_reset: ActionLimits {
+                per_browser: Limit::new(5, 3_600),
+                per_ip: Limit::new(20, 3_600),
+            },
+            magic_link: ActionLimits {
+                per_browser: Limit::new(5, 3_600),
+                per_ip: Limit::new(20, 3_600),
+            },
+            resend_verification: ActionLimits {
+                per_browser: Limit::new(5, 3_600),
+                per_ip: Limit::new(20, 3_600),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl RateLimits {
+    pub fn for_action(&self, action: LimitedAction) -> ActionLimits {
+        match action {
+            LimitedAction::Csrf => self.csrf,
+            LimitedAction::Signup => self.signup,
+            LimitedAction::UsernameCheck => self.username_check,
+            LimitedAction::PasswordReset => self.password_reset,
+            LimitedAction::MagicLink => self.magic_link,
+            LimitedAction::ResendVerification => self.resend_verification,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+#[cfg(feature = "ssr")]
+impl TokenBucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(limit.requests),
+            capacity: f64::from(limit.requests),
+            refill_per_sec: limit.refill_per_sec(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How full the bucket is, from 0 to 1.
+    fn fill(&self) -> f64 {
+        match self.capacity > 0.0 {
+            true => self.tokens / self.capacity,
+            false => 1.0,
+        }
+    }
+
+    /// Seconds until a whole token is available.
+    fn wait_secs(&self) -> u64 {
+        match self.tokens >= 1.0 {
+            true => 0,
+            false => ((1.0 - self.tokens) / self.refill_per_sec).ceil() as u64,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+type Buckets = HashMap<(LimitedAction, String), TokenBucket>;
+
+/// Forgets buckets that refilled completely, they behave exactly like new ones.
+#[cfg(feature = "ssr")]
+fn prune_buckets(buckets: &mut Buckets, now: Instant) -> usize {
+    let before = buckets.len();
+    buckets.retain(|_, bucket| {
+        bucket.refill(now);
+        bucket.tokens < bucket.capacity
+    });
+    before - buckets.len()
+}
+
+/// Token buckets of every limited browser and address, shared through `AppState` and provided
+/// as context. Buckets only live in memory, a restart forgives everyone.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    capacity: usize,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[cfg(feature = "ssr")]
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter::with_capacity(limits, RATE_LIMIT_MAX_BUCKETS)
+    }
+
+    /// A limiter keeping at most `capacity` buckets.
+    pub fn with_capacity(limits: RateLimits, capacity: usize) -> Self {
+        RateLimiter {
+            limits,
+            capacity,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Takes a token for `action` from the bucket of every key, or from none of them.
+    /// Fails with the seconds until every bucket has a token again.
+    pub fn check(&self, action: LimitedAction, keys: &[(String, Limit)]) -> Result<(), u64> {
+        self.check_at(action, keys, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        action: LimitedAction,
+        keys: &[(String, Limit)],
+        now: Instant,
+    ) -> Result<(), u64> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("rate limiter lock to not be poisoned");
+        let mut wait_secs = 0;
+        for (key, limit) in keys {
+            // unseen keys get a bucket only once a request is let through
+            let bucket_wait = match buckets.get_mut(&(action, key.clone())) {
+                Some(bucket) => {
+                    bucket.refill(now);
+                    bucket.wait_secs()
+                }
+                None => TokenBucket::new(*limit, now).wait_secs(),
+            };
+            wait_secs = wait_secs.max(bucket_wait);
+        }
+        if wait_secs > 0 {
+            return Err(wait_secs);
+        }
+        for (key, limit) in keys {
+            let key = (action, key.clone());
+            if !buckets.contains_key(&key) {
+                self.make_room(&mut buckets, now);
+            }
+            buckets
+                .entry(key)
+                .or_insert_with(|| TokenBucket::new(*limit, now))
+                .tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Makes room for one more bucket by dropping refilled buckets and then the fullest one,
+    /// which is the closest to being forgotten anyway.
+    fn make_room(&self, buckets: &mut Buckets, now: Instant) {
+        if buckets.len() < self.capacity {
+            return;
+        }
+        prune_buckets(buckets, now);
+        if buckets.len() < self.capacity {
+            return;
+        }
+        let fullest = buckets
+            .iter()
+            .max_by(|(_, a), (_, b)| a.fill().total_cmp(&b.fill()))
+            .map(|(key, _)| key.clone());
+        if let Some(fullest) = fullest {
+            log::warn!("rate limiter full, forgetting the bucket of {fullest:?}");
+            buckets.remove(&fullest);
+        }
+    }
+
+    /// Forgets buckets that refilled completely, they behave exactly like new ones.
+    pub fn prune(&self) -> usize {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("rate limiter lock to not be poisoned");
+        prune_buckets(&mut buckets, Instant::now())
+    }
+}
+
+/// Counts the current request against the limits of `action` for its browser and address.
+/// The browser is identified by its `__Host-antibot` cookie, which a bot can drop at will,
+/// so the address limit is what ultimately holds.
+#[cfg(feature = "ssr")]
+pub fn rate_limit(action: LimitedAction) -> Result<(), AppError> {
+    let limiter = match use_context::<RateLimiter>() {
+        Some(limiter) => limiter,
+        None => {
+            log::error!("rate limiter not available in context");
+            return Ok(());
+        }
+    };
+    let limits = limiter.limits.for_action(action);
+    let mut keys = Vec::with_capacity(2);
+    if let Some(ConnectInfo(addr)) = use_context::<ConnectInfo<SocketAddr>>() {
+        keys.push((format!("ip:{}", addr.ip()), limits.per_ip));
+    }
+    if let Some(antibot_id) = request_antibot_id() {
+        keys.push((format!("browser:{antibot_id}"), limits.per_browser));
+    }
+    match limiter.check(action, &keys) {
+        Ok(()) => Ok(()),
+        Err(wait_secs) => {
+            log::debug!("{action:?} rate limited for {keys:?}");
+            Err(RateLimitError::TooManyRequests(wait_secs).into())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn limiter(limit: Limit, capacity: usize) -> (RateLimiter, ActionLimits) {
+        let limits = ActionLimits {
+            per_browser: limit,
+            per_ip: limit,
+        };
+        let rate_limits = RateLimits {
+            csrf: limits,
+            signup: limits,
+            username_check: limits,
+            password_reset: limits,
+            magic_link: limits,
+            resend_verification: limits,
+        };
+        (RateLimiter::with_capacity(rate_limits, capacity), limits)
+    }
+
+    fn bucket_count(limiter: &RateLimiter) -> usize {
+        limiter.buckets.lock().unwrap().len()
+    }
+
+    #[test]
+    fn parses_requests_per_seconds() {
+        assert_eq!("10/1500".parse(), Ok(Limit::new(10, 1_500)));
+        assert_eq!(" 5 / 60 ".parse(), Ok(Limit::new(5, 60)));
+    }
+
+    #[test]
+    fn refuses_malformed_limits() {
+        for limit in [
+            "", "10", "10/", "/60", "ten/60", "10/sixty", "-1/60", "10/60/2",
+        ] {
+            assert!(limit.parse::<Limit>().is_err(), "{limit} parsed");
+        }
+    }
+
+    #[test]
+    fn bucket_refills_evenly_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Limit::new(10, 100), start);
+        bucket.tokens = 0.0;
+        assert_eq!(bucket.wait_secs(), 10);
+        bucket.refill(start + secs(25));
+        assert!((bucket.tokens - 2.5).abs() < 1e-9);
+        assert_eq!(bucket.wait_secs(), 0);
+        bucket.refill(start + secs(10_000));
+        assert_eq!(bucket.tokens, 10.0);
+    }
+
+    #[test]
+    fn refuses_once_the_bucket_is_empty() {
+        let (limiter, limits) = limiter(Limit::new(2, 60), 100);
+        let keys = [("ip:192.0.2.1".to_string(), limits.per_ip)];
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(LimitedAction::Signup, &keys, now), Ok(()));
+        assert_eq!(limiter.check_at(LimitedAction::Signup, &keys, now), Ok(()));
+        assert_eq!(limiter.check_at(LimitedAction::Signup, &keys, now), Err(30));
+        assert_eq!(
+            limiter.check_at(LimitedAction::Signup, &keys, now + secs(30)),
+            Ok(())
+        );
+        // every action has its own bucket
+        assert_eq!(limiter.check_at(LimitedAction::Csrf, &keys, now), Ok(()));
+    }
+
+    #[test]
+    fn refused_request_takes_no_token_and_stores_no_bucket() {
+        let (limiter, limits) = limiter(Limit::new(1, 60), 100);
+        let ip = ("ip:192.0.2.1".to_string(), limits.per_ip);
+        let now = Instant::now();
+        assert_eq!(
+            limiter.check_at(LimitedAction::Signup, std::slice::from_ref(&ip), now),
+            Ok(())
+        );
+        for n in 0..10 {
+            let browser = (format!("browser:{n}"), limits.per_browser);
+            let keys = [ip.clone(), browser];
+            assert!(limiter.check_at(LimitedAction::Signup, &keys, now).is_err());
+        }
+        assert_eq!(bucket_count(&limiter), 1);
+    }
+
+    #[test]
+    fn buckets_never_exceed_the_capacity() {
+        let (limiter, limits) = limiter(Limit::new(5, 60), 3);
+        let now = Instant::now();
+        for n in 0..10 {
+            let keys = [(format!("browser:{n}"), limits.per_browser)];
+            assert_eq!(limiter.check_at(LimitedAction::Signup, &keys, now), Ok(()));
+            assert!(bucket_count(&limiter) <= 3);
+        }
+    }
+
+    #[test]
+    fn full_limiter_forgets_the_fullest_bucket() {
+        let (limiter, limits) = limiter(Limit::new(5, 60), 2);
+        let busy = [("browser:busy".to_string(), limits.per_browser)];
+        let quiet = [("browser:quiet".to_string(), limits.per_browser)];
+        let new = [("browser:new".to_string(), limits.per_browser)];
+        let now = Instant::now();
+        for _ in 0..5 {
+            limiter.check_at(LimitedAction::Signup, &busy, now).unwrap();
+        }
+        limiter
+            .check_at(LimitedAction::Signup, &quiet, now)
+            .unwrap();
+        limiter.check_at(LimitedAction::Signup, &new, now).unwrap();
+        assert!(limiter.check_at(LimitedAction::Signup, &busy, now).is_err());
+    }
+}
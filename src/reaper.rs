@@ -0,0 +1,93 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::reaper::{
+        purge_expired_sessions_with_pool, purge_expired_with_pool, ExpiringTable,
+    };
+    use crate::defs::{DatabaseError, REAPER_BATCH_SIZE, REAPER_INTERVAL_SECS};
+    use crate::rate_limit::RateLimiter;
+    use crate::session_cache::SessionCache;
+    use crate::websocket::SessionSockets;
+    use chrono::prelude::*;
+    use sqlx::SqlitePool;
+    use std::time::Duration;
+    use tokio::sync::watch;
+}}
+
+/// Deletes expired sessions and tokens every `REAPER_INTERVAL_SECS` until `shutdown` changes,
+/// and forgets rate limiter buckets that refilled and session cache entries that expired.
+/// Rows are otherwise only removed when someone presents them again.
+#[cfg(feature = "ssr")]
+pub async fn run_reaper(
+    pool: SqlitePool,
+    sockets: SessionSockets,
+    limiter: RateLimiter,
+    session_cache: SessionCache,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(REAPER_INTERVAL_SECS));
+    // a slow purge should not be followed by a burst of catch up runs
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        if let Err(e) = reap_expired(&pool, &sockets, &shutdown).await {
+            log::error!("reaper run failed: {e}");
+        }
+        log_purged("rate limiter buckets", limiter.prune() as u64);
+        log_purged("session cache", session_cache.prune() as u64);
+        log::debug!("session cache: {:?}", session_cache.stats());
+    }
+    log::info!("reaper shut down");
+}
+
+/// One run of the reaper, deleting in batches of `REAPER_BATCH_SIZE` so the database is never
+/// locked for long. Stops between batches once `shutdown` changes.
+#[cfg(feature = "ssr")]
+async fn reap_expired(
+    pool: &SqlitePool,
+    sockets: &SessionSockets,
+    shutdown: &watch::Receiver<bool>,
+) -> Result<(), DatabaseError> {
+    let now = Utc::now();
+    let mut sessions = 0;
+    loop {
+        let session_ids =
+            purge_expired_sessions_with_pool(pool, now, REAPER_BATCH_SIZE).await?;
+        for session_id in &session_ids {
+            sockets.revoke(session_id);
+        }
+        sessions += session_ids.len();
+        if session_ids.len() < REAPER_BATCH_SIZE as usize || shutdown_requested(shutdown) {
+            break;
+        }
+    }
+    log_purged("active_sesssions", sessions as u64);
+    for table in ExpiringTable::ALL {
+        let mut purged = 0;
+        loop {
+            let deleted = purge_expired_with_pool(pool, table, now, REAPER_BATCH_SIZE).await?;
+            purged += deleted;
+            if deleted < REAPER_BATCH_SIZE as u64 || shutdown_requested(shutdown) {
+                break;
+            }
+        }
+        log_purged(table.name(), purged);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+fn shutdown_requested(shutdown: &watch::Receiver<bool>) -> bool {
+    *shutdown.borrow()
+}
+
+#[cfg(feature = "ssr")]
+fn log_purged(table: &str, count: u64) {
+    match count {
+        0 => log::debug!("reaper purged nothing from {table}"),
+        _ => log::info!("reaper purged {count} expired rows from {table}"),
+    }
+}
@@ -1,9 +1,42 @@
 use cfg_if::cfg_if;
 
+pub mod admin;
+pub mod api_tokens;
+pub mod audit;
+pub mod canonical;
+pub mod csrf_keys;
+pub mod magic_link;
+pub mod name_policy;
+pub mod oidc;
+pub mod passkeys;
+pub mod password_policy;
+pub mod password_reset;
+pub mod proof_of_work;
+pub mod recovery_codes;
+pub mod session_cookie;
+pub mod sessions;
+pub mod throttle;
+pub mod two_factor;
+pub mod verification;
+pub mod watched_file;
+
 cfg_if! { if #[cfg(feature = "ssr")] {
-    use crate::cookies::get_cookie_value;
-    use crate::database::{register_user, unique_cred_check, retrieve_credentials, UniqueCredential};
+    use crate::cookies::{
+        end_other_sessions, ensure_antibot_cookie, get_cookie_value,
+        parse_session_id_req_parts, request_session_id, require_session,
+    };
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::database::{
+        email_owner, previous_session_id, register_user, rename_user, unique_cred_check,
+        retrieve_credentials, retrieve_password_hash, two_factor::totp_enabled,
+        update_password_hash, username_for_id, UniqueCredential,
+    };
     use crate::defs::*;
+    use crate::security::canonical::canonical_email;
+    use crate::security::csrf_keys::CsrfKeys;
+    use chrono::prelude::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
     use argon2::{
         password_hash::{PasswordVerifier, SaltString},
         Argon2, PasswordHash, PasswordHasher,
@@ -18,41 +51,222 @@ cfg_if! { if #[cfg(feature = "ssr")] {
     use http::request::Parts;
     use secrecy::{ExposeSecret, SecretString};
     use std::str::FromStr;
+    use std::sync::OnceLock;
     use uuid::Uuid;
 }}
 
+/// Issues a CSRF token for `purpose`, signed with the current key over the `__Host-csrf`
+/// cookie and, while one is sent, the session cookie.
 #[cfg(feature = "ssr")]
-pub fn generate_csrf() -> String {
+pub fn generate_csrf(purpose: CsrfPurpose) -> String {
     let response = match use_context::<leptos_axum::ResponseOptions>() {
         Some(ro) => ro,
         None => return String::default(),
     };
-    let csrf_cookie = gen_128bit_base64();
-    let csrf_server = match use_context::<ServerVars>() {
-        Some(data) => stringify_u128_base64(data.csrf_server),
+    let csrf_server = match use_context::<CsrfKeys>() {
+        Some(keys) => keys.current(),
         None => return String::default(),
     };
-    response.append_header(
-        SET_COOKIE,
-        HeaderValue::from_str(
-            format!("__Host-csrf={csrf_cookie}; Secure; SameSite=Lax; HttpOnly; Path=/")
-                .as_str(),
+    ensure_antibot_cookie();
+    // every form on a page must be checked against the same cookie, so an existing cookie is
+    // reused instead of replacing it each time a CSRFField loads
+    let csrf_cookie = match existing_csrf_cookie(&response) {
+        Some(cookie) => cookie,
+        None => {
+            let csrf_cookie = gen_128bit_base64();
+            response.append_header(
+                SET_COOKIE,
+                HeaderValue::from_str(
+                    format!(
+                        "__Host-csrf={csrf_cookie}; Secure; SameSite=Lax; HttpOnly; Path=/"
+                    )
+                    .as_str(),
+                )
+                .expect("to create header value"),
+            );
+            log::trace!("provided a csrf cookie");
+            csrf_cookie
+        }
+    };
+    let session_id = request_session_id();
+    let session_id = (!session_id.is_empty()).then_some(session_id.as_str());
+    let issued = Utc::now().timestamp();
+    let mac = csrf_mac(&csrf_server, purpose, issued, &csrf_cookie, session_id)
+        .finalize()
+        .into_bytes();
+    let binding = match session_id {
+        Some(_) => "s",
+        None => "u",
+    };
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    format!(
+        "{issued}.{binding}.{}",
+        base64::Engine::encode(&CUSTOM_ENGINE, mac)
+    )
+}
+
+/// The MAC of a CSRF token, over everything the token is only valid for.
+#[cfg(feature = "ssr")]
+fn csrf_mac(
+    key: &str,
+    purpose: CsrfPurpose,
+    issued: i64,
+    csrf_cookie: &str,
+    session_id: Option<&str>,
+) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes())
+        .expect("hmac to accept keys of any length");
+    mac.update(
+        format!(
+            "csrf-v1|{}|{issued}|{csrf_cookie}|{}",
+            purpose.as_str(),
+            session_id.unwrap_or_default()
         )
-        .expect("to create header value"),
+        .as_bytes(),
     );
-    log::trace!("provided a csrf cookie");
-    gen_easy_hash(csrf_cookie, csrf_server)
+    mac
+}
+
+#[cfg(feature = "ssr")]
+fn existing_csrf_cookie(response: &leptos_axum::ResponseOptions) -> Option<String> {
+    // a cookie already issued while building this response
+    for set_cookie in response.0.read().headers.get_all(SET_COOKIE).iter() {
+        if let Some(csrf_cookie) = set_cookie
+            .to_str()
+            .ok()
+            .and_then(|cookie| get_cookie_value(cookie, "__Host-csrf"))
+        {
+            return Some(csrf_cookie);
+        }
+    }
+    // otherwise the cookie sent by the browser, unless it sent more than one
+    let http_req = use_context::<Parts>()?;
+    let mut csrf_cookie = None;
+    for headercookie in http_req.headers.get_all(COOKIE).iter() {
+        if let Some(cookie) = headercookie
+            .to_str()
+            .ok()
+            .and_then(|cookie| get_cookie_value(cookie, "__Host-csrf"))
+        {
+            if csrf_cookie.is_some() {
+                return None;
+            }
+            csrf_cookie = Some(cookie);
+        }
+    }
+    csrf_cookie
+}
+
+/// Validates the CSRF token of a server function against the current request.
+#[cfg(feature = "ssr")]
+pub async fn validate_csrf_request(
+    csrf: String,
+    purpose: CsrfPurpose,
+) -> Result<(), AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("validate_csrf_request: could not retrieve RequestParts");
+            return Err(RouterError::HTTPRequestMissing.into());
+        }
+        Some(rp) => rp,
+    };
+    Ok(validate_csrf(http_req, csrf, purpose).await?)
 }
 
 #[cfg(feature = "ssr")]
-pub fn validate_csrf(req: Parts, csrf_token: String) -> Result<(), CsrfError> {
-    let csrf_server = match use_context::<ServerVars>() {
-        Some(data) => stringify_u128_base64(data.csrf_server),
+pub async fn validate_csrf(
+    req: Parts,
+    csrf_token: String,
+    purpose: CsrfPurpose,
+) -> Result<(), CsrfError> {
+    // a cross site page cannot attach an Authorization header, so a request authenticated by
+    // a personal access token needs no CSRF token, only the write scope. Anything a leaked
+    // token could use to take over the account is refused.
+    if let Some(token) = api_tokens::bearer_token(&req.headers) {
+        if !purpose.accepts_bearer() {
+            log::trace!("access token used for {purpose:?}");
+            return Err(CsrfError::BearerTokenNotAllowed);
+        }
+        return match api_tokens::authenticate_api_token(&token, ApiTokenScope::Write).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err(CsrfError::BearerTokenRejected),
+            Err(e) => {
+                log::error!("could not check access token: {e}");
+                Err(CsrfError::BearerTokenUnchecked)
+            }
+        };
+    }
+    let csrf_keys = match use_context::<CsrfKeys>() {
+        Some(keys) => keys.accepted(),
         None => {
-            log::error!("could not retrieve servervars");
+            log::error!("could not retrieve csrf keys");
             return Err(CsrfError::ServerValMissing);
         }
     };
+    let cookie_value = request_csrf_cookie(&req)?;
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    let mut fields = csrf_token.splitn(3, '.');
+    let (issued, bound, mac) = match (
+        fields.next().and_then(|issued| issued.parse::<i64>().ok()),
+        fields.next(),
+        fields
+            .next()
+            .and_then(|mac| base64::Engine::decode(&CUSTOM_ENGINE, mac).ok()),
+    ) {
+        (Some(issued), Some("s"), Some(mac)) => (issued, true, mac),
+        (Some(issued), Some("u"), Some(mac)) => (issued, false, mac),
+        _ => {
+            log::trace!("csrf token is malformed");
+            return Err(CsrfError::NoMatchingCookie);
+        }
+    };
+    let session_id = match bound {
+        true => Some(parse_session_id_req_parts(req)),
+        false => None,
+    };
+    // tokens issued before a key rotation are signed with a previous key, the MAC is
+    // compared in constant time
+    let verifies = |session_id: Option<&str>| {
+        csrf_keys.iter().any(|csrf_server| {
+            csrf_mac(csrf_server, purpose, issued, &cookie_value, session_id)
+                .verify_slice(&mac)
+                .is_ok()
+        })
+    };
+    let mut valid = verifies(session_id.as_deref());
+    // the session may have been given a new id since the token was issued
+    if !valid {
+        if let Some(session_id) = &session_id {
+            match previous_session_id(session_id).await {
+                Ok(Some(previous)) => valid = verifies(Some(&previous)),
+                Ok(None) => {}
+                Err(e) => {
+                    log::error!("could not check csrf token against a rotated session: {e}")
+                }
+            }
+        }
+    }
+    if !valid {
+        return Err(CsrfError::NoMatchingCookie);
+    }
+    let age = Utc::now().timestamp() - issued;
+    if !(-CSRF_TOKEN_CLOCK_SKEW_SECS..=CSRF_TOKEN_MAX_AGE_SECS).contains(&age) {
+        log::trace!("csrf token for {purpose:?} expired");
+        return Err(CsrfError::Expired);
+    }
+    log::trace!("csrf cookie+token was validated for {purpose:?}");
+    Ok(())
+}
+
+/// Returns the `__Host-csrf` cookie sent with a request, empty if there was none.
+#[cfg(feature = "ssr")]
+pub fn request_csrf_cookie(req: &Parts) -> Result<String, CsrfError> {
     let mut only_one = 0;
     let mut cookie_value = String::default();
     for headercookie in req.headers.get_all(COOKIE).iter() {
@@ -71,24 +285,32 @@ pub fn validate_csrf(req: Parts, csrf_token: String) -> Result<(), CsrfError> {
             Err(_) => continue,
         }
     }
-    if verify_easy_hash(cookie_value, csrf_server, csrf_token) {
-        log::trace!("csrf cookie+token was validated");
-        Ok(())
-    } else {
-        Err(CsrfError::NoMatchingCookie)
-    }
+    Ok(cookie_value)
 }
 
 #[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationOutcome {
+    /// a new account was created
+    Created(Uuid),
+    /// the email address already belongs to this account, nothing was created and the
+    /// client must be answered as if something was
+    EmailTaken(Uuid),
+}
+
+#[cfg(feature = "ssr")]
+#[allow(clippy::too_many_arguments)]
 pub async fn validate_registration(
     csrf: String,
+    pow_challenge: String,
+    pow_nonce: String,
     username: String,
     display_name: String,
     email: String,
     email_confirmation: String,
     password: SecretString,
     password_confirmation: SecretString,
-) -> Result<Uuid, AppError> {
+) -> Result<RegistrationOutcome, AppError> {
     let http_req = match use_context::<Parts>() {
         None => {
             log::error!("validate_registration: could not retrieve RequestParts");
@@ -97,7 +319,7 @@ pub async fn validate_registration(
         Some(rp) => rp,
     };
     //validate token matches cookie
-    match validate_csrf(http_req, csrf) {
+    match validate_csrf(http_req, csrf, CsrfPurpose::Signup).await {
         Err(CsrfError::MultipleCookies) => {
             log::trace!(
                 "validate_registration: multiple csrf cookies present on client request"
@@ -112,35 +334,91 @@ pub async fn validate_registration(
             log::trace!("validate_registration: csrf server value could not be retrieved");
             return Err(CsrfError::ServerValMissing.into());
         }
+        Err(CsrfError::Expired) => {
+            log::trace!("validate_registration: csrf token expired");
+            return Err(CsrfError::Expired.into());
+        }
+        Err(CsrfError::BearerTokenRejected) => {
+            log::trace!("validate_registration: access token rejected");
+            return Err(CsrfError::BearerTokenRejected.into());
+        }
+        Err(CsrfError::BearerTokenNotAllowed) => {
+            log::trace!("validate_registration: access token used to sign up");
+            return Err(CsrfError::BearerTokenNotAllowed.into());
+        }
+        Err(CsrfError::BearerTokenUnchecked) => {
+            log::trace!("validate_registration: access token could not be checked");
+            return Err(CsrfError::BearerTokenUnchecked.into());
+        }
         Ok(_) => {}
     };
+    //validate the browser did the proof of work, before any other work is done
+    proof_of_work::verify_pow_solution(pow_challenge, pow_nonce).await?;
     //validate email matches in both fields
-    match email_confirmation.eq(&email) {
-        false => {
-            return Err(RegistrationError::EmailNotMatching.into());
-        }
-        true => {}
-    };
-    //validate password matches in both fields
-    match password_confirmation
-        .expose_secret()
-        .eq(password.expose_secret())
-    {
-        false => {
-            return Err(RegistrationError::PasswordNotMatching.into());
+    if email_confirmation != email {
+        return Err(RegistrationError::EmailNotMatching.into());
+    }
+    validate_new_password(
+        &password,
+        &password_confirmation,
+        &[&username, &display_name, &email],
+    )?;
+    validate_username(&username)?;
+    validate_display_name(&display_name)?;
+    //validate email is correct format
+    if EmailAddress::from_str(email.as_str()).is_err() {
+        return Err(RegistrationError::InvalidEmail.into());
+    }
+    //only attempts that passed the checks above count towards the limit
+    rate_limit(LimitedAction::Signup)?;
+    unique_cred_check(UniqueCredential::Username(username.clone())).await?;
+    unique_cred_check(UniqueCredential::DisplayName(display_name.clone())).await?;
+    let email_policy = match use_context::<ServerVars>() {
+        Some(vars) => Ok(vars.email_policy),
+        None => {
+            log::error!("validate_registration: server vars not available");
+            Err(RouterError::HTTPRequestMissing)
         }
-        true => {}
-    };
-    //validate password is within length requirements
-    if password.expose_secret().len() < PASSWORD_MIN_LEN - 1
-        || password.expose_secret().len() > PASSWORD_MAX_LEN
-    {
-        return Err(RegistrationError::PasswordLength.into());
+    }?;
+    let email_normalized = canonical_email(&email, email_policy);
+    // hashed either way, so a taken address does not answer any sooner
+    let password_hash = gen_hash(password)?;
+    if let Some(owner) = email_owner(&email_normalized).await? {
+        log::trace!("signup: email address of {username} already belongs to {owner}");
+        return Ok(RegistrationOutcome::EmailTaken(owner));
     }
+    log::trace!(
+        "signup: successful registration for username: {username}, display_name: \
+         {display_name}"
+    );
+    let id = register_user(
+        username,
+        display_name,
+        email,
+        email_normalized,
+        password_hash,
+    )
+    .await?;
+    log::trace!("signup: db write succeeded for new user");
+    Ok(RegistrationOutcome::Created(id))
+}
+
+/// Checks a username being chosen against the length and name policy rules. Whether it is
+/// taken is checked separately, once the attempt is rate limited.
+#[cfg(feature = "ssr")]
+pub fn validate_username(username: &str) -> Result<(), AppError> {
     //validate username is within length requirements
     if username.len() < USERNAME_MIN_LEN - 1 || username.len() > USERNAME_MAX_LEN {
         return Err(RegistrationError::UsernameLength.into());
     }
+    //validate username against the operator's name policy
+    name_policy::check_name_policy(username)
+}
+
+/// Checks a display name being chosen against the length, character and name policy rules.
+/// Whether it is taken is checked separately, once the attempt is rate limited.
+#[cfg(feature = "ssr")]
+pub fn validate_display_name(display_name: &str) -> Result<(), AppError> {
     //validate display_name is within length requirements
     if display_name.len() < DISPLAY_NAME_MIN_LEN - 1
         || display_name.len() > DISPLAY_NAME_MAX_LEN
@@ -153,21 +431,41 @@ pub async fn validate_registration(
             return Err(RegistrationError::DisplayNameInvalidCharacters.into());
         }
     }
-    //validate email is correct format
-    if EmailAddress::from_str(email.as_str()).is_err() {
-        return Err(RegistrationError::InvalidEmail.into());
+    //validate display_name against the operator's name policy
+    name_policy::check_name_policy(display_name)
+}
+
+/// Checks a password being set, at signup or when replacing an old one. `user_inputs` are
+/// what the password should not be based on, such as the username.
+#[cfg(feature = "ssr")]
+pub fn validate_new_password(
+    password: &SecretString,
+    password_confirmation: &SecretString,
+    user_inputs: &[&str],
+) -> Result<(), RegistrationError> {
+    //validate password matches in both fields
+    if !password_confirmation
+        .expose_secret()
+        .eq(password.expose_secret())
+    {
+        return Err(RegistrationError::PasswordNotMatching);
     }
-    unique_cred_check(UniqueCredential::Username(username.clone())).await?;
-    unique_cred_check(UniqueCredential::DisplayName(display_name.clone())).await?;
-    //unique_cred_check(UniqueCredential::Email(email)).await?;
-    let password_hash = gen_hash(password)?;
-    log::trace!(
-        "signup: successful registration for username: {username}, display_name: \
-         {display_name}"
-    );
-    let id = register_user(username, display_name, email, password_hash).await?;
-    log::trace!("signup: db write succeeded for new user");
-    Ok(id)
+    //validate password is within length requirements
+    if password.expose_secret().len() < PASSWORD_MIN_LEN - 1
+        || password.expose_secret().len() > PASSWORD_MAX_LEN
+    {
+        return Err(RegistrationError::PasswordLength);
+    }
+    password_policy::check_password_policy(password, user_inputs)
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginOutcome {
+    /// the password was all that was required, a session can be issued
+    Complete(Uuid),
+    /// the password was correct, but the account also requires a second factor
+    SecondFactorRequired(Uuid),
 }
 
 #[cfg(feature = "ssr")]
@@ -175,7 +473,7 @@ pub async fn validate_login(
     csrf: String,
     username: String,
     password: SecretString,
-) -> Result<Uuid, AppError> {
+) -> Result<LoginOutcome, AppError> {
     let http_req = match use_context::<Parts>() {
         None => {
             log::error!("login: could not retrieve RequestParts");
@@ -184,7 +482,7 @@ pub async fn validate_login(
         Some(rp) => Ok(rp),
     }?;
     //validate token matches cookie
-    match validate_csrf(http_req, csrf) {
+    match validate_csrf(http_req, csrf, CsrfPurpose::Login).await {
         Err(CsrfError::MultipleCookies) => {
             log::trace!("login: multiple cookies present on client request");
             Err(CsrfError::MultipleCookies)
@@ -197,6 +495,22 @@ pub async fn validate_login(
             log::trace!("login: servervars are not available");
             Err(CsrfError::ServerValMissing)
         }
+        Err(CsrfError::Expired) => {
+            log::trace!("login: csrf token expired");
+            Err(CsrfError::Expired)
+        }
+        Err(CsrfError::BearerTokenRejected) => {
+            log::trace!("login: access token rejected");
+            Err(CsrfError::BearerTokenRejected)
+        }
+        Err(CsrfError::BearerTokenNotAllowed) => {
+            log::trace!("login: access token used to log in");
+            Err(CsrfError::BearerTokenNotAllowed)
+        }
+        Err(CsrfError::BearerTokenUnchecked) => {
+            log::trace!("login: access token could not be checked");
+            Err(CsrfError::BearerTokenUnchecked)
+        }
         Ok(_) => Ok(()),
     }?;
     //validate password is within length requirements
@@ -209,9 +523,31 @@ pub async fn validate_login(
     if username.len() < USERNAME_MIN_LEN - 1 || username.len() > USERNAME_MAX_LEN {
         return Err(RegistrationError::UsernameLength.into());
     }
-    let id = validate_credentials(username.clone(), password).await?;
+    let throttle_keys = throttle::login_throttle_keys(&username);
+    let attempt = match throttle::begin_login_attempt(&throttle_keys).await {
+        Ok(attempt) => attempt,
+        Err(e) => {
+            //take as long as a real attempt and never reveal whether the account exists
+            let _task = tokio::task::spawn_blocking(move || spin_hash(password)).await;
+            return Err(e);
+        }
+    };
+    let id = match validate_credentials(username.clone(), password).await {
+        Ok(id) => id,
+        Err(e @ AppError::Login(LoginError::IncorrectCredentials)) => {
+            throttle::record_login_failure(&attempt).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    throttle::clear_login_failures(&attempt).await?;
+    verification::check_login_allowed(id).await?;
+    if totp_enabled(id).await? {
+        log::trace!("login: password accepted for user: {username}, second factor required");
+        return Ok(LoginOutcome::SecondFactorRequired(id));
+    }
     log::trace!("login: successful login for user: {username}");
-    Ok(id)
+    Ok(LoginOutcome::Complete(id))
 }
 
 #[cfg(feature = "ssr")]
@@ -219,12 +555,8 @@ pub fn gen_128bit_base64() -> String {
     // this will issue a CSPRNG created 128 bits of entropy in base 64
     // This function only generates the CSPRNG value.
     //
-    // For session cookies alternate implementations would deliver AES encrypted
-    // data to the user to prevent addtional DB load on each API request including
-    // the session cookie.
-    //
-    // for now we will only use the full random ID and hit the database with each request
-    // this is an easy place to improve performance later if it is needed with high DB load
+    // With SESSION_STORE="cookie" this is only the id sealed inside the AES-GCM encrypted
+    // session cookie, see `session_cookie`, so a request does not read its session row.
     const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
         &base64::alphabet::URL_SAFE,
         base64::engine::general_purpose::NO_PAD,
@@ -237,12 +569,8 @@ pub fn gen_128bit() -> u128 {
     // this will issue a CSPRNG created 128 bits of entropy.
     // This function only generates the CSPRNG value.
     //
-    // For session cookies alternate implementations would deliver AES encrypted
-    // data to the user to prevent addtional DB load on each API request including
-    // the session cookie.
-    //
-    // for now we will only use the full random ID and hit the database with each request
-    // this is an easy place to improve performance later if it is needed with high DB load
+    // With SESSION_STORE="cookie" this is only the id sealed inside the AES-GCM encrypted
+    // session cookie, see `session_cookie`, so a request does not read its session row.
     Uuid::new_v4().as_u128()
 }
 
@@ -260,6 +588,8 @@ pub fn stringify_u128_base64(input: u128) -> String {
 enum ValidateHashError {
     DatabaseError(argon2::password_hash::Error),
     VerifyError(argon2::password_hash::Error),
+    /// the hash was peppered with a version that is no longer configured
+    PepperMissing(u32),
 }
 
 #[cfg(feature = "ssr")]
@@ -280,10 +610,17 @@ pub async fn validate_credentials(
             }
             Err(e) => Err(e),
         }?;
+    let outdated = needs_rehash(&stored_phc);
+    let password = untrusted_password.clone();
     let task =
         tokio::task::spawn_blocking(move || verify_hash(stored_phc, untrusted_password)).await;
     match task {
-        Ok(Ok(())) => Ok(true_uuid),
+        Ok(Ok(())) => {
+            if outdated {
+                rehash_password(true_uuid, password).await;
+            }
+            Ok(true_uuid)
+        }
         Ok(Err(ValidateHashError::DatabaseError(e))) => {
             //database is possibly corrupted
             log::error!("could not parse PHC for {username} with error {e}");
@@ -293,6 +630,12 @@ pub async fn validate_credentials(
             log::trace!("invalid password attempt for {username} with error {e}");
             Err(LoginError::IncorrectCredentials.into())
         }
+        Ok(Err(ValidateHashError::PepperMissing(version))) => {
+            log::error!(
+                "password of {username} needs pepper version {version}, which is not set"
+            );
+            Err(AppError::Argon2Failure)
+        }
         Err(tokio_err) => {
             log::error!("failed to spawn blocking tokio task: {tokio_err}");
             Err(AppError::TokioFailure)
@@ -300,13 +643,93 @@ pub async fn validate_credentials(
     }
 }
 
+/// Replaces an outdated stored hash with one made with the current parameters. Failures are
+/// only logged, the old hash keeps working.
+#[cfg(feature = "ssr")]
+async fn rehash_password(user_id: Uuid, password: SecretString) {
+    let password_hash = match tokio::task::spawn_blocking(move || gen_hash(password)).await {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(e)) => {
+            log::error!("could not rehash the password of {user_id}: {e}");
+            return;
+        }
+        Err(tokio_err) => {
+            log::error!("failed to spawn blocking tokio task: {tokio_err}");
+            return;
+        }
+    };
+    match update_password_hash(user_id, password_hash).await {
+        Ok(()) => log::debug!("rehashed the password of {user_id} with current parameters"),
+        Err(e) => log::error!("could not store the rehashed password of {user_id}: {e}"),
+    }
+}
+
+/// Replaces the password of the logged in user after checking their current password.
+/// With `revoke_others` every session except the current one is logged out.
+#[cfg(feature = "ssr")]
+pub async fn change_password(
+    csrf: String,
+    current_password: SecretString,
+    password: SecretString,
+    password_confirmation: SecretString,
+    revoke_others: bool,
+) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ChangePassword).await?;
+    let user_id = require_session().await?;
+    let username = username_for_id(user_id).await?;
+    validate_new_password(&password, &password_confirmation, &[&username])?;
+    let stored_phc = retrieve_password_hash(user_id).await?;
+    let task =
+        tokio::task::spawn_blocking(move || verify_hash(stored_phc, current_password)).await;
+    match task {
+        Ok(Ok(())) => {}
+        Ok(Err(ValidateHashError::DatabaseError(e))) => {
+            //database is possibly corrupted
+            log::error!("could not parse PHC for {user_id} with error {e}");
+            return Err(AppError::Argon2Failure);
+        }
+        Ok(Err(ValidateHashError::VerifyError(e))) => {
+            log::trace!("invalid current password for {user_id} with error {e}");
+            return Err(LoginError::IncorrectPassword.into());
+        }
+        Ok(Err(ValidateHashError::PepperMissing(version))) => {
+            log::error!(
+                "password of {user_id} needs pepper version {version}, which is not set"
+            );
+            return Err(AppError::Argon2Failure);
+        }
+        Err(tokio_err) => {
+            log::error!("failed to spawn blocking tokio task: {tokio_err}");
+            return Err(AppError::TokioFailure);
+        }
+    }
+    update_password_hash(user_id, gen_hash(password)?).await?;
+    if revoke_others {
+        end_other_sessions(user_id).await?;
+    }
+    log::trace!("password changed for {user_id}");
+    Ok(())
+}
+
+/// Replaces the username of the logged in account, which is only allowed while it is
+/// flagged with `rename_required` because it looks like another account's.
 #[cfg(feature = "ssr")]
-// hash that does not use salt and not for passwords
-fn gen_easy_hash(input1: String, input2: String) -> String {
-    // forever TODO: watch for updates regarding blake2
-    // <https://github.com/RustCrypto/hashes#supported-algorithms>
+pub async fn choose_username(csrf: String, username: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ChooseUsername).await?;
+    let user_id = require_session().await?;
+    validate_username(&username)?;
+    rate_limit(LimitedAction::UsernameCheck)?;
+    rename_user(user_id, &username).await?;
+    log::trace!("{user_id} chose the new username {username}");
+    Ok(())
+}
+
+/// Hashes a random single-use token before it is stored, so a leaked table cannot be used to
+/// redeem tokens. Only for tokens with at least 128 bits of entropy, never for passwords.
+#[cfg(feature = "ssr")]
+pub fn hash_token(token: &str) -> String {
     let mut hasher = Blake2s256::new();
-    hasher.update(format!("{input1}!{input2}").as_bytes());
+    hasher.update(token.as_bytes());
     let res = hasher.finalize();
     const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
         &base64::alphabet::URL_SAFE,
@@ -315,38 +738,244 @@ fn gen_easy_hash(input1: String, input2: String) -> String {
     base64::Engine::encode(&CUSTOM_ENGINE, res)
 }
 
+/// The Argon2 settings for new hashes, the peppers and a dummy hash made with both, so time
+/// wasting costs exactly as much as checking a real password.
 #[cfg(feature = "ssr")]
-// hash that does not use salt and not for passwords
-fn verify_easy_hash(input1: String, input2: String, expected_result: String) -> bool {
-    let mut hasher = Blake2s256::new();
-    hasher.update(format!("{input1}!{input2}").as_bytes());
-    let res = hasher.finalize();
-    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
-        &base64::alphabet::URL_SAFE,
-        base64::engine::general_purpose::NO_PAD,
-    );
+struct PasswordHashing {
+    params: PasswordHashParams,
+    argon2_params: argon2::Params,
+    /// sorted by version, the last one peppers new hashes
+    peppers: Vec<Pepper>,
+    dummy_hash: OnceLock<String>,
+}
+
+#[cfg(feature = "ssr")]
+static PASSWORD_HASHING: OnceLock<PasswordHashing> = OnceLock::new();
+
+#[cfg(feature = "ssr")]
+impl PasswordHashing {
+    fn new(params: PasswordHashParams, mut peppers: Vec<Pepper>) -> Result<Self, AppError> {
+        peppers.sort_by_key(|pepper| pepper.version);
+        if peppers
+            .windows(2)
+            .any(|pair| pair[0].version == pair[1].version)
+        {
+            log::error!("password pepper versions must be unique");
+            return Err(AppError::Argon2Failure);
+        }
+        let argon2_params = peppered_params(&peppers, params)?;
+        Ok(PasswordHashing {
+            params,
+            argon2_params,
+            peppers,
+            dummy_hash: OnceLock::new(),
+        })
+    }
+
+    /// The hasher for new hashes.
+    fn hasher(&'static self) -> Result<Argon2<'static>, argon2::Error> {
+        self.hasher_with(self.params.algorithm, self.argon2_params.clone())
+    }
+
+    /// A hasher with the pepper of new hashes but other parameters.
+    fn hasher_with(
+        &'static self,
+        algorithm: argon2::Algorithm,
+        argon2_params: argon2::Params,
+    ) -> Result<Argon2<'static>, argon2::Error> {
+        match self.peppers.last() {
+            Some(pepper) => Argon2::new_with_secret(
+                pepper.key.expose_secret(),
+                algorithm,
+                argon2::Version::V0x13,
+                argon2_params,
+            ),
+            None => Ok(Argon2::new(
+                algorithm,
+                argon2::Version::V0x13,
+                argon2_params,
+            )),
+        }
+    }
+
+    /// The hasher that can verify `stored`, which holds its own parameters but not its pepper.
+    fn verifier(
+        &'static self,
+        stored: &PasswordHash,
+    ) -> Result<Argon2<'static>, ValidateHashError> {
+        let secret = match stored_pepper_version(stored) {
+            None => None,
+            Some(version) => {
+                match self.peppers.iter().find(|pepper| pepper.version == version) {
+                    Some(pepper) => Some(pepper.key.expose_secret()),
+                    None => return Err(ValidateHashError::PepperMissing(version)),
+                }
+            }
+        };
+        match secret {
+            Some(secret) => Argon2::new_with_secret(
+                secret,
+                self.params.algorithm,
+                argon2::Version::V0x13,
+                self.argon2_params.clone(),
+            )
+            .map_err(|e| ValidateHashError::DatabaseError(e.into())),
+            None => Ok(Argon2::new(
+                self.params.algorithm,
+                argon2::Version::V0x13,
+                self.argon2_params.clone(),
+            )),
+        }
+    }
+
+    fn dummy_hash(&'static self) -> &'static str {
+        self.dummy_hash.get_or_init(|| {
+            gen_hash(SecretString::from(gen_128bit_base64()))
+                .expect("the dummy password hash to be made")
+        })
+    }
+}
+
+/// Argon2 parameters of the cost in `params`, marked with the version of the pepper new
+/// hashes are made with.
+#[cfg(feature = "ssr")]
+fn peppered_params(
+    peppers: &[Pepper],
+    params: PasswordHashParams,
+) -> Result<argon2::Params, AppError> {
+    let mut builder = argon2::ParamsBuilder::new();
+    builder
+        .m_cost(params.memory_kib)
+        .t_cost(params.iterations)
+        .p_cost(params.parallelism);
+    if let Some(pepper) = peppers.last() {
+        // the version is stored in the hash as its keyid
+        builder.keyid(pepper_keyid(pepper.version)?);
+    }
+    builder.build().map_err(|e| {
+        log::error!("invalid argon2 parameters {params:?}: {e}");
+        AppError::Argon2Failure
+    })
+}
 
-    expected_result.eq(&base64::Engine::encode(&CUSTOM_ENGINE, res))
+#[cfg(feature = "ssr")]
+fn pepper_keyid(version: u32) -> Result<argon2::KeyId, AppError> {
+    argon2::KeyId::new(&version.to_be_bytes()).map_err(|e| {
+        log::error!("could not make a keyid for pepper version {version}: {e}");
+        AppError::Argon2Failure
+    })
+}
+
+/// The pepper version a stored hash was made with, none if it was not peppered.
+#[cfg(feature = "ssr")]
+fn stored_pepper_version(stored: &PasswordHash) -> Option<u32> {
+    let params = argon2::Params::try_from(stored).ok()?;
+    let keyid: [u8; 4] = params.keyid().try_into().ok()?;
+    Some(u32::from_be_bytes(keyid))
+}
+
+/// Sets the cost of password hashes and the peppers for the rest of the process. Call once at
+/// startup, it hashes a dummy password with the new settings.
+#[cfg(feature = "ssr")]
+pub fn init_password_hashing(
+    params: PasswordHashParams,
+    peppers: Vec<Pepper>,
+) -> Result<(), AppError> {
+    if PASSWORD_HASHING
+        .set(PasswordHashing::new(params, peppers)?)
+        .is_err()
+    {
+        log::error!("password hashing was already initialized");
+        return Err(AppError::Argon2Failure);
+    }
+    password_hashing().dummy_hash();
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+fn password_hashing() -> &'static PasswordHashing {
+    PASSWORD_HASHING.get_or_init(|| {
+        PasswordHashing::new(PasswordHashParams::default(), Vec::new())
+            .expect("default argon2 parameters to be valid")
+    })
+}
+
+/// Whether a stored hash was made with other parameters or another pepper than new hashes
+/// are.
+#[cfg(feature = "ssr")]
+fn needs_rehash(stored_password_hash: &SecretString) -> bool {
+    let stored = match PasswordHash::new(stored_password_hash.expose_secret()) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    let hashing = password_hashing();
+    let current = &hashing.params;
+    let algorithm_matches = argon2::Algorithm::try_from(stored.algorithm)
+        .is_ok_and(|algorithm| algorithm == current.algorithm);
+    let pepper_matches =
+        stored_pepper_version(&stored) == hashing.peppers.last().map(|pepper| pepper.version);
+    match argon2::Params::try_from(&stored) {
+        Ok(params) => {
+            !algorithm_matches
+                || !pepper_matches
+                || params.m_cost() != current.memory_kib
+                || params.t_cost() != current.iterations
+                || params.p_cost() != current.parallelism
+        }
+        Err(_) => false,
+    }
 }
 
 #[cfg(feature = "ssr")]
-fn gen_hash(input: SecretString) -> Result<String, AppError> {
-    // forever TODO: improve salt and complextity of hashing as computers get better
-    // and as people buy more PS5s and shove them in underwater hashing factories
+pub fn gen_hash(input: SecretString) -> Result<String, AppError> {
+    // the cost is configured with the PASSWORD_HASH_* variables, raise it as computers
+    // get better and as people buy more PS5s and shove them in underwater hashing factories
     // reference this article:
     // <https://argon2-cffi.readthedocs.io/en/stable/parameters.html>
     // archive:
     // <https://web.archive.org/web/20230111040733/https://argon2-cffi.readthedocs.io/en/stable/parameters.html>
     let salt = SaltString::generate(&mut rand::thread_rng());
-    Ok(
-        match Argon2::default().hash_password(input.expose_secret().as_bytes(), &salt) {
-            Ok(hash) => Ok(hash.to_string()),
-            Err(err) => {
-                log::error!("failed to produce hash of password in gen_hash: {err}");
-                Err(AppError::Argon2Failure)
-            }
-        }?,
-    )
+    let hasher = password_hashing().hasher().map_err(|e| {
+        log::error!("failed to set up argon2 in gen_hash: {e}");
+        AppError::Argon2Failure
+    })?;
+    match hasher.hash_password(input.expose_secret().as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => {
+            log::error!("failed to produce hash of password in gen_hash: {err}");
+            Err(AppError::Argon2Failure)
+        }
+    }
+}
+
+/// Hashes a random code such as a recovery code with the pepper of new password hashes but
+/// at the much lower `RECOVERY_CODE_HASH_*` cost. `verify_hash` checks it like a password,
+/// the parameters are read from the hash.
+#[cfg(feature = "ssr")]
+pub fn gen_code_hash(code: SecretString) -> Result<String, AppError> {
+    let hashing = password_hashing();
+    let params = PasswordHashParams {
+        algorithm: argon2::Algorithm::Argon2id,
+        memory_kib: RECOVERY_CODE_HASH_MEMORY_KIB,
+        iterations: RECOVERY_CODE_HASH_ITERATIONS,
+        parallelism: 1,
+    };
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let hasher = peppered_params(&hashing.peppers, params).and_then(|argon2_params| {
+        hashing
+            .hasher_with(params.algorithm, argon2_params)
+            .map_err(|e| {
+                log::error!("failed to set up argon2 in gen_code_hash: {e}");
+                AppError::Argon2Failure
+            })
+    })?;
+    match hasher.hash_password(code.expose_secret().as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => {
+            log::error!("failed to produce hash of code in gen_code_hash: {err}");
+            Err(AppError::Argon2Failure)
+        }
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -354,6 +983,13 @@ fn verify_hash(
     stored_password_hash: SecretString,
     password_candidate: SecretString,
 ) -> Result<(), ValidateHashError> {
+    // accounts made through a sign in provider have no password until one is reset
+    if stored_password_hash.expose_secret().is_empty() {
+        let _ = spin_hash(password_candidate);
+        return Err(ValidateHashError::VerifyError(
+            argon2::password_hash::Error::Password,
+        ));
+    }
     let expected_password_hash = match PasswordHash::new(stored_password_hash.expose_secret())
     {
         Ok(hash) => hash,
@@ -363,7 +999,9 @@ fn verify_hash(
         }
     };
 
-    Argon2::default()
+    // the parameters are read from the stored hash, its pepper is looked up by version
+    password_hashing()
+        .verifier(&expected_password_hash)?
         .verify_password(
             password_candidate.expose_secret().as_bytes(),
             &expected_password_hash,
@@ -373,16 +1011,208 @@ fn verify_hash(
 
 #[cfg(feature = "ssr")]
 fn spin_hash(untrusted_password: SecretString) -> Result<(), ValidateHashError> {
-    // forever TODO: update this hash as we improve the gen_hash
-    // notes about this hash: it is not a real hash, just to waste time using the same algo.
+    // notes about this hash: it is not a real hash, just to waste time using the same algo
+    // and the same parameters as gen_hash, it is made at startup.
     // This hash is NOT a secret. This function returns NOTHING, only wastes time.
-    let static_hash = PasswordHash::new(
-        "$argon2id$v=19$m=4096,t=3,p=1$tKyUZbQxabvC3XB323vWmw$mMtsEupAXrnh00lI/\
-         6kPHNpIGntadmxH/Hlr3i29CH0",
-    )
-    .unwrap();
+    let hashing = password_hashing();
+    let dummy_hash =
+        PasswordHash::new(hashing.dummy_hash()).map_err(ValidateHashError::DatabaseError)?;
 
-    Argon2::default()
-        .verify_password(untrusted_password.expose_secret().as_bytes(), &static_hash)
+    hashing
+        .verifier(&dummy_hash)?
+        .verify_password(untrusted_password.expose_secret().as_bytes(), &dummy_hash)
         .map_err(ValidateHashError::VerifyError)
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::database::memory_pool;
+    use axum::http::{header::AUTHORIZATION, Request};
+    use leptos::prelude::Owner;
+
+    const CSRF_COOKIE: &str = "csrf-cookie";
+
+    /// A token as `generate_csrf` would issue it, issued `age` seconds ago.
+    fn token(key: &str, purpose: CsrfPurpose, age: i64, session_id: Option<&str>) -> String {
+        let issued = Utc::now().timestamp() - age;
+        let mac = csrf_mac(key, purpose, issued, CSRF_COOKIE, session_id)
+            .finalize()
+            .into_bytes();
+        let binding = match session_id {
+            Some(_) => "s",
+            None => "u",
+        };
+        const CUSTOM_ENGINE: base64::engine::GeneralPurpose =
+            base64::engine::GeneralPurpose::new(
+                &base64::alphabet::URL_SAFE,
+                base64::engine::general_purpose::NO_PAD,
+            );
+        format!(
+            "{issued}.{binding}.{}",
+            base64::Engine::encode(&CUSTOM_ENGINE, mac)
+        )
+    }
+
+    /// A request carrying the csrf cookie and, if given, a session cookie.
+    fn request(session_id: Option<&str>) -> Parts {
+        let mut cookies = format!("__Host-csrf={CSRF_COOKIE}");
+        if let Some(session_id) = session_id {
+            cookies.push_str(&format!("; SESSIONID={session_id}"));
+        }
+        Request::builder()
+            .header(COOKIE, cookies)
+            .body(())
+            .expect("a valid request")
+            .into_parts()
+            .0
+    }
+
+    /// Provides `keys` and an empty database to `validate_csrf`.
+    async fn with_keys(keys: &CsrfKeys) -> Owner {
+        let owner = Owner::new();
+        owner.set();
+        provide_context(keys.clone());
+        provide_context(memory_pool().await);
+        owner
+    }
+
+    #[tokio::test]
+    async fn token_for_its_purpose_is_accepted() {
+        let keys = CsrfKeys::ephemeral();
+        let _owner = with_keys(&keys).await;
+        let csrf = token(&keys.current(), CsrfPurpose::Login, 0, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Ok(())
+        );
+        let csrf = token(
+            &keys.current(),
+            CsrfPurpose::ChangePassword,
+            0,
+            Some("session"),
+        );
+        assert_eq!(
+            validate_csrf(request(Some("session")), csrf, CsrfPurpose::ChangePassword).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn token_for_another_purpose_is_refused() {
+        let keys = CsrfKeys::ephemeral();
+        let _owner = with_keys(&keys).await;
+        let csrf = token(&keys.current(), CsrfPurpose::Login, 0, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::ChangePassword).await,
+            Err(CsrfError::NoMatchingCookie)
+        );
+    }
+
+    #[tokio::test]
+    async fn token_of_another_session_is_refused() {
+        let keys = CsrfKeys::ephemeral();
+        let _owner = with_keys(&keys).await;
+        let csrf = token(
+            &keys.current(),
+            CsrfPurpose::ChangePassword,
+            0,
+            Some("victim"),
+        );
+        assert_eq!(
+            validate_csrf(request(Some("attacker")), csrf, CsrfPurpose::ChangePassword).await,
+            Err(CsrfError::NoMatchingCookie)
+        );
+        // a token bound to a session is no good without it
+        let csrf = token(
+            &keys.current(),
+            CsrfPurpose::ChangePassword,
+            0,
+            Some("victim"),
+        );
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::ChangePassword).await,
+            Err(CsrfError::NoMatchingCookie)
+        );
+    }
+
+    #[tokio::test]
+    async fn token_past_its_max_age_is_refused() {
+        let keys = CsrfKeys::ephemeral();
+        let _owner = with_keys(&keys).await;
+        let age = CSRF_TOKEN_MAX_AGE_SECS - 5;
+        let csrf = token(&keys.current(), CsrfPurpose::Login, age, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Ok(())
+        );
+        let age = CSRF_TOKEN_MAX_AGE_SECS + 5;
+        let csrf = token(&keys.current(), CsrfPurpose::Login, age, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Err(CsrfError::Expired)
+        );
+        let age = -CSRF_TOKEN_CLOCK_SKEW_SECS - 5;
+        let csrf = token(&keys.current(), CsrfPurpose::Login, age, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Err(CsrfError::Expired)
+        );
+    }
+
+    #[tokio::test]
+    async fn token_of_a_rotated_out_key_is_refused() {
+        let file = std::env::temp_dir().join(format!("csrf_keys_{}", Uuid::new_v4()));
+        let keys = CsrfKeys::from_file(file.clone()).unwrap();
+        let _owner = with_keys(&keys).await;
+        let first = keys.current();
+        keys.rotate().unwrap();
+        // the previous key keeps working for tokens in open tabs
+        let csrf = token(&first, CsrfPurpose::Login, 0, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Ok(())
+        );
+        for _ in 0..CSRF_PREVIOUS_KEYS_KEPT {
+            keys.rotate().unwrap();
+        }
+        let csrf = token(&first, CsrfPurpose::Login, 0, None);
+        assert_eq!(
+            validate_csrf(request(None), csrf, CsrfPurpose::Login).await,
+            Err(CsrfError::NoMatchingCookie)
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn access_token_unchecked_for_a_database_error_is_told_apart() {
+        let owner = Owner::new();
+        owner.set();
+        provide_context(CsrfKeys::ephemeral());
+        let pool = memory_pool().await;
+        pool.close().await;
+        provide_context(pool);
+        let req = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {API_TOKEN_PREFIX}token"))
+            .body(())
+            .expect("a valid request")
+            .into_parts()
+            .0;
+        assert_eq!(
+            validate_csrf(req, String::new(), CsrfPurpose::VerifyEmail).await,
+            Err(CsrfError::BearerTokenUnchecked)
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_token_is_refused() {
+        let keys = CsrfKeys::ephemeral();
+        let _owner = with_keys(&keys).await;
+        for csrf in ["", "token", "1.u", "1.x.AAAA", "now.u.AAAA"] {
+            assert_eq!(
+                validate_csrf(request(None), csrf.to_string(), CsrfPurpose::Login).await,
+                Err(CsrfError::NoMatchingCookie)
+            );
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{require_session, validate_session};
+    use crate::database::username_for_id;
+    use crate::defs::*;
+    use crate::security::{csrf_keys::CsrfKeys, validate_csrf_request};
+    use crate::session_cache::{SessionCache, SessionCacheStats};
+    use leptos::prelude::*;
+    use uuid::Uuid;
+}}
+
+/// Whether `user_id` is one of the `ADMIN_USERNAMES`.
+#[cfg(feature = "ssr")]
+async fn is_admin_user(user_id: Uuid) -> Result<bool, AppError> {
+    let admins = match use_context::<AdminUsers>() {
+        Some(admins) => admins,
+        None => {
+            log::error!("is_admin_user: admin users not available in context");
+            return Err(RouterError::HTTPRequestMissing.into());
+        }
+    };
+    if admins.0.is_empty() {
+        return Ok(false);
+    }
+    Ok(admins.contains(&username_for_id(user_id).await?))
+}
+
+/// Whether the current request is from a logged in admin.
+#[cfg(feature = "ssr")]
+pub async fn is_admin() -> Result<bool, AppError> {
+    match validate_session().await? {
+        Some(user_id) => is_admin_user(user_id).await,
+        None => Ok(false),
+    }
+}
+
+/// Like `require_session`, for server functions only admins may use.
+#[cfg(feature = "ssr")]
+pub async fn require_admin() -> Result<Uuid, AppError> {
+    let user_id = require_session().await?;
+    match is_admin_user(user_id).await? {
+        true => Ok(user_id),
+        false => {
+            log::warn!("{user_id} attempted an admin action");
+            Err(AdminError::NotAdmin.into())
+        }
+    }
+}
+
+/// Signs new CSRF tokens with a new key. Forms already open keep working while their token
+/// is signed with one of the `CSRF_PREVIOUS_KEYS_KEPT` previous keys. Only keys from a
+/// CSRF_KEY_FILE can be rotated.
+#[cfg(feature = "ssr")]
+pub async fn rotate_csrf_key(csrf: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::RotateCsrfKey).await?;
+    let user_id = require_admin().await?;
+    let keys = match use_context::<CsrfKeys>() {
+        Some(keys) => keys,
+        None => {
+            log::error!("rotate_csrf_key: csrf keys not available in context");
+            return Err(RouterError::HTTPRequestMissing.into());
+        }
+    };
+    keys.rotate()?;
+    log::info!("csrf key rotated by {user_id}");
+    Ok(())
+}
+
+/// How well the session cache has been doing since the server started.
+#[cfg(feature = "ssr")]
+pub async fn session_cache_stats() -> Result<SessionCacheStats, AppError> {
+    require_admin().await?;
+    match use_context::<SessionCache>() {
+        Some(cache) => Ok(cache.stats()),
+        None => {
+            log::error!("session_cache_stats: session cache not available in context");
+            Err(RouterError::HTTPRequestMissing.into())
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::defs::ApiTokenScope;
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{close_session_sockets, require_session};
+    use crate::database::api_tokens::{
+        api_token_grant_with_pool, delete_api_token, insert_api_token, retrieve_api_tokens,
+        ApiTokenGrant,
+    };
+    use crate::defs::*;
+    use crate::security::{gen_128bit_base64, hash_token, validate_csrf_request};
+    use axum::http::{header::AUTHORIZATION, HeaderMap};
+    use chrono::prelude::*;
+    use http::request::Parts;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use uuid::Uuid;
+}}
+
+/// A personal access token as shown on the settings page, without its secret
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTokenSummary {
+    pub token_id: String,
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub created: String,
+    pub expiry: String,
+}
+
+/// The token of an `Authorization: Bearer` header, if the request has one.
+#[cfg(feature = "ssr")]
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    match scheme.eq_ignore_ascii_case("bearer") {
+        true => Some(token.trim().to_string()),
+        false => None,
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn parse_scopes(scopes: &str) -> Vec<ApiTokenScope> {
+    scopes.split(',').filter_map(ApiTokenScope::parse).collect()
+}
+
+/// Looks up a presented token, which only counts while it has not expired and has `scope`.
+#[cfg(feature = "ssr")]
+pub async fn authenticate_api_token_with_pool(
+    token: &str,
+    scope: ApiTokenScope,
+    pool: &SqlitePool,
+) -> Result<Option<ApiTokenGrant>, DatabaseError> {
+    if !token.starts_with(API_TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    let grant = match api_token_grant_with_pool(&hash_token(token), pool).await? {
+        Some(grant) => grant,
+        None => return Ok(None),
+    };
+    if grant.expiry < Utc::now() {
+        log::trace!("expired api token {} presented", grant.token_id);
+        return Ok(None);
+    }
+    if !parse_scopes(&grant.scopes).contains(&scope) {
+        log::trace!(
+            "api token {} lacks the {} scope",
+            grant.token_id,
+            scope.as_str()
+        );
+        return Ok(None);
+    }
+    Ok(Some(grant))
+}
+
+#[cfg(feature = "ssr")]
+pub async fn authenticate_api_token(
+    token: &str,
+    scope: ApiTokenScope,
+) -> Result<Option<ApiTokenGrant>, DatabaseError> {
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in authenticate_api_token");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    authenticate_api_token_with_pool(token, scope, &pool).await
+}
+
+/// The key websockets opened with a token are registered under in `SessionSockets`, so
+/// revoking the token closes them like logging out closes a session's.
+#[cfg(feature = "ssr")]
+pub fn api_token_socket_key(token_id: Uuid) -> String {
+    format!("api-token:{token_id}")
+}
+
+/// A token can do anything its scopes allow, except manage tokens and other credentials.
+#[cfg(feature = "ssr")]
+pub fn reject_bearer_request() -> Result<(), AppError> {
+    match use_context::<Parts>() {
+        Some(parts) if bearer_token(&parts.headers).is_some() => {
+            Err(ApiTokenError::SessionRequired.into())
+        }
+        Some(_) => Ok(()),
+        None => {
+            log::error!("reject_bearer_request: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing.into())
+        }
+    }
+}
+
+/// Makes a new token for the logged in account and returns it. Only its hash is stored,
+/// this is the one time it can be shown.
+#[cfg(feature = "ssr")]
+pub async fn create_api_token(
+    csrf: String,
+    name: String,
+    scopes: Vec<ApiTokenScope>,
+    lifetime_days: i64,
+) -> Result<String, AppError> {
+    reject_bearer_request()?;
+    validate_csrf_request(csrf, CsrfPurpose::CreateApiToken).await?;
+    let user_id = require_session().await?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > API_TOKEN_NAME_MAX_LEN {
+        return Err(ApiTokenError::NameLength.into());
+    }
+    if !API_TOKEN_LIFETIMES_DAYS.contains(&lifetime_days) {
+        return Err(ApiTokenError::InvalidLifetime.into());
+    }
+    let scopes: Vec<&str> = ApiTokenScope::ALL
+        .iter()
+        .filter(|scope| scopes.contains(scope))
+        .map(ApiTokenScope::as_str)
+        .collect();
+    if scopes.is_empty() {
+        return Err(ApiTokenError::NoScopes.into());
+    }
+    let token = format!("{API_TOKEN_PREFIX}{}", gen_128bit_base64());
+    let expiry = Utc::now() + chrono::Duration::days(lifetime_days);
+    let token_id = insert_api_token(
+        user_id,
+        &name,
+        &hash_token(&token),
+        &scopes.join(","),
+        expiry,
+        API_TOKEN_MAX_PER_USER,
+    )
+    .await?;
+    log::trace!("api token {token_id} created for {user_id}");
+    Ok(token)
+}
+
+#[cfg(feature = "ssr")]
+pub async fn api_token_summaries() -> Result<Vec<ApiTokenSummary>, AppError> {
+    let user_id = require_session().await?;
+    Ok(retrieve_api_tokens(user_id)
+        .await?
+        .into_iter()
+        .map(|stored| ApiTokenSummary {
+            token_id: stored.token_id.to_string(),
+            name: stored.name,
+            scopes: parse_scopes(&stored.scopes),
+            created: stored.created.format("%Y-%m-%d %H:%M UTC").to_string(),
+            expiry: stored.expiry.format("%Y-%m-%d %H:%M UTC").to_string(),
+        })
+        .collect())
+}
+
+/// Deletes a token of the logged in account and closes the websockets opened with it.
+#[cfg(feature = "ssr")]
+pub async fn revoke_api_token(csrf: String, token_id: String) -> Result<(), AppError> {
+    reject_bearer_request()?;
+    validate_csrf_request(csrf, CsrfPurpose::RevokeApiToken).await?;
+    let user_id = require_session().await?;
+    let token_id = match Uuid::parse_str(&token_id) {
+        Ok(token_id) => token_id,
+        Err(_) => return Err(ApiTokenError::NotFound.into()),
+    };
+    delete_api_token(user_id, token_id).await?;
+    close_session_sockets(&[api_token_socket_key(token_id)]);
+    log::trace!("api token {token_id} revoked for {user_id}");
+    Ok(())
+}
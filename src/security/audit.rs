@@ -0,0 +1,22 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::request_client;
+    use crate::database::audit::insert_audit_event;
+    use crate::defs::AuditEvent;
+    use uuid::Uuid;
+}}
+
+/// Writes an event about `user_id` to the audit log, with the address and user agent of the
+/// current request. A failed write is logged but does not fail the action being audited.
+#[cfg(feature = "ssr")]
+pub async fn record_audit_event(user_id: Uuid, event: AuditEvent) {
+    let (ip, user_agent) = request_client();
+    match insert_audit_event(user_id, event, &ip, &user_agent).await {
+        Ok(()) => log::info!("audit: {} for {user_id} from {ip}", event.as_str()),
+        Err(e) => log::error!(
+            "could not write audit event {} for {user_id}: {e}",
+            event.as_str()
+        ),
+    }
+}
@@ -0,0 +1,104 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::EmailPolicy;
+    use caseless::default_case_fold_str;
+    use unicode_normalization::UnicodeNormalization;
+    use unicode_security::skeleton;
+}}
+
+/// The form of a username or display name that look-alike names share, such as "Alice",
+/// "alice", "ａｌｉｃｅ" and "аlice" with a Cyrillic "а". Only one account may hold each
+/// canonical name.
+///
+/// Applies NFKC, case folding and the Unicode confusable skeleton. The result is only used
+/// for comparisons, names are still shown the way they were entered.
+#[cfg(feature = "ssr")]
+pub fn canonical_name(name: &str) -> String {
+    let folded = default_case_fold_str(&name.nfkc().collect::<String>());
+    // skeletons keep some capitals, "0" becomes "O", so they are folded again
+    let skeleton: String = skeleton(&folded).collect();
+    default_case_fold_str(&skeleton).nfkc().collect()
+}
+
+/// The form of an email address that addresses reaching the same mailbox share. The domain
+/// is lowercased, and with `strip_plus_tags` anything from the first "+" of the local part
+/// on is dropped, so "Alice+news@Example.com" becomes "Alice@example.com".
+///
+/// The local part keeps its case, some mail servers treat it as case sensitive.
+#[cfg(feature = "ssr")]
+pub fn canonical_email(email: &str, policy: EmailPolicy) -> String {
+    let email = email.trim();
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return email.to_string(),
+    };
+    let local = match local.split_once('+') {
+        Some((untagged, _)) if policy.strip_plus_tags && !untagged.is_empty() => untagged,
+        _ => local,
+    };
+    format!("{local}@{}", domain.to_lowercase())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatibility_forms_are_normalized() {
+        assert_eq!(canonical_name("ａｌｉｃｅ"), "alice");
+        assert_eq!(canonical_name("ﬁona"), canonical_name("fiona"));
+        // a combining accent is the same name as the precomposed letter
+        assert_eq!(canonical_name("jose\u{301}"), canonical_name("jos\u{e9}"));
+    }
+
+    #[test]
+    fn case_is_folded() {
+        assert_eq!(canonical_name("Alice"), canonical_name("alice"));
+        assert_eq!(canonical_name("ALICE"), canonical_name("alice"));
+        assert_eq!(canonical_name("Straße"), canonical_name("STRASSE"));
+    }
+
+    #[test]
+    fn confusables_share_a_name() {
+        assert_eq!(canonical_name("\u{430}lice"), canonical_name("alice"));
+        assert_eq!(canonical_name("b0b"), canonical_name("Bob"));
+        assert_eq!(canonical_name("rn"), canonical_name("m"));
+    }
+
+    #[test]
+    fn different_names_stay_apart() {
+        assert_ne!(canonical_name("alice"), canonical_name("alicia"));
+        assert_ne!(canonical_name("bob"), canonical_name("bob2"));
+    }
+
+    #[test]
+    fn email_keeps_the_case_of_its_local_part() {
+        let strip = EmailPolicy {
+            strip_plus_tags: true,
+        };
+        let keep = EmailPolicy {
+            strip_plus_tags: false,
+        };
+        assert_eq!(
+            canonical_email("Alice+news@Example.com", strip),
+            "Alice@example.com"
+        );
+        assert_eq!(
+            canonical_email(" Alice+news@Example.com ", keep),
+            "Alice+news@example.com"
+        );
+        assert_eq!(
+            canonical_email("+news@example.com", strip),
+            "+news@example.com"
+        );
+    }
+
+    #[test]
+    fn canonical_name_is_stable() {
+        for name in ["Alice", "ａｌｉｃｅ", "b0b", "Straße", "\u{430}lice"] {
+            let canonical = canonical_name(name);
+            assert_eq!(canonical_name(&canonical), canonical);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::*;
+    use crate::security::{gen_128bit, stringify_u128_base64, watched_file::WatchedFile};
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+}}
+
+/// The secrets CSRF tokens are signed with. New tokens use the current key, tokens signed
+/// with a previous key stay valid until it is rotated out.
+///
+/// With a key file every instance sharing the file uses the same keys, and an instance
+/// picks up a rotation done by another one within `CSRF_KEY_FILE_CHECK_SECS`.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct CsrfKeys {
+    ring: Arc<RwLock<KeyRing>>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+struct KeyRing {
+    current: u128,
+    /// newest first, at most `CSRF_PREVIOUS_KEYS_KEPT`
+    previous: Vec<u128>,
+    file: Option<WatchedFile>,
+}
+
+#[cfg(feature = "ssr")]
+impl CsrfKeys {
+    /// Keys that only live as long as the process, tokens do not survive a restart.
+    pub fn ephemeral() -> Self {
+        CsrfKeys::with_ring(KeyRing {
+            current: gen_128bit(),
+            previous: Vec::new(),
+            file: None,
+        })
+    }
+
+    /// Keys from configuration, `current` and `previous` written like `stringify_u128_base64`.
+    pub fn from_secrets(current: &str, previous: &[&str]) -> Result<Self, String> {
+        Ok(CsrfKeys::with_ring(KeyRing {
+            current: parse_key(current)?,
+            previous: previous
+                .iter()
+                .map(|key| parse_key(key))
+                .collect::<Result<Vec<u128>, String>>()?,
+            file: None,
+        }))
+    }
+
+    /// Keys from a file with the current key on its first line and previous keys on the
+    /// lines after it. A missing file is created with a new key.
+    pub fn from_file(file: PathBuf) -> Result<Self, String> {
+        if !file.exists() {
+            log::info!("creating csrf key file {}", file.display());
+            write_key_file(&file, gen_128bit(), &[])?;
+        }
+        let (current, previous) = read_key_file(&file)?;
+        Ok(CsrfKeys::with_ring(KeyRing {
+            current,
+            previous,
+            file: Some(WatchedFile::new(
+                file,
+                Duration::from_secs(CSRF_KEY_FILE_CHECK_SECS),
+            )),
+        }))
+    }
+
+    fn with_ring(ring: KeyRing) -> Self {
+        CsrfKeys {
+            ring: Arc::new(RwLock::new(ring)),
+        }
+    }
+
+    /// The key new tokens are signed with.
+    pub fn current(&self) -> String {
+        self.refresh();
+        stringify_u128_base64(self.read().current)
+    }
+
+    /// Every key a token may have been signed with, the current one first.
+    pub fn accepted(&self) -> Vec<String> {
+        self.refresh();
+        let ring = self.read();
+        std::iter::once(&ring.current)
+            .chain(ring.previous.iter())
+            .map(|key| stringify_u128_base64(*key))
+            .collect()
+    }
+
+    /// Replaces the current key with a new one, keeping the old one to validate tokens that
+    /// are already in open tabs. Only keys from a key file can be rotated, keys from the
+    /// environment would come back at the next restart and differ between instances.
+    ///
+    /// The file is read again first, so a rotation another instance did since this one last
+    /// checked is kept.
+    pub fn rotate(&self) -> Result<(), AdminError> {
+        let mut ring = self.ring.write().expect("csrf key lock to not be poisoned");
+        let file = match &ring.file {
+            Some(file) => file.path().to_path_buf(),
+            None => return Err(AdminError::CsrfKeyFileMissing),
+        };
+        let rotated = read_key_file(&file).and_then(|(current, mut previous)| {
+            previous.insert(0, current);
+            previous.truncate(CSRF_PREVIOUS_KEYS_KEPT);
+            let current = gen_128bit();
+            write_key_file(&file, current, &previous)?;
+            Ok((current, previous))
+        });
+        match rotated {
+            Ok((current, previous)) => {
+                ring.current = current;
+                ring.previous = previous;
+                if let Some(file) = ring.file.as_mut() {
+                    file.mark_read();
+                }
+                Ok(())
+            }
+            Err(e) => {
+                log::error!("csrf key rotation failed: {e}");
+                Err(AdminError::CsrfKeyRotation)
+            }
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, KeyRing> {
+        self.ring.read().expect("csrf key lock to not be poisoned")
+    }
+
+    /// Rereads the key file when another instance rotated it, checking at most every
+    /// `CSRF_KEY_FILE_CHECK_SECS`.
+    fn refresh(&self) {
+        if !self
+            .read()
+            .file
+            .as_ref()
+            .is_some_and(WatchedFile::check_due)
+        {
+            return;
+        }
+        let mut ring = self.ring.write().expect("csrf key lock to not be poisoned");
+        let file = match ring.file.as_mut().and_then(WatchedFile::changed) {
+            Some(file) => file.to_path_buf(),
+            None => return,
+        };
+        match read_key_file(&file) {
+            Ok((current, previous)) => {
+                log::info!("csrf keys reloaded from {}", file.display());
+                ring.current = current;
+                ring.previous = previous;
+            }
+            // the old keys keep working until the file is fixed
+            Err(e) => log::error!("could not reload csrf keys: {e}"),
+        }
+    }
+}
+
+/// Reads a 16 byte key written like `stringify_u128_base64`.
+#[cfg(feature = "ssr")]
+pub fn parse_key(key: &str) -> Result<u128, String> {
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    let bytes = base64::Engine::decode(&CUSTOM_ENGINE, key.trim())
+        .map_err(|e| format!("key is not valid base64: {e}"))?;
+    let bytes: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| "key is not 16 bytes long".to_string())?;
+    Ok(u128::from_be_bytes(bytes))
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn read_key_file(file: &Path) -> Result<(u128, Vec<u128>), String> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| format!("could not read key file {}: {e}", file.display()))?;
+    let mut keys = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(parse_key);
+    let current = keys
+        .next()
+        .ok_or(format!("key file {} is empty", file.display()))??;
+    Ok((current, keys.collect::<Result<Vec<u128>, String>>()?))
+}
+
+/// Replaces the key file in one rename, so other instances never read half of it.
+#[cfg(feature = "ssr")]
+pub(crate) fn write_key_file(
+    file: &Path,
+    current: u128,
+    previous: &[u128],
+) -> Result<(), String> {
+    let contents = std::iter::once(&current)
+        .chain(previous.iter())
+        .map(|key| stringify_u128_base64(*key) + "\n")
+        .collect::<String>();
+    let temporary = file.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&temporary)
+        .and_then(|mut opened| std::io::Write::write_all(&mut opened, contents.as_bytes()))
+        .and_then(|()| std::fs::rename(&temporary, file))
+        .map_err(|e| format!("could not write key file {}: {e}", file.display()))
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn key_file() -> PathBuf {
+        std::env::temp_dir().join(format!("csrf_keys_{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn keys_without_a_file_are_not_rotated() {
+        let keys = CsrfKeys::ephemeral();
+        let current = keys.current();
+        assert!(matches!(keys.rotate(), Err(AdminError::CsrfKeyFileMissing)));
+        assert_eq!(keys.current(), current);
+    }
+
+    #[test]
+    fn rotation_keeps_a_rotation_by_another_instance() {
+        let file = key_file();
+        let first = CsrfKeys::from_file(file.clone()).unwrap();
+        let second = CsrfKeys::from_file(file.clone()).unwrap();
+        let original = first.current();
+        first.rotate().unwrap();
+        // the second instance has not noticed the first rotation yet
+        second.rotate().unwrap();
+        // the key the first instance rotated in is kept instead of the original one
+        assert_eq!(second.accepted(), vec![second.current(), first.current()]);
+        assert!(!second.accepted().contains(&original));
+        let (current, _) = read_key_file(&file).unwrap();
+        assert_eq!(stringify_u128_base64(current), second.current());
+        std::fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::{
+        magic_link::{replace_magic_link_token_with_pool, take_magic_link_token},
+        password_reset::reset_candidates_with_pool,
+        two_factor::totp_enabled,
+    };
+    use crate::defs::*;
+    use crate::mail::{send_mail_with_mailer, Mail, Mailer};
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::security::{
+        canonical::canonical_email, gen_128bit_base64, hash_token, request_csrf_cookie,
+        validate_csrf, verification::check_login_allowed, LoginOutcome,
+    };
+    use chrono::prelude::*;
+    use http::request::Parts;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+}}
+
+/// Mails a login link to every account whose username or email is `identifier`. The link
+/// only works in the browser that asked for it, found by its `__Host-csrf` cookie.
+/// Like a password reset the lookup and mail happen after the response is sent, so neither
+/// the reply nor its timing reveals whether an account matched, and it is limited the same way.
+#[cfg(feature = "ssr")]
+pub async fn request_magic_link(csrf: String, identifier: String) -> Result<(), AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("request_magic_link: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    validate_csrf(http_req.clone(), csrf, CsrfPurpose::RequestMagicLink).await?;
+    let csrf_cookie = request_csrf_cookie(&http_req)?;
+    rate_limit(LimitedAction::MagicLink)?;
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in request_magic_link");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let mailer = match use_context::<Mailer>() {
+        Some(mailer) => Ok(mailer),
+        None => {
+            log::error!("mailer not available in context");
+            Err(MailError::SenderMissing)
+        }
+    }?;
+    let email_policy = match use_context::<ServerVars>() {
+        Some(vars) => Ok(vars.email_policy),
+        None => {
+            log::error!("request_magic_link: server vars not available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    tokio::spawn(async move {
+        let identifier = identifier.trim().to_string();
+        let email_normalized = canonical_email(&identifier, email_policy);
+        if let Err(e) =
+            mail_magic_links(identifier, email_normalized, csrf_cookie, pool, mailer).await
+        {
+            log::error!("could not send login link: {e:?}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+async fn mail_magic_links(
+    identifier: String,
+    email_normalized: String,
+    csrf_cookie: String,
+    pool: SqlitePool,
+    mailer: Mailer,
+) -> Result<(), AppError> {
+    let candidates = reset_candidates_with_pool(&identifier, &email_normalized, &pool).await?;
+    if candidates.is_empty() {
+        log::trace!("login link requested for unknown {identifier}");
+    }
+    for candidate in candidates {
+        let token = gen_128bit_base64();
+        let expire_time: DateTime<Utc> =
+            Utc::now() + chrono::Duration::seconds(MAGIC_LINK_DURATION_SECS);
+        replace_magic_link_token_with_pool(
+            candidate.user_id,
+            &hash_token(&token),
+            &csrf_cookie,
+            expire_time,
+            &pool,
+        )
+        .await?;
+        send_mail_with_mailer(
+            Mail {
+                to: candidate.email,
+                subject: format!("Log in to {SITE_NAME}"),
+                body: format!(
+                    "Someone asked for a link to log in to the account {}.\n\n\
+                     To log in open this link in the same browser you asked for it from:\n\n\
+                     https://{SITE_DOMAIN}/login/email/{token}\n\n\
+                     The link expires in {} minutes and works once. If you did not ask for \
+                     this, you can ignore this email.",
+                    candidate.username,
+                    MAGIC_LINK_DURATION_SECS / 60
+                ),
+            },
+            mailer.clone(),
+        )
+        .await?;
+        log::trace!("login link sent for {}", candidate.user_id);
+    }
+    Ok(())
+}
+
+/// Uses up a mailed login link in place of a password. The browser has to carry the
+/// `__Host-csrf` cookie the link was requested with.
+#[cfg(feature = "ssr")]
+pub async fn login_with_magic_link(
+    csrf: String,
+    token: String,
+) -> Result<LoginOutcome, AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("login_with_magic_link: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    validate_csrf(http_req.clone(), csrf, CsrfPurpose::MagicLinkLogin).await?;
+    let csrf_cookie = request_csrf_cookie(&http_req)?;
+    if csrf_cookie.is_empty() {
+        return Err(MagicLinkError::InvalidLink.into());
+    }
+    let stored = match take_magic_link_token(&hash_token(token.trim()), &csrf_cookie).await? {
+        Some(stored) => stored,
+        None => return Err(MagicLinkError::InvalidLink.into()),
+    };
+    if stored.expiry < Utc::now() {
+        return Err(MagicLinkError::InvalidLink.into());
+    }
+    let user_id = stored.user_id;
+    check_login_allowed(user_id).await?;
+    if totp_enabled(user_id).await? {
+        log::trace!("login link accepted for {user_id}, second factor required");
+        return Ok(LoginOutcome::SecondFactorRequired(user_id));
+    }
+    log::trace!("login link accepted for {user_id}");
+    Ok(LoginOutcome::Complete(user_id))
+}
@@ -0,0 +1,197 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::*;
+    use crate::security::{canonical::canonical_name, watched_file::WatchedFile};
+    use leptos::prelude::*;
+    use regex::Regex;
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+}}
+
+/// What usernames and display names may not be, loaded from NAME_POLICY_FILE and provided as
+/// context. The file is read again when it changes, checked at most every
+/// `NAME_POLICY_CHECK_SECS`, so operators can edit it without a restart.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct NamePolicy {
+    state: Arc<RwLock<PolicyState>>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+struct PolicyState {
+    rules: NameRules,
+    file: Option<WatchedFile>,
+}
+
+/// Names are compared by their canonical form, so rules also catch look-alikes.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Default)]
+struct NameRules {
+    /// canonical names no one may register
+    reserved: Vec<String>,
+    /// matched against the name as entered and against its canonical form
+    denied: Vec<Regex>,
+    /// canonical words no name may contain
+    profanity: Vec<String>,
+}
+
+#[cfg(feature = "ssr")]
+impl NameRules {
+    fn defaults() -> Self {
+        NameRules {
+            reserved: DEFAULT_RESERVED_NAMES
+                .iter()
+                .map(|name| canonical_name(name))
+                .collect(),
+            ..NameRules::default()
+        }
+    }
+
+    /// Parses a policy file with `[reserved]`, `[deny]` and `[profanity]` sections, one entry
+    /// per line. Lines starting with `#` are comments.
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut rules = NameRules::default();
+        let mut section = None;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(line.to_string());
+                continue;
+            }
+            match section.as_deref() {
+                Some("[reserved]") => rules.reserved.push(canonical_name(line)),
+                Some("[deny]") => rules.denied.push(
+                    Regex::new(line)
+                        .map_err(|e| format!("line {}: invalid pattern: {e}", number + 1))?,
+                ),
+                Some("[profanity]") => rules.profanity.push(canonical_name(line)),
+                Some(other) => {
+                    return Err(format!("line {}: unknown section {other}", number + 1))
+                }
+                None => {
+                    return Err(format!("line {}: entry outside of a section", number + 1))
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    fn check(&self, name: &str) -> Result<(), RegistrationError> {
+        let canonical = canonical_name(name);
+        if self.reserved.contains(&canonical) {
+            return Err(RegistrationError::ReservedName);
+        }
+        if self
+            .denied
+            .iter()
+            .any(|pattern| pattern.is_match(name) || pattern.is_match(&canonical))
+        {
+            return Err(RegistrationError::DeniedName);
+        }
+        if self
+            .profanity
+            .iter()
+            .any(|word| !word.is_empty() && canonical.contains(word.as_str()))
+        {
+            return Err(RegistrationError::ProfaneName);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl NamePolicy {
+    /// Only the `DEFAULT_RESERVED_NAMES`, for servers without a policy file.
+    pub fn defaults() -> Self {
+        NamePolicy::with_state(PolicyState {
+            rules: NameRules::defaults(),
+            file: None,
+        })
+    }
+
+    /// The policy in `file`, which replaces the defaults entirely.
+    pub fn from_file(file: PathBuf) -> Result<Self, String> {
+        let rules = read_policy_file(&file)?;
+        Ok(NamePolicy::with_state(PolicyState {
+            rules,
+            file: Some(WatchedFile::new(
+                file,
+                Duration::from_secs(NAME_POLICY_CHECK_SECS),
+            )),
+        }))
+    }
+
+    fn with_state(state: PolicyState) -> Self {
+        NamePolicy {
+            state: Arc::new(RwLock::new(state)),
+        }
+    }
+
+    /// Checks a username or display name against the policy.
+    pub fn check(&self, name: &str) -> Result<(), RegistrationError> {
+        self.refresh();
+        self.state
+            .read()
+            .expect("name policy lock to not be poisoned")
+            .rules
+            .check(name)
+    }
+
+    /// Rereads the policy file when it changed. A file that no longer parses is logged and
+    /// the previous rules stay in place.
+    fn refresh(&self) {
+        let check_due = self
+            .state
+            .read()
+            .expect("name policy lock to not be poisoned")
+            .file
+            .as_ref()
+            .is_some_and(WatchedFile::check_due);
+        if !check_due {
+            return;
+        }
+        let mut state = self
+            .state
+            .write()
+            .expect("name policy lock to not be poisoned");
+        let file = match state.file.as_mut().and_then(WatchedFile::changed) {
+            Some(file) => file.to_path_buf(),
+            None => return,
+        };
+        match read_policy_file(&file) {
+            Ok(rules) => {
+                log::info!("name policy reloaded from {}", file.display());
+                state.rules = rules;
+            }
+            Err(e) => log::error!("could not reload name policy, keeping the old one: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn read_policy_file(file: &Path) -> Result<NameRules, String> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| format!("could not read name policy {}: {e}", file.display()))?;
+    NameRules::parse(&contents).map_err(|e| format!("{}: {e}", file.display()))
+}
+
+/// Checks a username or display name against the `NamePolicy` of the server. Every path
+/// that sets a name goes through here.
+#[cfg(feature = "ssr")]
+pub fn check_name_policy(name: &str) -> Result<(), AppError> {
+    match use_context::<NamePolicy>() {
+        Some(policy) => Ok(policy.check(name)?),
+        None => {
+            log::error!("check_name_policy: name policy not available in context");
+            Err(RouterError::HTTPRequestMissing.into())
+        }
+    }
+}
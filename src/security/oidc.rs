@@ -0,0 +1,476 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{
+        destroy_oidc_signup_cookie, issue_oidc_signup_cookie, parse_oidc_signup_req_parts_cookie,
+    };
+    use crate::database::{
+        email_owner,
+        oidc::{
+            drop_pending_oidc_signup, link_identity, linked_user, retrieve_pending_oidc_signup,
+            store_oidc_flow, store_pending_oidc_signup, take_oidc_flow, OidcFlow,
+            PendingOidcSignup,
+        },
+        register_user,
+        two_factor::totp_enabled,
+        unique_cred_check,
+        verification::mark_email_verified,
+        UniqueCredential,
+    };
+    use crate::defs::*;
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::security::{
+        canonical::canonical_email, gen_128bit_base64, hash_token, request_csrf_cookie,
+        validate_csrf, validate_csrf_request, validate_display_name,
+        verification::check_login_allowed,
+    };
+    use chrono::prelude::*;
+    use http::request::Parts;
+    use leptos::prelude::*;
+    use openidconnect::{
+        core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+        reqwest, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet,
+        EndpointNotSet, EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+        RedirectUrl, Scope, TokenResponse,
+    };
+    use secrecy::{ExposeSecret, SecretString};
+    use std::{
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    };
+    use uuid::Uuid;
+}}
+
+/// A sign in provider as shown on the login and signup pages
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcProviderSummary {
+    pub id: String,
+    pub name: String,
+}
+
+/// How to reach one OpenID Connect provider, set with the OIDC_* variables
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug)]
+pub struct OidcProviderConfig {
+    /// short lowercase name used in urls and generated usernames, such as "google"
+    pub id: String,
+    /// shown on the sign in button
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: SecretString,
+    /// where the provider sends the browser back to
+    pub redirect_url: String,
+}
+
+/// The client `CoreClient::from_provider_metadata` builds, which knows every endpoint the
+/// authorization code flow needs once discovery found them.
+#[cfg(feature = "ssr")]
+type DiscoveredClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+/// One provider together with its discovery document. The document is fetched when first
+/// needed and again every `OIDC_METADATA_REFRESH_SECS`, so rotated signing keys are picked
+/// up without a restart.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug)]
+pub struct OidcProvider {
+    id: String,
+    name: String,
+    issuer: IssuerUrl,
+    client_id: ClientId,
+    client_secret: ClientSecret,
+    redirect_url: RedirectUrl,
+    http: reqwest::Client,
+    metadata: Arc<RwLock<Option<(CoreProviderMetadata, Instant)>>>,
+}
+
+/// Where to send the browser, and what has to be kept until it comes back
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+}
+
+/// A provider's user, as vouched for by a validated ID token
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FederatedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl OidcProvider {
+    pub fn new(config: OidcProviderConfig) -> Result<Self, String> {
+        if config.id.is_empty()
+            || config.id.len() > OIDC_PROVIDER_ID_MAX_LEN
+            || !config
+                .id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        {
+            return Err(format!(
+                "provider id {} must be 1 to {OIDC_PROVIDER_ID_MAX_LEN} lowercase letters or \
+                 digits",
+                config.id
+            ));
+        }
+        let issuer = IssuerUrl::new(config.issuer)
+            .map_err(|e| format!("issuer of {} is not a url: {e}", config.id))?;
+        let redirect_url = RedirectUrl::new(config.redirect_url)
+            .map_err(|e| format!("redirect url of {} is not a url: {e}", config.id))?;
+        // following redirects would let a provider point the server at any address
+        let http = reqwest::ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("could not build http client: {e}"))?;
+        Ok(OidcProvider {
+            id: config.id,
+            name: config.name,
+            issuer,
+            client_id: ClientId::new(config.client_id),
+            client_secret: ClientSecret::new(config.client_secret.expose_secret().to_string()),
+            redirect_url,
+            http,
+            metadata: Arc::new(RwLock::new(None)),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn summary(&self) -> OidcProviderSummary {
+        OidcProviderSummary {
+            id: self.id.clone(),
+            name: self.name.clone(),
+        }
+    }
+
+    async fn metadata(&self) -> Result<CoreProviderMetadata, OidcError> {
+        let cached = self
+            .metadata
+            .read()
+            .expect("oidc metadata lock to not be poisoned")
+            .clone();
+        if let Some((metadata, fetched)) = &cached {
+            if fetched.elapsed() < Duration::from_secs(OIDC_METADATA_REFRESH_SECS) {
+                return Ok(metadata.clone());
+            }
+        }
+        match CoreProviderMetadata::discover_async(self.issuer.clone(), &self.http).await {
+            Ok(metadata) => {
+                log::info!("discovered oidc provider {}", self.id);
+                *self
+                    .metadata
+                    .write()
+                    .expect("oidc metadata lock to not be poisoned") =
+                    Some((metadata.clone(), Instant::now()));
+                Ok(metadata)
+            }
+            Err(e) => {
+                log::error!("could not discover oidc provider {}: {e}", self.id);
+                // a provider that is briefly unreachable keeps working with the last document
+                match cached {
+                    Some((metadata, _)) => Ok(metadata),
+                    None => Err(OidcError::ProviderUnavailable),
+                }
+            }
+        }
+    }
+
+    async fn client(&self) -> Result<DiscoveredClient, OidcError> {
+        Ok(CoreClient::from_provider_metadata(
+            self.metadata().await?,
+            self.client_id.clone(),
+            Some(self.client_secret.clone()),
+        )
+        .set_redirect_uri(self.redirect_url.clone()))
+    }
+
+    /// Starts the authorization code flow with PKCE, asking for the user's email address.
+    pub async fn authorization_request(&self) -> Result<AuthorizationRequest, OidcError> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new(String::from("email")))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        })
+    }
+
+    /// Redeems the code the browser came back with and validates the ID token that comes
+    /// with the tokens: its signature, issuer, audience, expiry and nonce.
+    pub async fn verify_callback(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<FederatedIdentity, OidcError> {
+        let client = self.client().await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .map_err(|e| {
+                log::error!("oidc provider {} has no token endpoint: {e}", self.id);
+                OidcError::ProviderUnavailable
+            })?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(&self.http)
+            .await
+            .map_err(|e| {
+                log::debug!("oidc code exchange with {} failed: {e}", self.id);
+                OidcError::ExchangeFailed
+            })?;
+        let id_token = match token_response.id_token() {
+            Some(id_token) => id_token,
+            None => {
+                log::debug!("oidc provider {} sent no id token", self.id);
+                return Err(OidcError::InvalidIdToken);
+            }
+        };
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(nonce))
+            .map_err(|e| {
+                log::debug!("oidc id token from {} was rejected: {e}", self.id);
+                OidcError::InvalidIdToken
+            })?;
+        Ok(FederatedIdentity {
+            issuer: claims.issuer().as_str().to_string(),
+            subject: claims.subject().as_str().to_string(),
+            email: claims.email().map(|email| email.as_str().to_string()),
+            email_verified: claims.email_verified().unwrap_or(false),
+        })
+    }
+}
+
+/// The configured providers, provided as context
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, Default)]
+pub struct OidcProviders(pub Arc<Vec<OidcProvider>>);
+
+#[cfg(feature = "ssr")]
+impl OidcProviders {
+    pub fn get(&self, id: &str) -> Option<&OidcProvider> {
+        self.0.iter().find(|provider| provider.id == id)
+    }
+
+    pub fn summaries(&self) -> Vec<OidcProviderSummary> {
+        self.0.iter().map(OidcProvider::summary).collect()
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn use_oidc_providers() -> Result<OidcProviders, AppError> {
+    match use_context::<OidcProviders>() {
+        Some(providers) => Ok(providers),
+        None => {
+            log::error!("oidc providers not available in context");
+            Err(RouterError::HTTPRequestMissing.into())
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub fn oidc_provider_summaries() -> Result<Vec<OidcProviderSummary>, AppError> {
+    Ok(use_oidc_providers()?.summaries())
+}
+
+/// Stores a new login against the browser's csrf cookie and returns the provider url to
+/// send the browser to.
+#[cfg(feature = "ssr")]
+pub async fn start_oidc_login(csrf: String, provider_id: String) -> Result<String, AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("start_oidc_login: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    validate_csrf(http_req.clone(), csrf, CsrfPurpose::OidcLogin).await?;
+    let csrf_cookie = request_csrf_cookie(&http_req)?;
+    let providers = use_oidc_providers()?;
+    let provider = match providers.get(&provider_id) {
+        Some(provider) => provider,
+        None => return Err(OidcError::UnknownProvider.into()),
+    };
+    let request = provider.authorization_request().await?;
+    store_oidc_flow(
+        &hash_token(&request.state),
+        &OidcFlow {
+            provider: provider_id,
+            csrf_cookie,
+            pkce_verifier: request.pkce_verifier,
+            nonce: request.nonce,
+            expiry: Utc::now() + chrono::Duration::seconds(OIDC_FLOW_DURATION_SECS),
+        },
+    )
+    .await?;
+    Ok(request.url)
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OidcOutcome {
+    /// the identity is linked to an account, a session can be issued
+    Complete(Uuid),
+    /// the identity is linked to an account that also requires a second factor
+    SecondFactorRequired(Uuid),
+    /// a first time user, who has to choose a display name before an account is made
+    DisplayNameRequired,
+}
+
+/// Handles the browser coming back from a provider with `code` and `state`, or with `error`.
+#[cfg(feature = "ssr")]
+pub async fn finish_oidc_login(
+    provider_id: String,
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+) -> Result<OidcOutcome, AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("finish_oidc_login: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    let flow = match take_oidc_flow(&hash_token(&state)).await? {
+        Some(flow) => flow,
+        None => return Err(OidcError::FlowMissing.into()),
+    };
+    // only the browser that left for the provider may come back with its state
+    let csrf_cookie = request_csrf_cookie(&http_req)?;
+    if flow.provider != provider_id
+        || csrf_cookie.is_empty()
+        || flow.csrf_cookie != csrf_cookie
+        || flow.expiry < Utc::now()
+    {
+        return Err(OidcError::FlowMissing.into());
+    }
+    let code = match (code, error) {
+        (_, Some(error)) => {
+            log::debug!("oidc provider {provider_id} returned error {error}");
+            return Err(OidcError::Denied.into());
+        }
+        (Some(code), None) => code,
+        (None, None) => return Err(OidcError::Denied.into()),
+    };
+    let providers = use_oidc_providers()?;
+    let provider = match providers.get(&provider_id) {
+        Some(provider) => provider,
+        None => return Err(OidcError::UnknownProvider.into()),
+    };
+    let identity = provider
+        .verify_callback(code, flow.pkce_verifier, flow.nonce)
+        .await?;
+    if let Some(user_id) = linked_user(&identity.issuer, &identity.subject).await? {
+        check_login_allowed(user_id).await?;
+        log::trace!("oidc login for {user_id} through {provider_id}");
+        return Ok(match totp_enabled(user_id).await? {
+            true => OidcOutcome::SecondFactorRequired(user_id),
+            false => OidcOutcome::Complete(user_id),
+        });
+    }
+    // an address the provider did not verify could belong to someone else's account
+    let email = match (identity.email, identity.email_verified) {
+        (Some(email), true) => email,
+        _ => return Err(OidcError::EmailMissing.into()),
+    };
+    let token = gen_128bit_base64();
+    store_pending_oidc_signup(
+        &hash_token(&token),
+        &PendingOidcSignup {
+            provider: provider_id,
+            issuer: identity.issuer,
+            subject: identity.subject,
+            email,
+            expiry: Utc::now() + chrono::Duration::seconds(OIDC_SIGNUP_DURATION_SECS),
+        },
+    )
+    .await?;
+    issue_oidc_signup_cookie(&token)?;
+    Ok(OidcOutcome::DisplayNameRequired)
+}
+
+/// Makes the account of a first time provider user once they chose a display name, which
+/// has to meet the same rules as at a regular signup. The account gets a generated username
+/// and no password, it signs in through the provider until a password is set by reset.
+#[cfg(feature = "ssr")]
+pub async fn complete_oidc_signup(
+    csrf: String,
+    display_name: String,
+) -> Result<Uuid, AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::OidcSignup).await?;
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("complete_oidc_signup: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    let token_hash = hash_token(&parse_oidc_signup_req_parts_cookie(http_req));
+    let signup = match retrieve_pending_oidc_signup(&token_hash).await? {
+        Some(signup) if signup.expiry >= Utc::now() => signup,
+        _ => return Err(OidcError::SignupMissing.into()),
+    };
+    validate_display_name(&display_name)?;
+    //only attempts that passed the checks above count towards the limit
+    rate_limit(LimitedAction::Signup)?;
+    unique_cred_check(UniqueCredential::DisplayName(display_name.clone())).await?;
+    let email_policy = match use_context::<ServerVars>() {
+        Some(vars) => Ok(vars.email_policy),
+        None => {
+            log::error!("complete_oidc_signup: server vars not available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    let email_normalized = canonical_email(&signup.email, email_policy);
+    if email_owner(&email_normalized).await?.is_some() {
+        return Err(OidcError::EmailTaken.into());
+    }
+    // claimed before the account is made, so a signup completed twice makes one account
+    if !drop_pending_oidc_signup(&token_hash).await? {
+        return Err(OidcError::SignupMissing.into());
+    }
+    destroy_oidc_signup_cookie();
+    let username: String = format!("{}-{}", signup.provider, gen_128bit_base64())
+        .chars()
+        .take(USERNAME_MAX_LEN)
+        .collect();
+    let user_id = register_user(
+        username,
+        display_name,
+        signup.email.clone(),
+        email_normalized,
+        String::new(),
+    )
+    .await?;
+    link_identity(&signup.issuer, &signup.subject, &signup.provider, user_id).await?;
+    mark_email_verified(user_id, &signup.email).await?;
+    log::trace!("oidc signup for {user_id} through {}", signup.provider);
+    Ok(user_id)
+}
@@ -0,0 +1,681 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::require_session;
+    use crate::database::{
+        passkeys::{
+            delete_passkey, insert_passkey, retrieve_passkeys, store_passkey_challenge_with_pool,
+            take_passkey_challenge_with_pool, update_passkey,
+        },
+        user_data, username_for_id,
+    };
+    use crate::defs::*;
+    use crate::security::{
+        api_tokens::reject_bearer_request, request_csrf_cookie, validate_csrf,
+        verification::check_login_allowed,
+    };
+    use chrono::prelude::*;
+    use http::request::Parts;
+    use leptos::prelude::*;
+    use serde::de::DeserializeOwned;
+    use sqlx::SqlitePool;
+    use std::sync::Arc;
+    use uuid::Uuid;
+    use webauthn_rs::prelude::{
+        CreationChallengeResponse, DiscoverableAuthentication, DiscoverableKey,
+        Passkey, PasskeyRegistration, PublicKeyCredential, RegisterPublicKeyCredential,
+        RequestChallengeResponse, Url, Webauthn, WebauthnBuilder,
+    };
+}}
+
+/// A registered passkey as shown on the settings page
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasskeySummary {
+    pub credential_id: String,
+    pub name: String,
+    pub created: String,
+    pub last_used: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+const REGISTER_CEREMONY: &str = "register";
+#[cfg(feature = "ssr")]
+const LOGIN_CEREMONY: &str = "login";
+
+#[cfg(feature = "ssr")]
+pub fn build_webauthn() -> Webauthn {
+    webauthn_for(SITE_DOMAIN)
+}
+
+#[cfg(feature = "ssr")]
+fn webauthn_for(site_domain: &str) -> Webauthn {
+    // the relying party id is the host name the browser sees, without a port
+    let rp_id = site_domain.split(':').next().unwrap_or(site_domain);
+    let rp_origin =
+        Url::parse(&format!("https://{site_domain}")).expect("SITE_DOMAIN to be a valid host");
+    WebauthnBuilder::new(rp_id, &rp_origin)
+        .expect("SITE_DOMAIN to be a valid webauthn relying party")
+        .rp_name(SITE_NAME)
+        .build()
+        .expect("webauthn configuration to be valid")
+}
+
+#[cfg(feature = "ssr")]
+fn use_webauthn() -> Result<Arc<Webauthn>, PasskeyError> {
+    match use_context::<Arc<Webauthn>>() {
+        Some(webauthn) => Ok(webauthn),
+        None => {
+            log::error!("webauthn not available in context");
+            Err(PasskeyError::WebauthnMissing)
+        }
+    }
+}
+
+/// Validates the csrf token and returns the cookie it was checked against.
+/// Passkey ceremonies are stored against that cookie, so only the browser that started a
+/// ceremony can finish it. Requests without the cookie, such as ones made with an access
+/// token, would all share one ceremony and are refused.
+#[cfg(feature = "ssr")]
+async fn validate_csrf_binding(
+    csrf: String,
+    purpose: CsrfPurpose,
+) -> Result<String, AppError> {
+    reject_bearer_request()?;
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("validate_csrf_binding: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    validate_csrf(http_req.clone(), csrf, purpose).await?;
+    match request_csrf_cookie(&http_req)? {
+        csrf_cookie if csrf_cookie.is_empty() => Err(CsrfError::NoMatchingCookie.into()),
+        csrf_cookie => Ok(csrf_cookie),
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn encode_credential_id<T: AsRef<[u8]>>(credential_id: T) -> String {
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    base64::Engine::encode(&CUSTOM_ENGINE, credential_id)
+}
+
+#[cfg(feature = "ssr")]
+fn use_pool() -> Result<SqlitePool, DatabaseError> {
+    match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available for a passkey ceremony");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+async fn store_challenge<T: Serialize>(
+    csrf_cookie: &String,
+    ceremony: &str,
+    user_id: Option<Uuid>,
+    state: &T,
+) -> Result<(), AppError> {
+    store_challenge_with_pool(csrf_cookie, ceremony, user_id, state, &use_pool()?).await
+}
+
+#[cfg(feature = "ssr")]
+async fn store_challenge_with_pool<T: Serialize>(
+    csrf_cookie: &String,
+    ceremony: &str,
+    user_id: Option<Uuid>,
+    state: &T,
+    pool: &SqlitePool,
+) -> Result<(), AppError> {
+    let state = match serde_json::to_string(state) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("could not serialize passkey {ceremony} state: {e}");
+            return Err(PasskeyError::VerificationFailed.into());
+        }
+    };
+    let expire_time: DateTime<Utc> =
+        Utc::now() + chrono::Duration::seconds(PASSKEY_CHALLENGE_DURATION_SECS);
+    Ok(store_passkey_challenge_with_pool(
+        csrf_cookie,
+        ceremony,
+        user_id,
+        state,
+        expire_time,
+        pool,
+    )
+    .await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn take_challenge<T: DeserializeOwned>(
+    csrf_cookie: &String,
+    ceremony: &str,
+) -> Result<(Option<Uuid>, T), AppError> {
+    take_challenge_with_pool(csrf_cookie, ceremony, &use_pool()?).await
+}
+
+#[cfg(feature = "ssr")]
+async fn take_challenge_with_pool<T: DeserializeOwned>(
+    csrf_cookie: &String,
+    ceremony: &str,
+    pool: &SqlitePool,
+) -> Result<(Option<Uuid>, T), AppError> {
+    let challenge = match take_passkey_challenge_with_pool(csrf_cookie, ceremony, pool).await?
+    {
+        Some(challenge) => challenge,
+        None => return Err(PasskeyError::ChallengeMissing.into()),
+    };
+    if challenge.expiry < Utc::now() {
+        return Err(PasskeyError::ChallengeMissing.into());
+    }
+    match serde_json::from_str(&challenge.state) {
+        Ok(state) => Ok((challenge.user_id, state)),
+        Err(e) => {
+            log::error!("could not deserialize passkey {ceremony} state: {e}");
+            Err(PasskeyError::ChallengeMissing.into())
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn parse_passkey(stored: &str) -> Result<Passkey, PasskeyError> {
+    serde_json::from_str(stored).map_err(|e| {
+        //database is possibly corrupted
+        log::error!("could not deserialize stored passkey: {e}");
+        PasskeyError::VerificationFailed
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub async fn begin_passkey_registration(
+    csrf: String,
+) -> Result<CreationChallengeResponse, AppError> {
+    let csrf_cookie = validate_csrf_binding(csrf, CsrfPurpose::PasskeyRegistration).await?;
+    let user_id = require_session().await?;
+    let webauthn = use_webauthn()?;
+    let username = username_for_id(user_id).await?;
+    let display_name = user_data(user_id).await?.display_name;
+    // stop the browser from registering the same authenticator twice
+    let mut existing = Vec::new();
+    for stored in retrieve_passkeys(user_id).await? {
+        existing.push(parse_passkey(&stored.passkey)?.cred_id().clone());
+    }
+    let (challenge, state) = webauthn
+        .start_passkey_registration(user_id, &username, &display_name, Some(existing))
+        .map_err(|e| {
+            log::error!("could not start passkey registration: {e}");
+            PasskeyError::VerificationFailed
+        })?;
+    store_challenge::<PasskeyRegistration>(
+        &csrf_cookie,
+        REGISTER_CEREMONY,
+        Some(user_id),
+        &state,
+    )
+    .await?;
+    Ok(challenge)
+}
+
+#[cfg(feature = "ssr")]
+pub async fn complete_passkey_registration(
+    csrf: String,
+    name: String,
+    credential: RegisterPublicKeyCredential,
+) -> Result<(), AppError> {
+    let csrf_cookie = validate_csrf_binding(csrf, CsrfPurpose::PasskeyRegistration).await?;
+    let user_id = require_session().await?;
+    let webauthn = use_webauthn()?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > PASSKEY_NAME_MAX_LEN {
+        return Err(PasskeyError::NameLength.into());
+    }
+    let (challenge_user, state) =
+        take_challenge::<PasskeyRegistration>(&csrf_cookie, REGISTER_CEREMONY).await?;
+    if challenge_user != Some(user_id) {
+        return Err(PasskeyError::ChallengeMissing.into());
+    }
+    let passkey = webauthn
+        .finish_passkey_registration(&credential, &state)
+        .map_err(|e| {
+            log::debug!("passkey registration for {user_id} failed: {e}");
+            PasskeyError::VerificationFailed
+        })?;
+    let credential_id = encode_credential_id(passkey.cred_id());
+    let passkey = match serde_json::to_string(&passkey) {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            log::error!("could not serialize passkey: {e}");
+            return Err(PasskeyError::VerificationFailed.into());
+        }
+    };
+    insert_passkey(&credential_id, user_id, &name, passkey).await?;
+    log::trace!("passkey {credential_id} registered for {user_id}");
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub async fn begin_passkey_login(csrf: String) -> Result<RequestChallengeResponse, AppError> {
+    let csrf_cookie = validate_csrf_binding(csrf, CsrfPurpose::PasskeyLogin).await?;
+    let webauthn = use_webauthn()?;
+    // discoverable credentials let the authenticator pick the account, so the login never
+    // reveals whether a username has passkeys
+    let (mut challenge, state) =
+        webauthn.start_discoverable_authentication().map_err(|e| {
+            log::error!("could not start passkey login: {e}");
+            PasskeyError::VerificationFailed
+        })?;
+    // the login page asks from a button press, not through form autofill
+    challenge.mediation = None;
+    store_challenge::<DiscoverableAuthentication>(&csrf_cookie, LOGIN_CEREMONY, None, &state)
+        .await?;
+    Ok(challenge)
+}
+
+#[cfg(feature = "ssr")]
+pub async fn complete_passkey_login(
+    csrf: String,
+    credential: PublicKeyCredential,
+) -> Result<Uuid, AppError> {
+    let csrf_cookie = validate_csrf_binding(csrf, CsrfPurpose::PasskeyLogin).await?;
+    let webauthn = use_webauthn()?;
+    let (_, state) =
+        take_challenge::<DiscoverableAuthentication>(&csrf_cookie, LOGIN_CEREMONY).await?;
+    let (user_id, credential_id) = webauthn
+        .identify_discoverable_authentication(&credential)
+        .map_err(|e| {
+            log::debug!("passkey login did not identify a user: {e}");
+            PasskeyError::VerificationFailed
+        })?;
+    let credential_id = encode_credential_id(credential_id);
+    let stored = match retrieve_passkeys(user_id)
+        .await?
+        .into_iter()
+        .find(|stored| stored.credential_id == credential_id)
+    {
+        Some(stored) => stored,
+        None => {
+            log::debug!("passkey login with unknown credential {credential_id}");
+            return Err(PasskeyError::VerificationFailed.into());
+        }
+    };
+    let mut passkey = parse_passkey(&stored.passkey)?;
+    let result = webauthn
+        .finish_discoverable_authentication(
+            &credential,
+            state,
+            &[DiscoverableKey::from(&passkey)],
+        )
+        .map_err(|e| {
+            log::debug!("passkey login for {user_id} failed: {e}");
+            PasskeyError::VerificationFailed
+        })?;
+    // keeps the signature counter current so cloned authenticators are detected
+    passkey.update_credential(&result);
+    match serde_json::to_string(&passkey) {
+        Ok(passkey) => update_passkey(&credential_id, passkey, Utc::now()).await?,
+        Err(e) => log::error!("could not serialize passkey: {e}"),
+    };
+    check_login_allowed(user_id).await?;
+    log::trace!("passkey login for {user_id} with {credential_id}");
+    Ok(user_id)
+}
+
+#[cfg(feature = "ssr")]
+pub async fn passkey_summaries() -> Result<Vec<PasskeySummary>, AppError> {
+    let user_id = require_session().await?;
+    Ok(retrieve_passkeys(user_id)
+        .await?
+        .into_iter()
+        .map(|stored| PasskeySummary {
+            credential_id: stored.credential_id,
+            name: stored.name,
+            created: stored.created.format("%Y-%m-%d %H:%M UTC").to_string(),
+            last_used: stored
+                .last_used
+                .map(|last_used| last_used.format("%Y-%m-%d %H:%M UTC").to_string()),
+        })
+        .collect())
+}
+
+#[cfg(feature = "ssr")]
+pub async fn remove_passkey(csrf: String, credential_id: String) -> Result<(), AppError> {
+    validate_csrf_binding(csrf, CsrfPurpose::RemovePasskey).await?;
+    let user_id = require_session().await?;
+    delete_passkey(user_id, &credential_id).await?;
+    log::trace!("passkey {credential_id} removed for {user_id}");
+    Ok(())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::database::{insert_test_user, memory_pool};
+    use openssl::{
+        bn::{BigNum, BigNumContext},
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        sign::Signer,
+    };
+    use serde_cbor_2::Value;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use std::collections::BTreeMap;
+
+    // webauthn does not accept an ip address as the relying party, as SITE_DOMAIN is in dev
+    const TEST_DOMAIN: &str = "localhost:3000";
+
+    /// A software authenticator holding one ES256 passkey, answering as a browser would.
+    struct SoftAuthenticator {
+        key: PKey<Private>,
+        credential_id: Vec<u8>,
+        counter: u32,
+    }
+
+    fn b64(bytes: &[u8]) -> String {
+        encode_credential_id(bytes)
+    }
+
+    fn client_data(kind: &str, challenge: &serde_json::Value) -> Vec<u8> {
+        json!({
+            "type": kind,
+            "challenge": challenge,
+            "origin": format!("https://{TEST_DOMAIN}"),
+            "crossOrigin": false,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            SoftAuthenticator {
+                key,
+                credential_id: Uuid::new_v4().as_bytes().to_vec(),
+                counter: 0,
+            }
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.counter.to_be_bytes());
+            data
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let ec_key = self.key.ec_key().unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+            ec_key
+                .public_key()
+                .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)
+                .unwrap();
+            let key = BTreeMap::from([
+                (Value::Integer(1), Value::Integer(2)),
+                (Value::Integer(3), Value::Integer(-7)),
+                (Value::Integer(-1), Value::Integer(1)),
+                (
+                    Value::Integer(-2),
+                    Value::Bytes(x.to_vec_padded(32).unwrap()),
+                ),
+                (
+                    Value::Integer(-3),
+                    Value::Bytes(y.to_vec_padded(32).unwrap()),
+                ),
+            ]);
+            serde_cbor_2::to_vec(&Value::Map(key)).unwrap()
+        }
+
+        /// Answers `navigator.credentials.create` with a "none" attestation.
+        fn register(
+            &self,
+            challenge: &CreationChallengeResponse,
+        ) -> RegisterPublicKeyCredential {
+            let options = serde_json::to_value(challenge).unwrap();
+            let rp_id = options["publicKey"]["rp"]["id"].as_str().unwrap();
+            // user present, user verified, attested credential data included
+            let mut auth_data = self.authenticator_data(rp_id, 0x45);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            auth_data.extend_from_slice(&self.cose_key());
+            let attestation = BTreeMap::from([
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(BTreeMap::new())),
+                (Value::Text("authData".into()), Value::Bytes(auth_data)),
+            ]);
+            let attestation = serde_cbor_2::to_vec(&Value::Map(attestation)).unwrap();
+            let client_data =
+                client_data("webauthn.create", &options["publicKey"]["challenge"]);
+            serde_json::from_value(json!({
+                "id": b64(&self.credential_id),
+                "rawId": b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": b64(&attestation),
+                    "clientDataJSON": b64(&client_data),
+                },
+                "extensions": {},
+            }))
+            .unwrap()
+        }
+
+        /// Answers `navigator.credentials.get` for the discoverable credential of `user_id`.
+        fn assert(
+            &mut self,
+            challenge: &RequestChallengeResponse,
+            user_id: Uuid,
+        ) -> PublicKeyCredential {
+            let options = serde_json::to_value(challenge).unwrap();
+            let rp_id = options["publicKey"]["rpId"].as_str().unwrap();
+            self.counter += 1;
+            // user present, user verified
+            let auth_data = self.authenticator_data(rp_id, 0x05);
+            let client_data = client_data("webauthn.get", &options["publicKey"]["challenge"]);
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(&auth_data).unwrap();
+            signer.update(&Sha256::digest(&client_data)).unwrap();
+            let signature = signer.sign_to_vec().unwrap();
+            serde_json::from_value(json!({
+                "id": b64(&self.credential_id),
+                "rawId": b64(&self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": b64(&auth_data),
+                    "clientDataJSON": b64(&client_data),
+                    "signature": b64(&signature),
+                    "userHandle": b64(user_id.as_bytes()),
+                },
+                "extensions": {},
+            }))
+            .unwrap()
+        }
+    }
+
+    /// Registers a passkey for `user_id` through the challenge store, as the settings page does.
+    async fn register(
+        webauthn: &Webauthn,
+        authenticator: &SoftAuthenticator,
+        user_id: Uuid,
+        csrf_cookie: &String,
+        pool: &SqlitePool,
+    ) -> Passkey {
+        let (challenge, state) = webauthn
+            .start_passkey_registration(user_id, "alice", "Alice", None)
+            .unwrap();
+        store_challenge_with_pool(csrf_cookie, REGISTER_CEREMONY, Some(user_id), &state, pool)
+            .await
+            .unwrap();
+        let credential = authenticator.register(&challenge);
+        let (challenge_user, state) = take_challenge_with_pool::<PasskeyRegistration>(
+            csrf_cookie,
+            REGISTER_CEREMONY,
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(challenge_user, Some(user_id));
+        webauthn
+            .finish_passkey_registration(&credential, &state)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn registers_and_logs_in_with_a_soft_authenticator() {
+        let pool = memory_pool().await;
+        let user_id = insert_test_user("alice", &pool).await;
+        let webauthn = webauthn_for(TEST_DOMAIN);
+        let csrf_cookie = String::from("browser-cookie");
+        let mut authenticator = SoftAuthenticator::new();
+        let mut passkey =
+            register(&webauthn, &authenticator, user_id, &csrf_cookie, &pool).await;
+        assert_eq!(
+            encode_credential_id(passkey.cred_id()),
+            b64(&authenticator.credential_id)
+        );
+        // the stored form is what later logins read back
+        let passkey_json = serde_json::to_string(&passkey).unwrap();
+        assert_eq!(
+            parse_passkey(&passkey_json).unwrap().cred_id(),
+            passkey.cred_id()
+        );
+
+        let (challenge, state) = webauthn.start_discoverable_authentication().unwrap();
+        store_challenge_with_pool(&csrf_cookie, LOGIN_CEREMONY, None, &state, &pool)
+            .await
+            .unwrap();
+        let credential = authenticator.assert(&challenge, user_id);
+        let (_, state) = take_challenge_with_pool::<DiscoverableAuthentication>(
+            &csrf_cookie,
+            LOGIN_CEREMONY,
+            &pool,
+        )
+        .await
+        .unwrap();
+        let (identified, credential_id) = webauthn
+            .identify_discoverable_authentication(&credential)
+            .unwrap();
+        assert_eq!(identified, user_id);
+        assert_eq!(
+            encode_credential_id(credential_id),
+            b64(&authenticator.credential_id)
+        );
+        let result = webauthn
+            .finish_discoverable_authentication(
+                &credential,
+                state,
+                &[DiscoverableKey::from(&passkey)],
+            )
+            .unwrap();
+        assert!(result.user_verified());
+        assert_eq!(result.counter(), 1);
+        assert_eq!(passkey.update_credential(&result), Some(true));
+    }
+
+    #[tokio::test]
+    async fn replayed_challenge_is_refused() {
+        let pool = memory_pool().await;
+        let user_id = insert_test_user("alice", &pool).await;
+        let webauthn = webauthn_for(TEST_DOMAIN);
+        let csrf_cookie = String::from("browser-cookie");
+        let mut authenticator = SoftAuthenticator::new();
+        let passkey = register(&webauthn, &authenticator, user_id, &csrf_cookie, &pool).await;
+
+        let (challenge, state) = webauthn.start_discoverable_authentication().unwrap();
+        store_challenge_with_pool(&csrf_cookie, LOGIN_CEREMONY, None, &state, &pool)
+            .await
+            .unwrap();
+        let credential = authenticator.assert(&challenge, user_id);
+        let (_, state) = take_challenge_with_pool::<DiscoverableAuthentication>(
+            &csrf_cookie,
+            LOGIN_CEREMONY,
+            &pool,
+        )
+        .await
+        .unwrap();
+        webauthn
+            .finish_discoverable_authentication(
+                &credential,
+                state,
+                &[DiscoverableKey::from(&passkey)],
+            )
+            .unwrap();
+
+        // the ceremony was removed when it was taken, it cannot be finished a second time
+        let replay = take_challenge_with_pool::<DiscoverableAuthentication>(
+            &csrf_cookie,
+            LOGIN_CEREMONY,
+            &pool,
+        )
+        .await;
+        assert!(matches!(
+            replay,
+            Err(AppError::Passkey(PasskeyError::ChallengeMissing))
+        ));
+
+        // nor does the signed answer work against a newer challenge
+        let (_, state) = webauthn.start_discoverable_authentication().unwrap();
+        assert!(webauthn
+            .finish_discoverable_authentication(
+                &credential,
+                state,
+                &[DiscoverableKey::from(&passkey)],
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn challenge_of_another_csrf_cookie_is_refused() {
+        let pool = memory_pool().await;
+        let user_id = insert_test_user("alice", &pool).await;
+        let webauthn = webauthn_for(TEST_DOMAIN);
+        let started_by = String::from("browser-cookie");
+        let finished_by = String::from("attacker-cookie");
+        let authenticator = SoftAuthenticator::new();
+
+        let (challenge, state) = webauthn
+            .start_passkey_registration(user_id, "alice", "Alice", None)
+            .unwrap();
+        store_challenge_with_pool(
+            &started_by,
+            REGISTER_CEREMONY,
+            Some(user_id),
+            &state,
+            &pool,
+        )
+        .await
+        .unwrap();
+        authenticator.register(&challenge);
+        let taken = take_challenge_with_pool::<PasskeyRegistration>(
+            &finished_by,
+            REGISTER_CEREMONY,
+            &pool,
+        )
+        .await;
+        assert!(matches!(
+            taken,
+            Err(AppError::Passkey(PasskeyError::ChallengeMissing))
+        ));
+        // the browser that started the ceremony can still finish it
+        let (challenge_user, _) = take_challenge_with_pool::<PasskeyRegistration>(
+            &started_by,
+            REGISTER_CEREMONY,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(challenge_user, Some(user_id));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::defs::PASSWORD_MIN_SCORE;
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::defs::*;
+    use secrecy::{ExposeSecret, SecretString};
+    use sha1::{Digest, Sha1};
+    use std::{path::Path, sync::OnceLock};
+}}
+
+/// Only this many characters are estimated, zxcvbn slows down quickly on long input and
+/// anything longer is far past the minimum score anyway
+const ESTIMATE_MAX_CHARS: usize = 100;
+
+/// How guessable a password is, the same estimate on the server and in the browser.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordStrength {
+    /// 0 to 4, see `PASSWORD_MIN_SCORE`
+    pub score: u8,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl PasswordStrength {
+    pub fn acceptable(&self) -> bool {
+        self.score >= PASSWORD_MIN_SCORE
+    }
+
+    /// The warning and suggestions as one sentence list.
+    pub fn feedback(&self) -> String {
+        self.warning
+            .iter()
+            .chain(self.suggestions.iter())
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Estimates the strength of `password`, which is weaker when it contains any of
+/// `user_inputs` such as the username or email address.
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> PasswordStrength {
+    let truncated: String = password.chars().take(ESTIMATE_MAX_CHARS).collect();
+    let entropy = zxcvbn::zxcvbn(&truncated, user_inputs);
+    let feedback = entropy.feedback();
+    PasswordStrength {
+        score: entropy.score().into(),
+        warning: feedback
+            .and_then(|feedback| feedback.warning())
+            .map(|warning| warning.to_string()),
+        suggestions: feedback
+            .map(|feedback| {
+                feedback
+                    .suggestions()
+                    .iter()
+                    .map(|suggestion| suggestion.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Leading bytes of the SHA-1 of every breached password, sorted so lookups are a binary
+/// search. Eight bytes keep the list small while a false match stays vanishingly unlikely.
+#[cfg(feature = "ssr")]
+static BREACHED_PASSWORDS: OnceLock<Vec<u64>> = OnceLock::new();
+
+/// Loads a breached password list from `path`, one hex SHA-1 hash or hash prefix of at least
+/// 16 digits per line, in the format of the Pwned Passwords download: "HASH:COUNT" lines
+/// are accepted and the count is ignored. Returns how many hashes were loaded.
+#[cfg(feature = "ssr")]
+pub fn load_breached_passwords(path: &Path) -> Result<usize, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    let mut prefixes = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let hash = line.split(':').next().unwrap_or_default();
+            hash.get(..16)
+                .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+                .ok_or(format!("{line} does not start with a SHA-1 hash"))
+        })
+        .collect::<Result<Vec<u64>, String>>()?;
+    // the list should already be sorted, sorting again is cheap compared to reading it
+    prefixes.sort_unstable();
+    prefixes.dedup();
+    let count = prefixes.len();
+    BREACHED_PASSWORDS
+        .set(prefixes)
+        .map_err(|_| "breached passwords were already loaded".to_string())?;
+    Ok(count)
+}
+
+/// Whether `password` is on the breached password list, always false without a list.
+#[cfg(feature = "ssr")]
+fn is_breached(password: &SecretString) -> bool {
+    let prefixes = match BREACHED_PASSWORDS.get() {
+        Some(prefixes) => prefixes,
+        None => return false,
+    };
+    let digest = Sha1::digest(password.expose_secret().as_bytes());
+    let mut prefix = [0_u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    prefixes.binary_search(&u64::from_be_bytes(prefix)).is_ok()
+}
+
+/// Rejects passwords that are on the breached password list or are too easy to guess.
+#[cfg(feature = "ssr")]
+pub fn check_password_policy(
+    password: &SecretString,
+    user_inputs: &[&str],
+) -> Result<(), RegistrationError> {
+    if is_breached(password) {
+        log::trace!("rejected a password found in the breached password list");
+        return Err(RegistrationError::BreachedPassword);
+    }
+    let strength = estimate_strength(password.expose_secret(), user_inputs);
+    if !strength.acceptable() {
+        return Err(RegistrationError::WeakPassword(strength.feedback()));
+    }
+    Ok(())
+}
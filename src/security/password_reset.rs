@@ -0,0 +1,130 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::end_user_sessions;
+    use crate::database::{
+        password_reset::{
+            replace_reset_token_with_pool, reset_candidates_with_pool, take_reset_token,
+        },
+        update_password_hash,
+    };
+    use crate::defs::*;
+    use crate::mail::{send_mail_with_mailer, Mail, Mailer};
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::security::{
+        canonical::canonical_email, gen_128bit_base64, gen_hash, hash_token,
+        validate_csrf_request, validate_new_password,
+    };
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use secrecy::SecretString;
+    use sqlx::SqlitePool;
+}}
+
+/// Mails a reset link to every account whose username or email is `identifier`.
+/// The lookup and mail happen after the response is sent, so neither the reply nor its
+/// timing reveals whether an account matched. Limited per browser and address, every
+/// request can send mail to someone else.
+#[cfg(feature = "ssr")]
+pub async fn request_password_reset(csrf: String, identifier: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ForgotPassword).await?;
+    rate_limit(LimitedAction::PasswordReset)?;
+    let pool = match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available in request_password_reset");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }?;
+    let mailer = match use_context::<Mailer>() {
+        Some(mailer) => Ok(mailer),
+        None => {
+            log::error!("mailer not available in context");
+            Err(MailError::SenderMissing)
+        }
+    }?;
+    let email_policy = match use_context::<ServerVars>() {
+        Some(vars) => Ok(vars.email_policy),
+        None => {
+            log::error!("request_password_reset: server vars not available");
+            Err(RouterError::HTTPRequestMissing)
+        }
+    }?;
+    tokio::spawn(async move {
+        let identifier = identifier.trim().to_string();
+        let email_normalized = canonical_email(&identifier, email_policy);
+        if let Err(e) = mail_reset_links(identifier, email_normalized, pool, mailer).await {
+            log::error!("could not send password reset: {e:?}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+async fn mail_reset_links(
+    identifier: String,
+    email_normalized: String,
+    pool: SqlitePool,
+    mailer: Mailer,
+) -> Result<(), AppError> {
+    let candidates = reset_candidates_with_pool(&identifier, &email_normalized, &pool).await?;
+    if candidates.is_empty() {
+        log::trace!("password reset requested for unknown {identifier}");
+    }
+    for candidate in candidates {
+        let token = gen_128bit_base64();
+        let expire_time: DateTime<Utc> =
+            Utc::now() + chrono::Duration::seconds(PASSWORD_RESET_DURATION_SECS);
+        replace_reset_token_with_pool(
+            candidate.user_id,
+            &hash_token(&token),
+            expire_time,
+            &pool,
+        )
+        .await?;
+        send_mail_with_mailer(
+            Mail {
+                to: candidate.email,
+                subject: format!("Reset your {SITE_NAME} password"),
+                body: format!(
+                    "Someone asked to reset the password of the account {}.\n\n\
+                     To choose a new password open this link:\n\n\
+                     https://{SITE_DOMAIN}/reset-password/{token}\n\n\
+                     The link expires in {} minutes. If you did not ask for this, you can \
+                     ignore this email and your password will stay the same.",
+                    candidate.username,
+                    PASSWORD_RESET_DURATION_SECS / 60
+                ),
+            },
+            mailer.clone(),
+        )
+        .await?;
+        log::trace!("password reset sent for {}", candidate.user_id);
+    }
+    Ok(())
+}
+
+/// Sets a new password with a mailed reset token and logs the account out everywhere.
+#[cfg(feature = "ssr")]
+pub async fn reset_password(
+    csrf: String,
+    token: String,
+    password: SecretString,
+    password_confirmation: SecretString,
+) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ResetPassword).await?;
+    // the account is only known once the token is used up, so names are not checked here
+    validate_new_password(&password, &password_confirmation, &[])?;
+    let stored = match take_reset_token(&hash_token(token.trim())).await? {
+        Some(stored) => stored,
+        None => return Err(PasswordResetError::InvalidToken.into()),
+    };
+    if stored.expiry < Utc::now() {
+        return Err(PasswordResetError::InvalidToken.into());
+    }
+    let password_hash = gen_hash(password)?;
+    update_password_hash(stored.user_id, password_hash).await?;
+    end_user_sessions(stored.user_id).await?;
+    log::trace!("password reset for {}", stored.user_id);
+    Ok(())
+}
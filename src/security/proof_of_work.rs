@@ -0,0 +1,276 @@
+use crate::defs::POW_SOLVE_CHUNK;
+use blake2::{Blake2s256, Digest};
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::proof_of_work::{redeem_challenge, redemptions_since};
+    use crate::defs::*;
+    use crate::security::{
+        csrf_keys::{read_key_file, write_key_file},
+        gen_128bit, gen_128bit_base64, hash_token, stringify_u128_base64,
+    };
+    use chrono::prelude::*;
+    use hmac::{Hmac, Mac};
+    use leptos::prelude::*;
+    use sha2::Sha256;
+    use std::path::Path;
+}}
+
+/// A signed puzzle the browser solves before it may sign up. A solution is a nonce whose
+/// hash together with `challenge` starts with `difficulty` zero bits.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+#[cfg(feature = "ssr")]
+fn pow_hash(challenge: &str, nonce: u64) -> [u8; 32] {
+    let mut hasher = Blake2s256::new();
+    hasher.update(format!("{challenge}:{nonce}").as_bytes());
+    hasher.finalize().into()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Searches `count` nonces from `start` for a solution. The hash of the challenge is only
+/// started once and the nonce is written into the same buffer every time.
+fn solve_range(challenge: &PowChallenge, start: u64, count: u64) -> Option<u64> {
+    let mut prefix = Blake2s256::new();
+    prefix.update(challenge.challenge.as_bytes());
+    prefix.update(b":");
+    let mut digits = String::with_capacity(20);
+    (start..start.saturating_add(count)).find(|nonce| {
+        digits.clear();
+        let _ = write!(digits, "{nonce}");
+        let mut hasher = prefix.clone();
+        hasher.update(digits.as_bytes());
+        leading_zero_bits(&hasher.finalize()) >= challenge.difficulty
+    })
+}
+
+/// Searches for a solution, taking about 2^difficulty hashes. Every `POW_SOLVE_CHUNK`
+/// nonces it lets the browser handle events, so the page stays responsive while it works.
+pub async fn solve_challenge(challenge: &PowChallenge) -> u64 {
+    let mut start = 0;
+    loop {
+        if let Some(nonce) = solve_range(challenge, start, POW_SOLVE_CHUNK) {
+            return nonce;
+        }
+        start += POW_SOLVE_CHUNK;
+        yield_to_browser().await;
+    }
+}
+
+/// Waits for a zero length timeout, so the event loop runs what queued up meanwhile.
+async fn yield_to_browser() {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    leptos::prelude::set_timeout(
+        move || {
+            let _ = sender.send(());
+        },
+        std::time::Duration::ZERO,
+    );
+    let _ = receiver.await;
+}
+
+/// The difficulty for the current signup volume: one more bit for every doubling of attempts
+/// past `POW_VOLUME_THRESHOLD` within `POW_VOLUME_WINDOW_SECS`.
+#[cfg(feature = "ssr")]
+fn current_difficulty(recent_attempts: i64) -> u32 {
+    if recent_attempts < POW_VOLUME_THRESHOLD {
+        return POW_BASE_DIFFICULTY;
+    }
+    let doublings = (recent_attempts / POW_VOLUME_THRESHOLD).ilog2() + 1;
+    (POW_BASE_DIFFICULTY + doublings).min(POW_MAX_DIFFICULTY)
+}
+
+/// Reads the key challenges are signed with from a file holding it on its first line, as
+/// written for `CSRF_KEY_FILE`. A missing file is created with a new key.
+#[cfg(feature = "ssr")]
+pub fn pow_secret_from_file(file: &Path) -> Result<u128, String> {
+    if !file.exists() {
+        log::info!("creating proof of work key file {}", file.display());
+        write_key_file(file, gen_128bit(), &[])?;
+    }
+    Ok(read_key_file(file)?.0)
+}
+
+#[cfg(feature = "ssr")]
+fn pow_secret() -> Result<String, AppError> {
+    match use_context::<ServerVars>() {
+        Some(vars) => Ok(stringify_u128_base64(vars.pow_secret)),
+        None => {
+            log::error!("pow_secret: servervars are not available");
+            Err(RouterError::HTTPRequestMissing.into())
+        }
+    }
+}
+
+/// The MAC of a challenge's payload.
+#[cfg(feature = "ssr")]
+fn pow_mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("hmac to accept keys of any length");
+    mac.update(format!("pow-v1|{payload}").as_bytes());
+    mac
+}
+
+#[cfg(feature = "ssr")]
+fn sign_challenge(secret: &str, payload: &str) -> String {
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    let signature = pow_mac(secret, payload).finalize().into_bytes();
+    format!(
+        "{payload}.{}",
+        base64::Engine::encode(&CUSTOM_ENGINE, signature)
+    )
+}
+
+/// The payload of a challenge signed with `secret`, the signature is compared in constant
+/// time.
+#[cfg(feature = "ssr")]
+fn verified_payload<'a>(secret: &str, challenge: &'a str) -> Option<&'a str> {
+    const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    let (payload, signature) = challenge.rsplit_once('.')?;
+    let signature = base64::Engine::decode(&CUSTOM_ENGINE, signature).ok()?;
+    pow_mac(secret, payload)
+        .verify_slice(&signature)
+        .is_ok()
+        .then_some(payload)
+}
+
+/// Issues a challenge signed with the server's `pow_secret`, so the server does not need to
+/// remember it until it is solved.
+#[cfg(feature = "ssr")]
+pub async fn issue_pow_challenge() -> Result<PowChallenge, AppError> {
+    let now = Utc::now();
+    let recent_attempts =
+        redemptions_since(now - chrono::Duration::seconds(POW_VOLUME_WINDOW_SECS)).await?;
+    let difficulty = current_difficulty(recent_attempts);
+    let expiry = now.timestamp() + POW_CHALLENGE_DURATION_SECS;
+    let payload = format!("{}.{expiry}.{difficulty}", gen_128bit_base64());
+    Ok(PowChallenge {
+        challenge: sign_challenge(&pow_secret()?, &payload),
+        difficulty,
+    })
+}
+
+/// Checks that `nonce` solves a challenge this server issued, and uses the challenge up.
+#[cfg(feature = "ssr")]
+pub async fn verify_pow_solution(challenge: String, nonce: String) -> Result<(), AppError> {
+    let payload = match verified_payload(&pow_secret()?, &challenge) {
+        Some(payload) => payload,
+        None => {
+            log::trace!("proof of work challenge with an invalid signature");
+            return Err(ProofOfWorkError::InvalidSolution.into());
+        }
+    };
+    // the signature proves these were written by issue_pow_challenge
+    let mut fields = payload.split('.').skip(1);
+    let (expiry, difficulty): (i64, u32) = match (
+        fields.next().and_then(|expiry| expiry.parse().ok()),
+        fields.next().and_then(|difficulty| difficulty.parse().ok()),
+    ) {
+        (Some(expiry), Some(difficulty)) => (expiry, difficulty),
+        _ => return Err(ProofOfWorkError::InvalidSolution.into()),
+    };
+    let now = Utc::now();
+    if expiry < now.timestamp() {
+        return Err(ProofOfWorkError::Expired.into());
+    }
+    let nonce: u64 = match nonce.parse() {
+        Ok(nonce) => nonce,
+        Err(_) => return Err(ProofOfWorkError::InvalidSolution.into()),
+    };
+    if leading_zero_bits(&pow_hash(&challenge, nonce)) < difficulty {
+        log::trace!("proof of work solution does not meet difficulty {difficulty}");
+        return Err(ProofOfWorkError::InvalidSolution.into());
+    }
+    // kept for the volume window, which outlasts the challenge itself
+    let expire_time = now + chrono::Duration::seconds(POW_VOLUME_WINDOW_SECS);
+    if !redeem_challenge(&hash_token(&challenge), now, expire_time).await? {
+        log::trace!("proof of work challenge was already used");
+        return Err(ProofOfWorkError::InvalidSolution.into());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "kV6dHn1mW8bA0cQfR3sT2w";
+
+    #[test]
+    fn signed_challenge_verifies() {
+        let challenge = sign_challenge(SECRET, "id.1700000000.16");
+        assert_eq!(
+            verified_payload(SECRET, &challenge),
+            Some("id.1700000000.16")
+        );
+    }
+
+    #[test]
+    fn changed_challenge_is_refused() {
+        let challenge = sign_challenge(SECRET, "id.1700000000.16");
+        // lowering the difficulty breaks the signature
+        let lowered = challenge.replacen(".16.", ".1.", 1);
+        assert_eq!(verified_payload(SECRET, &lowered), None);
+        let (payload, _) = challenge.rsplit_once('.').unwrap();
+        assert_eq!(verified_payload(SECRET, &format!("{payload}.AAAA")), None);
+        assert_eq!(verified_payload(SECRET, payload), None);
+    }
+
+    #[test]
+    fn challenge_of_another_secret_is_refused() {
+        let challenge = sign_challenge("x9PqL4zY7uJ5vK0eN2hG1A", "id.1700000000.16");
+        assert_eq!(verified_payload(SECRET, &challenge), None);
+    }
+
+    #[test]
+    fn solution_meets_the_difficulty_the_server_checks() {
+        let challenge = PowChallenge {
+            challenge: sign_challenge(SECRET, "id.1700000000.8"),
+            difficulty: 8,
+        };
+        let nonce = solve_range(&challenge, 0, u64::MAX).unwrap();
+        assert!(leading_zero_bits(&pow_hash(&challenge.challenge, nonce)) >= 8);
+        // every smaller nonce falls short
+        assert_eq!(solve_range(&challenge, 0, nonce), None);
+    }
+
+    #[test]
+    fn difficulty_rises_with_volume() {
+        assert_eq!(current_difficulty(0), POW_BASE_DIFFICULTY);
+        assert_eq!(
+            current_difficulty(POW_VOLUME_THRESHOLD - 1),
+            POW_BASE_DIFFICULTY
+        );
+        assert_eq!(
+            current_difficulty(POW_VOLUME_THRESHOLD),
+            POW_BASE_DIFFICULTY + 1
+        );
+        assert_eq!(
+            current_difficulty(POW_VOLUME_THRESHOLD * 2),
+            POW_BASE_DIFFICULTY + 2
+        );
+        assert_eq!(current_difficulty(i64::MAX), POW_MAX_DIFFICULTY);
+    }
+}
@@ -0,0 +1,156 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::recovery_codes::{
+        consume_recovery_code, replace_recovery_codes, unused_recovery_codes,
+    };
+    use crate::defs::*;
+    use crate::security::{
+        audit::record_audit_event, gen_code_hash, verify_hash, ValidateHashError,
+    };
+    use rand::Rng;
+    use secrecy::SecretString;
+    use uuid::Uuid;
+}}
+
+/// A new set of `RECOVERY_CODE_COUNT` codes, each written as "abcde-fghjk".
+#[cfg(feature = "ssr")]
+pub fn gen_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..RECOVERY_CODE_LEN)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())]
+                        as char
+                })
+                .collect();
+            let (first, second) = code.split_at(RECOVERY_CODE_LEN / 2);
+            format!("{first}-{second}")
+        })
+        .collect()
+}
+
+/// The code as it is hashed, without the dash, spaces or capitals people may type.
+#[cfg(feature = "ssr")]
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether `code` has the shape of a recovery code rather than an authenticator code.
+#[cfg(feature = "ssr")]
+pub fn is_recovery_code(code: &str) -> bool {
+    let code = normalize_recovery_code(code);
+    code.len() == RECOVERY_CODE_LEN
+        && code.bytes().all(|c| RECOVERY_CODE_ALPHABET.contains(&c))
+}
+
+/// Replaces the recovery codes of an account with a new set and returns it. The codes are
+/// only stored hashed, this is the one time they can be shown.
+#[cfg(feature = "ssr")]
+pub async fn issue_recovery_codes(user_id: Uuid) -> Result<Vec<String>, AppError> {
+    let codes = gen_recovery_codes();
+    let normalized: Vec<String> = codes
+        .iter()
+        .map(|code| normalize_recovery_code(code))
+        .collect();
+    let code_hashes = match tokio::task::spawn_blocking(move || {
+        normalized
+            .into_iter()
+            .map(|code| gen_code_hash(SecretString::from(code)))
+            .collect::<Result<Vec<String>, AppError>>()
+    })
+    .await
+    {
+        Ok(hashes) => hashes?,
+        Err(tokio_err) => {
+            log::error!("failed to spawn blocking tokio task: {tokio_err}");
+            return Err(AppError::TokioFailure);
+        }
+    };
+    replace_recovery_codes(user_id, &code_hashes).await?;
+    record_audit_event(user_id, AuditEvent::RecoveryCodesGenerated).await;
+    Ok(codes)
+}
+
+/// Accepts an unused recovery code of the account in place of its second factor and marks
+/// it used. Codes issued before they got their own cheaper hash cost as much to check as a
+/// password, until the account gets a new set.
+#[cfg(feature = "ssr")]
+pub async fn use_recovery_code(user_id: Uuid, code: &str) -> Result<(), AppError> {
+    if !is_recovery_code(code) {
+        return Err(TwoFactorError::InvalidCode.into());
+    }
+    let candidate = normalize_recovery_code(code);
+    let stored = unused_recovery_codes(user_id).await?;
+    let remaining = stored.len();
+    let task = tokio::task::spawn_blocking(move || {
+        for stored in stored {
+            match verify_hash(stored.code_hash, SecretString::from(candidate.clone())) {
+                Ok(()) => return Some(stored.code_id),
+                Err(ValidateHashError::VerifyError(_)) => {}
+                Err(ValidateHashError::DatabaseError(e)) => {
+                    //database is possibly corrupted
+                    log::error!("could not parse recovery code hash {}: {e}", stored.code_id)
+                }
+                Err(ValidateHashError::PepperMissing(version)) => log::error!(
+                    "recovery code {} needs pepper version {version}, which is not set",
+                    stored.code_id
+                ),
+            }
+        }
+        None
+    })
+    .await;
+    let code_id = match task {
+        Ok(Some(code_id)) => code_id,
+        Ok(None) => return Err(TwoFactorError::InvalidCode.into()),
+        Err(tokio_err) => {
+            log::error!("failed to spawn blocking tokio task: {tokio_err}");
+            return Err(AppError::TokioFailure);
+        }
+    };
+    match consume_recovery_code(code_id).await {
+        Ok(()) => {}
+        // another request used this code first
+        Err(DatabaseError::IncorrectRowsAffected) => {
+            return Err(TwoFactorError::InvalidCode.into())
+        }
+        Err(e) => return Err(e.into()),
+    }
+    record_audit_event(user_id, AuditEvent::RecoveryCodeUsed).await;
+    log::trace!(
+        "recovery code used for {user_id}, {} left",
+        remaining.saturating_sub(1)
+    );
+    Ok(())
+}
+
+/// Number of recovery codes the account has not used yet.
+#[cfg(feature = "ssr")]
+pub async fn recovery_codes_remaining(user_id: Uuid) -> Result<usize, AppError> {
+    Ok(unused_recovery_codes(user_id).await?.len())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use argon2::PasswordHash;
+
+    #[test]
+    fn recovery_code_hash_is_cheap_and_verifies() {
+        let code = normalize_recovery_code(&gen_recovery_codes()[0]);
+        let hash = gen_code_hash(SecretString::from(code.clone())).unwrap();
+        let params = argon2::Params::try_from(&PasswordHash::new(&hash).unwrap()).unwrap();
+        assert_eq!(params.m_cost(), RECOVERY_CODE_HASH_MEMORY_KIB);
+        assert_eq!(params.t_cost(), RECOVERY_CODE_HASH_ITERATIONS);
+        assert!(
+            verify_hash(SecretString::from(hash.clone()), SecretString::from(code)).is_ok()
+        );
+        let other = normalize_recovery_code(&gen_recovery_codes()[0]);
+        assert!(verify_hash(SecretString::from(hash), SecretString::from(other)).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::session_cookie::session_cookie_standing_with_pool;
+    use crate::session_cache::SessionCache;
+    use crate::defs::*;
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+        Aes256Gcm, Key,
+    };
+    use chrono::prelude::*;
+    use serde::{Deserialize, Serialize};
+    use sqlx::SqlitePool;
+    use std::sync::Arc;
+    use uuid::Uuid;
+}}
+
+/// Bound into every sealed cookie, so a value sealed for something else never opens as a
+/// session.
+#[cfg(feature = "ssr")]
+const SESSION_COOKIE_AAD: &[u8] = b"SESSIONID.v1";
+
+/// Length of the nonce in front of every sealed cookie
+#[cfg(feature = "ssr")]
+const NONCE_LEN: usize = 12;
+
+#[cfg(feature = "ssr")]
+const CUSTOM_ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+    &base64::alphabet::URL_SAFE,
+    base64::engine::general_purpose::NO_PAD,
+);
+
+/// The keys encrypted session cookies are sealed with. New cookies use the current key,
+/// cookies sealed with a previous key still open until it is removed.
+#[cfg(feature = "ssr")]
+#[derive(Clone)]
+pub struct SessionCookieKeys {
+    /// the current key first
+    keys: Arc<Vec<Aes256Gcm>>,
+}
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Debug for SessionCookieKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SessionCookieKeys({} keys)", self.keys.len())
+    }
+}
+
+/// Everything an encrypted session cookie holds
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// identifies the session to log it out, never the cookie value itself
+    pub session_id: String,
+    pub user_id: Uuid,
+    /// must match the user's `session_generation`, which "log out everywhere" bumps
+    pub generation: i64,
+    pub remember: bool,
+    /// unix seconds
+    pub absolute_expiry: i64,
+    /// unix seconds, pushed back each time the cookie is refreshed
+    pub idle_expiry: i64,
+    /// unix seconds of the last refresh
+    pub refreshed: i64,
+}
+
+#[cfg(feature = "ssr")]
+impl SessionClaims {
+    pub fn absolute_expiry(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.absolute_expiry, 0).unwrap_or_default()
+    }
+
+    /// Whether the idle expiry should be pushed back by sending a new cookie.
+    pub fn refresh_due(&self, now: DateTime<Utc>) -> bool {
+        now.timestamp() - self.refreshed > SESSION_LAST_SEEN_INTERVAL_SECS
+    }
+
+    /// The same session with its idle expiry pushed back from `now`.
+    pub fn refreshed(&self, now: DateTime<Utc>, policy: SessionPolicy) -> Self {
+        SessionClaims {
+            idle_expiry: policy.idle_expiry(now, self.absolute_expiry()).timestamp(),
+            refreshed: now.timestamp(),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl SessionCookieKeys {
+    /// A key that only lives as long as the process, sessions end at every restart.
+    pub fn ephemeral() -> Self {
+        SessionCookieKeys {
+            keys: Arc::new(vec![Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng))]),
+        }
+    }
+
+    /// Keys from configuration, each 32 bytes in url safe base64 without padding.
+    pub fn from_secrets(current: &str, previous: &[&str]) -> Result<Self, String> {
+        Ok(SessionCookieKeys {
+            keys: Arc::new(
+                std::iter::once(&current)
+                    .chain(previous.iter())
+                    .map(|key| parse_key(key))
+                    .collect::<Result<Vec<Aes256Gcm>, String>>()?,
+            ),
+        })
+    }
+
+    /// Encrypts `claims` into a cookie value with the current key.
+    pub fn seal(&self, claims: &SessionClaims) -> Result<String, AppError> {
+        let plaintext = serde_json::to_vec(claims).map_err(|e| {
+            log::error!("could not serialize session claims: {e}");
+            AppError::SessionCookieFailure
+        })?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[0]
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: SESSION_COOKIE_AAD,
+                },
+            )
+            .map_err(|e| {
+                log::error!("could not seal session cookie: {e}");
+                AppError::SessionCookieFailure
+            })?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(base64::Engine::encode(&CUSTOM_ENGINE, sealed))
+    }
+
+    /// Decrypts a cookie value sealed with any of the keys. Only proves the server made the
+    /// cookie, not that the session is still valid.
+    pub fn open(&self, sealed: &str) -> Option<SessionClaims> {
+        let sealed = base64::Engine::decode(&CUSTOM_ENGINE, sealed.trim()).ok()?;
+        if sealed.len() <= NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self.keys.iter().find_map(|key| {
+            key.decrypt(
+                nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: SESSION_COOKIE_AAD,
+                },
+            )
+            .ok()
+        })?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn parse_key(key: &str) -> Result<Aes256Gcm, String> {
+    let bytes = base64::Engine::decode(&CUSTOM_ENGINE, key.trim())
+        .map_err(|e| format!("session cookie key is not valid base64: {e}"))?;
+    if bytes.len() != 32 {
+        return Err("session cookie key is not 32 bytes long".to_string());
+    }
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+/// Opens an encrypted session cookie and checks it has not expired, been logged out or been
+/// ended by a newer session generation. The generation and logouts are read through `cache`,
+/// so a cookie is only checked against the database every so often.
+#[cfg(feature = "ssr")]
+pub async fn validate_session_cookie_with_pool(
+    sealed: &str,
+    keys: &SessionCookieKeys,
+    pool: &SqlitePool,
+    cache: &SessionCache,
+) -> Result<Option<SessionClaims>, DatabaseError> {
+    if sealed.is_empty() {
+        return Ok(None);
+    }
+    let claims = match keys.open(sealed) {
+        Some(claims) => claims,
+        None => {
+            log::trace!("session cookie could not be opened");
+            return Ok(None);
+        }
+    };
+    let now = Utc::now().timestamp();
+    if claims.idle_expiry < now || claims.absolute_expiry < now {
+        return Ok(None);
+    }
+    let standing = match cache.cookie_standing(&claims.session_id) {
+        Some((user_id, standing)) if user_id == claims.user_id => standing,
+        _ => {
+            let epoch = cache.epoch();
+            let standing = match session_cookie_standing_with_pool(
+                claims.user_id,
+                &claims.session_id,
+                pool,
+            )
+            .await?
+            {
+                Some(standing) => standing,
+                None => return Ok(None),
+            };
+            cache.store_cookie_standing(
+                &claims.session_id,
+                claims.user_id,
+                standing.clone(),
+                epoch,
+            );
+            standing
+        }
+    };
+    if standing.revoked || standing.session_generation != claims.generation {
+        log::trace!("ended session cookie of {} presented", claims.user_id);
+        return Ok(None);
+    }
+    Ok(Some(claims))
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::database::{insert_test_user, memory_pool};
+
+    fn claims(user_id: Uuid, generation: i64) -> SessionClaims {
+        let now = Utc::now().timestamp();
+        SessionClaims {
+            session_id: "session".to_string(),
+            user_id,
+            generation,
+            remember: false,
+            absolute_expiry: now + 3_600,
+            idle_expiry: now + 600,
+            refreshed: now,
+        }
+    }
+
+    fn key() -> String {
+        base64::Engine::encode(&CUSTOM_ENGINE, Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Flips one bit of the sealed bytes at `index`.
+    fn tamper(sealed: &str, index: usize) -> String {
+        let mut bytes = base64::Engine::decode(&CUSTOM_ENGINE, sealed).unwrap();
+        bytes[index] ^= 1;
+        base64::Engine::encode(&CUSTOM_ENGINE, bytes)
+    }
+
+    #[test]
+    fn sealed_claims_open_again() {
+        let keys = SessionCookieKeys::ephemeral();
+        let claims = claims(Uuid::now_v7(), 0);
+        let sealed = keys.seal(&claims).unwrap();
+        assert_eq!(keys.open(&sealed), Some(claims.clone()));
+        // a fresh nonce every time
+        assert_ne!(keys.seal(&claims).unwrap(), sealed);
+    }
+
+    #[test]
+    fn tampered_cookie_does_not_open() {
+        let keys = SessionCookieKeys::ephemeral();
+        let sealed = keys.seal(&claims(Uuid::now_v7(), 0)).unwrap();
+        let len = base64::Engine::decode(&CUSTOM_ENGINE, &sealed)
+            .unwrap()
+            .len();
+        for index in [0, NONCE_LEN, len - 1] {
+            assert_eq!(keys.open(&tamper(&sealed, index)), None);
+        }
+        assert_eq!(keys.open(&sealed[..sealed.len() / 2]), None);
+        assert_eq!(keys.open("not a cookie"), None);
+    }
+
+    #[test]
+    fn previous_key_opens_until_it_is_removed() {
+        let (old, new) = (key(), key());
+        let claims = claims(Uuid::now_v7(), 0);
+        let sealed = SessionCookieKeys::from_secrets(&old, &[])
+            .unwrap()
+            .seal(&claims)
+            .unwrap();
+        let rotated = SessionCookieKeys::from_secrets(&new, &[&old]).unwrap();
+        assert_eq!(rotated.open(&sealed), Some(claims));
+        let removed = SessionCookieKeys::from_secrets(&new, &[]).unwrap();
+        assert_eq!(removed.open(&sealed), None);
+    }
+
+    #[tokio::test]
+    async fn current_cookie_is_valid() {
+        let pool = memory_pool().await;
+        let keys = SessionCookieKeys::ephemeral();
+        let user_id = insert_test_user("alice", &pool).await;
+        let claims = claims(user_id, 0);
+        let sealed = keys.seal(&claims).unwrap();
+        let validated = validate_session_cookie_with_pool(
+            &sealed,
+            &keys,
+            &pool,
+            &SessionCache::disabled(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validated, Some(claims));
+    }
+
+    #[tokio::test]
+    async fn stale_generation_is_refused() {
+        let pool = memory_pool().await;
+        let keys = SessionCookieKeys::ephemeral();
+        let user_id = insert_test_user("alice", &pool).await;
+        let sealed = keys.seal(&claims(user_id, 0)).unwrap();
+        sqlx::query!(
+            "UPDATE users SET session_generation = session_generation + 1 WHERE user_id = ?",
+            user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let validated = validate_session_cookie_with_pool(
+            &sealed,
+            &keys,
+            &pool,
+            &SessionCache::disabled(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validated, None);
+    }
+
+    #[tokio::test]
+    async fn cached_standing_is_used_until_forgotten() {
+        let pool = memory_pool().await;
+        let keys = SessionCookieKeys::ephemeral();
+        let cache = SessionCache::new(8, std::time::Duration::from_secs(60));
+        let user_id = insert_test_user("alice", &pool).await;
+        let claims = claims(user_id, 0);
+        let sealed = keys.seal(&claims).unwrap();
+        let validate = || validate_session_cookie_with_pool(&sealed, &keys, &pool, &cache);
+        assert_eq!(validate().await.unwrap(), Some(claims.clone()));
+        sqlx::query!(
+            "UPDATE users SET session_generation = session_generation + 1 WHERE user_id = ?",
+            user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // another instance ended the sessions, this one notices once the entry expires
+        assert_eq!(validate().await.unwrap(), Some(claims));
+        cache.forget_user(user_id);
+        assert_eq!(validate().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_claims_are_refused() {
+        let pool = memory_pool().await;
+        let keys = SessionCookieKeys::ephemeral();
+        let user_id = insert_test_user("alice", &pool).await;
+        let now = Utc::now().timestamp();
+        let idle = SessionClaims {
+            idle_expiry: now - 1,
+            ..claims(user_id, 0)
+        };
+        let absolute = SessionClaims {
+            absolute_expiry: now - 1,
+            ..claims(user_id, 0)
+        };
+        for expired in [idle, absolute] {
+            let sealed = keys.seal(&expired).unwrap();
+            let validated = validate_session_cookie_with_pool(
+                &sealed,
+                &keys,
+                &pool,
+                &SessionCache::disabled(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(validated, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn logged_out_cookie_is_refused() {
+        let pool = memory_pool().await;
+        let keys = SessionCookieKeys::ephemeral();
+        let user_id = insert_test_user("alice", &pool).await;
+        let claims = claims(user_id, 0);
+        let sealed = keys.seal(&claims).unwrap();
+        let expiry = claims.absolute_expiry();
+        sqlx::query!(
+            "INSERT INTO revoked_session_cookies (session_id, user_id, expiry) VALUES (?, ?, ?)",
+            claims.session_id,
+            user_id,
+            expiry
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let validated = validate_session_cookie_with_pool(
+            &sealed,
+            &keys,
+            &pool,
+            &SessionCache::disabled(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validated, None);
+    }
+}
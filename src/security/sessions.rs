@@ -0,0 +1,104 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{end_other_sessions, end_session, request_session_id, require_session};
+    use crate::database::retrieve_sessions;
+    use crate::defs::*;
+    use crate::security::{hash_token, validate_csrf_request};
+}}
+
+/// A logged in browser as shown on the settings page
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    /// identifies the session to revoke it, the session id itself never leaves the server
+    pub handle: String,
+    pub device: String,
+    pub ip: String,
+    pub created: Option<String>,
+    pub last_seen: Option<String>,
+    /// the session this list was requested with
+    pub current: bool,
+}
+
+/// Turns a user agent header into something like "Firefox on Linux".
+#[cfg(feature = "ssr")]
+pub fn describe_user_agent(user_agent: &str) -> String {
+    if user_agent.is_empty() {
+        return String::from("Unknown device");
+    }
+    // order matters, most browsers also claim to be the browsers they are based on
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map_or("Unknown browser", |(_, name)| name);
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("CrOS", "ChromeOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map_or("an unknown system", |(_, name)| name);
+    format!("{browser} on {os}")
+}
+
+#[cfg(feature = "ssr")]
+pub async fn session_summaries() -> Result<Vec<SessionSummary>, AppError> {
+    let user_id = require_session().await?;
+    let current_session = request_session_id();
+    Ok(retrieve_sessions(user_id)
+        .await?
+        .into_iter()
+        .map(|stored| SessionSummary {
+            handle: hash_token(&stored.session_id),
+            device: describe_user_agent(&stored.user_agent),
+            ip: stored.ip,
+            created: stored
+                .created
+                .map(|created| created.format("%Y-%m-%d %H:%M UTC").to_string()),
+            last_seen: stored
+                .last_seen
+                .map(|last_seen| last_seen.format("%Y-%m-%d %H:%M UTC").to_string()),
+            current: stored.session_id == current_session,
+        })
+        .collect())
+}
+
+/// Logs out one of the user's sessions and closes its websockets.
+#[cfg(feature = "ssr")]
+pub async fn revoke_session(csrf: String, handle: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::RevokeSession).await?;
+    let user_id = require_session().await?;
+    let session = match retrieve_sessions(user_id)
+        .await?
+        .into_iter()
+        .find(|stored| hash_token(&stored.session_id) == handle)
+    {
+        Some(stored) => stored,
+        None => return Err(LoginError::SessionNotFound.into()),
+    };
+    end_session(user_id, &session.session_id, session.expiry).await?;
+    log::trace!("session revoked for {user_id}");
+    Ok(())
+}
+
+/// Logs out every session of the user except the one making this request.
+#[cfg(feature = "ssr")]
+pub async fn revoke_other_sessions(csrf: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::RevokeOtherSessions).await?;
+    let user_id = require_session().await?;
+    end_other_sessions(user_id).await?;
+    log::trace!("other sessions revoked for {user_id}");
+    Ok(())
+}
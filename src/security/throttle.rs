@@ -0,0 +1,412 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::throttle::{
+        block_throttle_key_with_pool, clear_throttle_key_with_pool,
+        count_login_attempt_with_pool, uncount_login_attempt_with_pool,
+    };
+    use crate::defs::*;
+    use crate::security::canonical::canonical_name;
+    use axum::extract::ConnectInfo;
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use sqlx::SqlitePool;
+    use std::net::{IpAddr, SocketAddr};
+    use uuid::Uuid;
+}}
+
+/// Something failed logins and second factor checks are counted against. Usernames are
+/// counted whether or not an account exists, so a lockout reveals nothing about registered
+/// names.
+#[cfg(feature = "ssr")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ThrottleKey {
+    /// A username, from anywhere. Only ever delayed, never locked out.
+    Username(String),
+    /// A username, from one address. Locking it out leaves the account usable elsewhere.
+    UsernameFromIp(String, IpAddr),
+    Ip(IpAddr),
+    /// The second factor of a logged in account, checked again to change its two-factor
+    /// settings. Locked out after as many failures as a login challenge allows.
+    SecondFactor(Uuid),
+}
+
+#[cfg(feature = "ssr")]
+impl ThrottleKey {
+    fn stored(&self) -> String {
+        match self {
+            // look-alike names are the same account, and count as one
+            ThrottleKey::Username(username) => format!("user:{}", canonical_name(username)),
+            ThrottleKey::UsernameFromIp(username, ip) => {
+                format!("user-ip:{ip}:{}", canonical_name(username))
+            }
+            ThrottleKey::Ip(ip) => format!("ip:{ip}"),
+            ThrottleKey::SecondFactor(user_id) => format!("second-factor:{user_id}"),
+        }
+    }
+
+    /// Failures allowed before attempts are delayed, failures allowed before the key is
+    /// locked out, and the longest delay before that.
+    fn limits(&self) -> (i64, Option<i64>, i64) {
+        match self {
+            ThrottleKey::Username(_) => (
+                LOGIN_THROTTLE_ACCOUNT_FREE_FAILURES,
+                None,
+                LOGIN_THROTTLE_ACCOUNT_MAX_DELAY_SECS,
+            ),
+            ThrottleKey::UsernameFromIp(..) => (
+                LOGIN_THROTTLE_USERNAME_FREE_FAILURES,
+                Some(LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES),
+                LOGIN_THROTTLE_MAX_DELAY_SECS,
+            ),
+            ThrottleKey::Ip(_) => (
+                LOGIN_THROTTLE_IP_FREE_FAILURES,
+                Some(LOGIN_THROTTLE_IP_LOCKOUT_FAILURES),
+                LOGIN_THROTTLE_MAX_DELAY_SECS,
+            ),
+            ThrottleKey::SecondFactor(_) => (
+                LOGIN_CHALLENGE_MAX_ATTEMPTS,
+                Some(LOGIN_CHALLENGE_MAX_ATTEMPTS),
+                LOGIN_THROTTLE_MAX_DELAY_SECS,
+            ),
+        }
+    }
+
+    /// Whether the key is locked out after `failures` failures.
+    fn locked_out(&self, failures: i64) -> bool {
+        let (_, lockout, _) = self.limits();
+        lockout.is_some_and(|lockout| failures >= lockout)
+    }
+
+    /// How long the key is blocked after its `failures`th failure.
+    fn delay(&self, failures: i64) -> Option<chrono::Duration> {
+        let (free, _, max_delay) = self.limits();
+        if self.locked_out(failures) {
+            return Some(chrono::Duration::seconds(LOGIN_LOCKOUT_DURATION_SECS));
+        }
+        if failures <= free {
+            return None;
+        }
+        let doublings = u32::try_from(failures - free - 1).unwrap_or(u32::MAX);
+        let delay = 2_i64
+            .checked_pow(doublings)
+            .and_then(|factor| factor.checked_mul(LOGIN_THROTTLE_BASE_DELAY_SECS))
+            .map_or(max_delay, |delay| delay.min(max_delay));
+        Some(chrono::Duration::seconds(delay))
+    }
+
+    /// Blocks the key for `delay`. A lockout keeps the counter one short of the lockout, so
+    /// once it ends one more attempt is checked and a failure locks the key out again.
+    async fn block(
+        &self,
+        now: DateTime<Utc>,
+        delay: chrono::Duration,
+        failures: i64,
+        pool: &SqlitePool,
+    ) -> Result<(), AppError> {
+        let max_failures = match self.locked_out(failures) {
+            true => self.limits().1.map(|lockout| lockout - 1),
+            false => None,
+        };
+        block_throttle_key_with_pool(&self.stored(), now + delay, max_failures, pool).await?;
+        Ok(())
+    }
+}
+
+/// A login counted against its throttle keys before the password is checked, so concurrent
+/// guesses can't all slip in under the limits.
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct LoginAttempt {
+    counted: Vec<(ThrottleKey, i64)>,
+}
+
+#[cfg(feature = "ssr")]
+fn use_pool() -> Result<SqlitePool, DatabaseError> {
+    match use_context::<SqlitePool>() {
+        Some(pool) => Ok(pool),
+        None => {
+            log::error!("sql pool not available for the login throttle");
+            Err(DatabaseError::CouldNotFindPool)
+        }
+    }
+}
+
+/// The keys a password login for `username` from the current request is counted against.
+#[cfg(feature = "ssr")]
+pub fn login_throttle_keys(username: &str) -> Vec<ThrottleKey> {
+    let mut keys = vec![ThrottleKey::Username(username.to_string())];
+    match use_context::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => {
+            keys.push(ThrottleKey::UsernameFromIp(username.to_string(), addr.ip()));
+            keys.push(ThrottleKey::Ip(addr.ip()));
+        }
+        None => log::warn!("login_throttle_keys: no client address available"),
+    }
+    keys
+}
+
+/// Counts a login against every key, failing with `LoginError::Throttled` while any of them
+/// is blocked or already used up its attempts.
+#[cfg(feature = "ssr")]
+pub async fn begin_login_attempt(keys: &[ThrottleKey]) -> Result<LoginAttempt, AppError> {
+    begin_login_attempt_with_pool(keys, Utc::now(), &use_pool()?).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn begin_login_attempt_with_pool(
+    keys: &[ThrottleKey],
+    now: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<LoginAttempt, AppError> {
+    let expire_time = now + chrono::Duration::seconds(LOGIN_THROTTLE_WINDOW_SECS);
+    let mut counted = Vec::with_capacity(keys.len());
+    for key in keys {
+        match count_login_attempt_with_pool(&key.stored(), now, expire_time, pool).await? {
+            None => log::trace!("login throttled for {key:?}"),
+            // attempts made together all counted before any of them failed
+            Some(failures) if key.locked_out(failures - 1) => {
+                log::debug!("{key:?} locked out after {failures} concurrent logins");
+                let lockout = chrono::Duration::seconds(LOGIN_LOCKOUT_DURATION_SECS);
+                key.block(now, lockout, failures, pool).await?;
+            }
+            Some(failures) => {
+                counted.push((key.clone(), failures));
+                continue;
+            }
+        }
+        // a refused login is not held against the keys that would have allowed it
+        for (key, _) in &counted {
+            uncount_login_attempt_with_pool(&key.stored(), pool).await?;
+        }
+        return Err(LoginError::Throttled.into());
+    }
+    Ok(LoginAttempt { counted })
+}
+
+/// Blocks the keys of a failed login that failed too often.
+#[cfg(feature = "ssr")]
+pub async fn record_login_failure(attempt: &LoginAttempt) -> Result<(), AppError> {
+    record_login_failure_with_pool(attempt, Utc::now(), &use_pool()?).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn record_login_failure_with_pool(
+    attempt: &LoginAttempt,
+    now: DateTime<Utc>,
+    pool: &SqlitePool,
+) -> Result<(), AppError> {
+    for (key, failures) in &attempt.counted {
+        if let Some(delay) = key.delay(*failures) {
+            log::debug!("{key:?} blocked for {delay} after {failures} failed logins");
+            key.block(now, delay, *failures, pool).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the failures of a username after a successful login. Addresses keep theirs, a
+/// single valid account must not let one address keep guessing at others, but the
+/// successful attempt itself is taken back.
+#[cfg(feature = "ssr")]
+pub async fn clear_login_failures(attempt: &LoginAttempt) -> Result<(), AppError> {
+    clear_login_failures_with_pool(attempt, &use_pool()?).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn clear_login_failures_with_pool(
+    attempt: &LoginAttempt,
+    pool: &SqlitePool,
+) -> Result<(), AppError> {
+    for (key, _) in &attempt.counted {
+        match key {
+            ThrottleKey::Ip(_) => uncount_login_attempt_with_pool(&key.stored(), pool).await?,
+            _ => clear_throttle_key_with_pool(&key.stored(), pool).await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::database::memory_pool;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn secs(delay: Option<chrono::Duration>) -> Option<i64> {
+        delay.map(|delay| delay.num_seconds())
+    }
+
+    #[test]
+    fn delay_doubles_after_the_free_failures() {
+        let key = ThrottleKey::UsernameFromIp("alice".to_string(), IP);
+        let free = LOGIN_THROTTLE_USERNAME_FREE_FAILURES;
+        assert_eq!(secs(key.delay(1)), None);
+        assert_eq!(secs(key.delay(free)), None);
+        assert_eq!(
+            secs(key.delay(free + 1)),
+            Some(LOGIN_THROTTLE_BASE_DELAY_SECS)
+        );
+        assert_eq!(
+            secs(key.delay(free + 2)),
+            Some(2 * LOGIN_THROTTLE_BASE_DELAY_SECS)
+        );
+        assert_eq!(
+            secs(key.delay(free + 3)),
+            Some(4 * LOGIN_THROTTLE_BASE_DELAY_SECS)
+        );
+    }
+
+    #[test]
+    fn second_factor_locks_out_like_a_login_challenge() {
+        let key = ThrottleKey::SecondFactor(Uuid::now_v7());
+        let attempts = LOGIN_CHALLENGE_MAX_ATTEMPTS;
+        assert_eq!(secs(key.delay(attempts - 1)), None);
+        assert_eq!(secs(key.delay(attempts)), Some(LOGIN_LOCKOUT_DURATION_SECS));
+    }
+
+    #[test]
+    fn delay_becomes_a_lockout() {
+        let key = ThrottleKey::UsernameFromIp("alice".to_string(), IP);
+        let lockout = LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES;
+        assert!(secs(key.delay(lockout - 1)) < Some(LOGIN_LOCKOUT_DURATION_SECS));
+        assert_eq!(secs(key.delay(lockout)), Some(LOGIN_LOCKOUT_DURATION_SECS));
+        assert_eq!(
+            secs(key.delay(lockout + 5)),
+            Some(LOGIN_LOCKOUT_DURATION_SECS)
+        );
+    }
+
+    #[test]
+    fn username_delay_is_capped_and_never_a_lockout() {
+        let key = ThrottleKey::Username("alice".to_string());
+        let free = LOGIN_THROTTLE_ACCOUNT_FREE_FAILURES;
+        assert_eq!(secs(key.delay(free)), None);
+        assert_eq!(
+            secs(key.delay(free + 1)),
+            Some(LOGIN_THROTTLE_BASE_DELAY_SECS)
+        );
+        for failures in [free + 10, 1_000, i64::MAX] {
+            assert_eq!(
+                secs(key.delay(failures)),
+                Some(LOGIN_THROTTLE_ACCOUNT_MAX_DELAY_SECS)
+            );
+        }
+    }
+
+    #[test]
+    fn ip_delay_is_capped_before_its_lockout() {
+        let key = ThrottleKey::Ip(IP);
+        let lockout = LOGIN_THROTTLE_IP_LOCKOUT_FAILURES;
+        assert_eq!(
+            secs(key.delay(lockout - 1)),
+            Some(LOGIN_THROTTLE_MAX_DELAY_SECS)
+        );
+        assert_eq!(secs(key.delay(lockout)), Some(LOGIN_LOCKOUT_DURATION_SECS));
+    }
+
+    #[test]
+    fn look_alike_usernames_share_a_key() {
+        let plain = ThrottleKey::UsernameFromIp("alice".to_string(), IP);
+        let shouting = ThrottleKey::UsernameFromIp("ALICE".to_string(), IP);
+        assert_eq!(plain.stored(), shouting.stored());
+        assert_ne!(
+            plain.stored(),
+            ThrottleKey::Username("alice".to_string()).stored()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_attempts_cannot_pass_the_lockout() {
+        let pool = memory_pool().await;
+        let keys = [ThrottleKey::UsernameFromIp("alice".to_string(), IP)];
+        let now = Utc::now();
+        let lockout = LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES;
+        // none of these fail before all of them are counted
+        let mut attempts = Vec::new();
+        for _ in 0..lockout {
+            attempts.push(
+                begin_login_attempt_with_pool(&keys, now, &pool)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert!(matches!(
+            begin_login_attempt_with_pool(&keys, now, &pool).await,
+            Err(AppError::Login(LoginError::Throttled))
+        ));
+        for attempt in &attempts {
+            record_login_failure_with_pool(attempt, now, &pool)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            begin_login_attempt_with_pool(&keys, now, &pool).await,
+            Err(AppError::Login(LoginError::Throttled))
+        ));
+    }
+
+    #[tokio::test]
+    async fn lockout_lets_one_attempt_through_once_it_ends() {
+        let pool = memory_pool().await;
+        let keys = [ThrottleKey::UsernameFromIp("alice".to_string(), IP)];
+        let mut now = Utc::now();
+        for _ in 0..LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES {
+            let attempt = begin_login_attempt_with_pool(&keys, now, &pool)
+                .await
+                .unwrap();
+            record_login_failure_with_pool(&attempt, now, &pool)
+                .await
+                .unwrap();
+            now += chrono::Duration::seconds(LOGIN_THROTTLE_MAX_DELAY_SECS);
+        }
+        assert!(begin_login_attempt_with_pool(&keys, now, &pool)
+            .await
+            .is_err());
+        now += chrono::Duration::seconds(LOGIN_LOCKOUT_DURATION_SECS);
+        let attempt = begin_login_attempt_with_pool(&keys, now, &pool)
+            .await
+            .unwrap();
+        record_login_failure_with_pool(&attempt, now, &pool)
+            .await
+            .unwrap();
+        assert!(begin_login_attempt_with_pool(&keys, now, &pool)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn lockout_from_one_address_spares_the_others() {
+        let pool = memory_pool().await;
+        let other_ip = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+        let attacker = [
+            ThrottleKey::Username("alice".to_string()),
+            ThrottleKey::UsernameFromIp("alice".to_string(), IP),
+        ];
+        let owner = [
+            ThrottleKey::Username("alice".to_string()),
+            ThrottleKey::UsernameFromIp("alice".to_string(), other_ip),
+        ];
+        let mut now = Utc::now();
+        for _ in 0..LOGIN_THROTTLE_USERNAME_LOCKOUT_FAILURES {
+            let attempt = begin_login_attempt_with_pool(&attacker, now, &pool)
+                .await
+                .unwrap();
+            record_login_failure_with_pool(&attempt, now, &pool)
+                .await
+                .unwrap();
+            now += chrono::Duration::seconds(LOGIN_THROTTLE_MAX_DELAY_SECS);
+        }
+        assert!(begin_login_attempt_with_pool(&attacker, now, &pool)
+            .await
+            .is_err());
+        let attempt = begin_login_attempt_with_pool(&owner, now, &pool)
+            .await
+            .unwrap();
+        clear_login_failures_with_pool(&attempt, &pool)
+            .await
+            .unwrap();
+    }
+}
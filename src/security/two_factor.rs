@@ -0,0 +1,281 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::{parse_login_challenge_req_parts_cookie, require_session};
+    use crate::database::{
+        recovery_codes::delete_recovery_codes,
+        two_factor::{
+            confirm_totp, consume_totp_step, count_challenge_attempt, delete_totp,
+            drop_login_challenge, retrieve_login_challenge, retrieve_totp,
+            store_pending_totp, totp_enabled,
+        },
+        username_for_id,
+    };
+    use crate::defs::*;
+    use crate::security::{
+        recovery_codes::{is_recovery_code, issue_recovery_codes, use_recovery_code},
+        throttle::{begin_login_attempt, clear_login_failures, record_login_failure, ThrottleKey},
+        validate_csrf, validate_csrf_request,
+    };
+    use chrono::prelude::*;
+    use http::request::Parts;
+    use leptos::prelude::*;
+    use qrcode::{render::svg, QrCode};
+    use rand::RngCore;
+    use secrecy::{ExposeSecret, SecretString};
+    use totp_rs::{Algorithm, Secret, TOTP};
+    use uuid::Uuid;
+}}
+
+/// Seconds each TOTP code is valid for, the value every authenticator app expects
+#[cfg(feature = "ssr")]
+const TOTP_STEP_SECS: u64 = 30;
+
+/// Everything the settings page needs to add an account to an authenticator app
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// base32 secret for manual entry
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// the otpauth uri as an svg QR code, encoded as a data uri
+    pub qr_code: String,
+}
+
+/// What the settings page shows about two-factor authentication
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// recovery codes that have not been used yet
+    pub recovery_codes_remaining: usize,
+}
+
+#[cfg(feature = "ssr")]
+pub fn gen_totp_secret() -> String {
+    // 160 bits as recommended by RFC 4226
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    match Secret::Raw(secret.to_vec()).to_encoded() {
+        Secret::Encoded(encoded) => encoded,
+        Secret::Raw(_) => unreachable!("to_encoded always returns Secret::Encoded"),
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn build_totp(secret: &SecretString, account_name: String) -> Result<TOTP, TwoFactorError> {
+    let secret = match Secret::Encoded(secret.expose_secret().to_string()).to_bytes() {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            //database is possibly corrupted
+            log::error!("could not decode stored totp secret: {e}");
+            return Err(TwoFactorError::SecretInvalid);
+        }
+    };
+    // skew is handled in verify_totp_code so the matching time step is known
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_CODE_LEN,
+        0,
+        TOTP_STEP_SECS,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account_name.replace(':', ""),
+    )
+    .map_err(|e| {
+        log::error!("could not build totp: {e}");
+        TwoFactorError::SecretInvalid
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub fn totp_enrollment(
+    secret: &SecretString,
+    username: String,
+) -> Result<TotpEnrollment, TwoFactorError> {
+    let totp = build_totp(secret, username)?;
+    let otpauth_uri = totp.get_url();
+    let qr_svg = match QrCode::new(otpauth_uri.as_bytes()) {
+        Ok(code) => code.render::<svg::Color>().min_dimensions(200, 200).build(),
+        Err(e) => {
+            log::error!("could not render otpauth uri as a QR code: {e}");
+            return Err(TwoFactorError::SecretInvalid);
+        }
+    };
+    Ok(TotpEnrollment {
+        secret: totp.get_secret_base32(),
+        otpauth_uri,
+        qr_code: format!(
+            "data:image/svg+xml;base64,{}",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, qr_svg)
+        ),
+    })
+}
+
+/// Returns the time step the code belongs to if it is valid and newer than `last_used_step`.
+#[cfg(feature = "ssr")]
+pub fn verify_totp_code(
+    secret: &SecretString,
+    code: &str,
+    last_used_step: i64,
+) -> Result<Option<i64>, TwoFactorError> {
+    if code.len() != TOTP_CODE_LEN || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let totp = build_totp(secret, String::default())?;
+    let current_step = Utc::now().timestamp() / TOTP_STEP_SECS as i64;
+    // allow one step of clock drift in either direction
+    Ok((current_step - 1..=current_step + 1)
+        .filter(|step| *step > last_used_step)
+        .find(|step| totp.check(code, *step as u64 * TOTP_STEP_SECS)))
+}
+
+#[cfg(feature = "ssr")]
+pub async fn verify_user_totp(user_id: Uuid, code: &str) -> Result<(), AppError> {
+    let stored = match retrieve_totp(user_id).await? {
+        Some(stored) if stored.confirmed => stored,
+        _ => return Err(TwoFactorError::NotEnrolled.into()),
+    };
+    match verify_totp_code(&stored.secret, code, stored.last_used_step)? {
+        Some(step) => match consume_totp_step(user_id, step).await {
+            Ok(()) => Ok(()),
+            // another request used this code first
+            Err(DatabaseError::IncorrectRowsAffected) => {
+                Err(TwoFactorError::InvalidCode.into())
+            }
+            Err(e) => Err(e.into()),
+        },
+        None => Err(TwoFactorError::InvalidCode.into()),
+    }
+}
+
+/// Accepts a code from the authenticator app, or one of the account's recovery codes in its
+/// place.
+#[cfg(feature = "ssr")]
+pub async fn verify_user_second_factor(user_id: Uuid, code: &str) -> Result<(), AppError> {
+    match is_recovery_code(code) {
+        true => use_recovery_code(user_id, code).await,
+        false => verify_user_totp(user_id, code).await,
+    }
+}
+
+/// Checks the second factor of a logged in user before their two-factor settings change.
+/// Wrong codes are counted like at a login challenge and lock these changes out for
+/// `LOGIN_LOCKOUT_DURATION_SECS` once there are `LOGIN_CHALLENGE_MAX_ATTEMPTS` of them.
+#[cfg(feature = "ssr")]
+async fn confirm_user_second_factor(user_id: Uuid, code: &str) -> Result<(), AppError> {
+    let attempt = match begin_login_attempt(&[ThrottleKey::SecondFactor(user_id)]).await {
+        Ok(attempt) => attempt,
+        Err(AppError::Login(LoginError::Throttled)) => {
+            return Err(TwoFactorError::TooManyAttempts.into())
+        }
+        Err(e) => return Err(e),
+    };
+    match verify_user_second_factor(user_id, code).await {
+        Ok(()) => {
+            clear_login_failures(&attempt).await?;
+            Ok(())
+        }
+        Err(AppError::TwoFactor(TwoFactorError::InvalidCode)) => {
+            record_login_failure(&attempt).await?;
+            Err(TwoFactorError::InvalidCode.into())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Second step of a login for accounts with two-factor authentication enabled.
+/// The first step left a `__Host-login` challenge cookie after the password was verified.
+/// Returns the user and whether they asked to be remembered in the first step.
+#[cfg(feature = "ssr")]
+pub async fn validate_second_factor(
+    csrf: String,
+    code: String,
+) -> Result<(Uuid, bool), AppError> {
+    let http_req = match use_context::<Parts>() {
+        None => {
+            log::error!("validate_second_factor: could not retrieve RequestParts");
+            Err(RouterError::HTTPRequestMissing)
+        }
+        Some(rp) => Ok(rp),
+    }?;
+    validate_csrf(http_req.clone(), csrf, CsrfPurpose::SecondFactor).await?;
+    let challenge_id = parse_login_challenge_req_parts_cookie(http_req);
+    let challenge = match retrieve_login_challenge(&challenge_id).await? {
+        Some(challenge) => challenge,
+        None => return Err(TwoFactorError::ChallengeMissing.into()),
+    };
+    // every attempt counts before the code is checked, a recovery code takes a while to check
+    if challenge.expiry < Utc::now()
+        || !count_challenge_attempt(&challenge_id, LOGIN_CHALLENGE_MAX_ATTEMPTS).await?
+    {
+        let _ = drop_login_challenge(&challenge_id).await;
+        return Err(TwoFactorError::ChallengeMissing.into());
+    }
+    verify_user_second_factor(challenge.user_id, code.trim()).await?;
+    drop_login_challenge(&challenge_id).await?;
+    log::trace!("second factor accepted for {}", challenge.user_id);
+    Ok((challenge.user_id, challenge.remember))
+}
+
+#[cfg(feature = "ssr")]
+pub async fn begin_totp_enrollment(csrf: String) -> Result<TotpEnrollment, AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::EnrollTotp).await?;
+    let user_id = require_session().await?;
+    if totp_enabled(user_id).await? {
+        return Err(TwoFactorError::AlreadyEnrolled.into());
+    }
+    let secret = gen_totp_secret();
+    store_pending_totp(user_id, &secret).await?;
+    let username = username_for_id(user_id).await?;
+    Ok(totp_enrollment(&SecretString::from(secret), username)?)
+}
+
+/// Enables TOTP once the first code from the app checks out, and returns the account's new
+/// recovery codes.
+#[cfg(feature = "ssr")]
+pub async fn confirm_totp_enrollment(
+    csrf: String,
+    code: String,
+) -> Result<Vec<String>, AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ConfirmTotp).await?;
+    let user_id = require_session().await?;
+    let stored = match retrieve_totp(user_id).await? {
+        Some(stored) => stored,
+        None => return Err(TwoFactorError::NotEnrolled.into()),
+    };
+    if stored.confirmed {
+        return Err(TwoFactorError::AlreadyEnrolled.into());
+    }
+    let step = match verify_totp_code(&stored.secret, code.trim(), stored.last_used_step)? {
+        Some(step) => step,
+        None => return Err(TwoFactorError::InvalidCode.into()),
+    };
+    confirm_totp(user_id, step).await?;
+    log::trace!("totp enabled for {user_id}");
+    issue_recovery_codes(user_id).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn disable_totp(csrf: String, code: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::DisableTotp).await?;
+    let user_id = require_session().await?;
+    confirm_user_second_factor(user_id, code.trim()).await?;
+    delete_totp(user_id).await?;
+    delete_recovery_codes(user_id).await?;
+    log::trace!("totp disabled for {user_id}");
+    Ok(())
+}
+
+/// Replaces the account's recovery codes, for when they were lost or are running out.
+#[cfg(feature = "ssr")]
+pub async fn regenerate_recovery_codes(
+    csrf: String,
+    code: String,
+) -> Result<Vec<String>, AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::RegenerateRecoveryCodes).await?;
+    let user_id = require_session().await?;
+    confirm_user_second_factor(user_id, code.trim()).await?;
+    let codes = issue_recovery_codes(user_id).await?;
+    log::trace!("recovery codes regenerated for {user_id}");
+    Ok(codes)
+}
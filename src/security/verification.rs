@@ -0,0 +1,124 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::cookies::require_session;
+    use crate::database::verification::{
+        email_status, mark_email_verified, replace_verification_token, take_verification_token,
+    };
+    use crate::defs::*;
+    use crate::mail::{send_mail, Mail};
+    use crate::rate_limit::{rate_limit, LimitedAction};
+    use crate::security::{gen_128bit_base64, hash_token, validate_csrf_request};
+    use chrono::prelude::*;
+    use leptos::prelude::*;
+    use uuid::Uuid;
+}}
+
+/// The account's email address as shown on the settings page
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmailVerificationStatus {
+    pub email: String,
+    pub verified: bool,
+}
+
+/// Mails a new verification link to the account's current address.
+#[cfg(feature = "ssr")]
+pub async fn send_verification_email(user_id: Uuid) -> Result<(), AppError> {
+    let status = email_status(user_id).await?;
+    if status.verified {
+        return Err(VerificationError::AlreadyVerified.into());
+    }
+    let token = gen_128bit_base64();
+    let expire_time: DateTime<Utc> =
+        Utc::now() + chrono::Duration::seconds(EMAIL_VERIFICATION_DURATION_SECS);
+    replace_verification_token(user_id, &hash_token(&token), &status.email, expire_time)
+        .await?;
+    send_mail(Mail {
+        to: status.email,
+        subject: format!("Verify your email address for {SITE_NAME}"),
+        body: format!(
+            "Please verify your email address by opening this link:\n\n\
+             https://{SITE_DOMAIN}/verify/{token}\n\n\
+             The link expires in {} hours. If you did not sign up, you can ignore this email.",
+            EMAIL_VERIFICATION_DURATION_SECS / 3600
+        ),
+    })
+    .await?;
+    log::trace!("verification email sent for {user_id}");
+    Ok(())
+}
+
+/// Tells the owner of an address that someone tried to sign up with it. Sent in place of a
+/// verification email, so a signup with a taken address looks like any other.
+#[cfg(feature = "ssr")]
+pub async fn send_signup_notice(owner: Uuid) -> Result<(), AppError> {
+    let status = email_status(owner).await?;
+    send_mail(Mail {
+        to: status.email,
+        subject: format!("Sign up attempt on {SITE_NAME}"),
+        body: format!(
+            "Someone tried to create a {SITE_NAME} account with this email address, which \
+             already belongs to an account.\n\n\
+             If it was you, log in instead, or choose a new password here:\n\n\
+             https://{SITE_DOMAIN}/forgot-password\n\n\
+             If it was not you, you can ignore this email, no account was created."
+        ),
+    })
+    .await?;
+    log::trace!("signup notice sent for {owner}");
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub async fn verify_email(csrf: String, token: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::VerifyEmail).await?;
+    let stored = match take_verification_token(&hash_token(token.trim())).await? {
+        Some(stored) => stored,
+        None => return Err(VerificationError::InvalidToken.into()),
+    };
+    if stored.expiry < Utc::now() {
+        return Err(VerificationError::InvalidToken.into());
+    }
+    // a link sent before the address was changed must not verify the new address
+    if !mark_email_verified(stored.user_id, &stored.email).await? {
+        return Err(VerificationError::InvalidToken.into());
+    }
+    log::trace!("email verified for {}", stored.user_id);
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub async fn resend_verification_email(csrf: String) -> Result<(), AppError> {
+    validate_csrf_request(csrf, CsrfPurpose::ResendVerification).await?;
+    let user_id = require_session().await?;
+    rate_limit(LimitedAction::ResendVerification)?;
+    send_verification_email(user_id).await
+}
+
+#[cfg(feature = "ssr")]
+pub async fn verification_status() -> Result<EmailVerificationStatus, AppError> {
+    let user_id = require_session().await?;
+    let status = email_status(user_id).await?;
+    Ok(EmailVerificationStatus {
+        email: status.email,
+        verified: status.verified,
+    })
+}
+
+/// Fails with `VerificationError::Unverified` when the configured policy keeps this account
+/// from logging in.
+#[cfg(feature = "ssr")]
+pub async fn check_login_allowed(user_id: Uuid) -> Result<(), AppError> {
+    let policy = match use_context::<ServerVars>() {
+        Some(vars) => vars.unverified_policy,
+        None => {
+            log::error!("could not retrieve servervars");
+            return Err(CsrfError::ServerValMissing.into());
+        }
+    };
+    if policy == UnverifiedPolicy::Deny && !email_status(user_id).await?.verified {
+        return Err(VerificationError::Unverified.into());
+    }
+    Ok(())
+}
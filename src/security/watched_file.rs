@@ -0,0 +1,90 @@
+use cfg_if::cfg_if;
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, Instant, SystemTime},
+    };
+}}
+
+/// A file that is read again when it changes, such as the csrf key file another instance
+/// may rotate or the name policy an operator may edit. Its modification time is compared at
+/// most every `interval`, so checking it on every request stays cheap.
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct WatchedFile {
+    path: PathBuf,
+    interval: Duration,
+    /// modification time of the file when it was last read
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+#[cfg(feature = "ssr")]
+impl WatchedFile {
+    /// Watches `path`, which was just read.
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        WatchedFile {
+            modified: modified_time(&path),
+            path,
+            interval,
+            checked: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `interval` passed since the file was last checked.
+    pub fn check_due(&self) -> bool {
+        self.checked.elapsed() >= self.interval
+    }
+
+    /// The path of the file if it changed since it was last read, counting it as read. A
+    /// change that fails to load is only tried again once the file changes again.
+    pub fn changed(&mut self) -> Option<&Path> {
+        self.checked = Instant::now();
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(&self.path)
+    }
+
+    /// Notes that the file was just read or written by this instance.
+    pub fn mark_read(&mut self) {
+        self.checked = Instant::now();
+        self.modified = modified_time(&self.path);
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn modified_time(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_is_seen_once() {
+        let path = std::env::temp_dir().join(format!("watched_{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "first").unwrap();
+        let mut watched = WatchedFile::new(path.clone(), Duration::ZERO);
+        assert!(watched.check_due());
+        assert_eq!(watched.changed(), None);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(watched.changed(), Some(path.as_path()));
+        assert_eq!(watched.changed(), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+cfg_if! { if #[cfg(feature = "ssr")] {
+    use crate::database::{
+        session_cookie::SessionCookieStanding, APIUserData, ValidateSession,
+    };
+    use std::{
+        borrow::Borrow,
+        collections::HashMap,
+        hash::Hash,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+    use uuid::Uuid;
+}}
+
+/// How often the session cache answered instead of the database, shown to admins
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionCacheStats {
+    pub session_hits: u64,
+    pub session_misses: u64,
+    pub user_hits: u64,
+    pub user_misses: u64,
+    /// sessions, encrypted session standings and users cached right now
+    pub entries: u64,
+}
+
+/// Recently validated sessions, encrypted session standings and loaded user data, so a page
+/// render and the server functions it calls do not each read the same rows. Shared through
+/// `AppState` and provided as context.
+///
+/// Every change this instance makes to a session or a user invalidates its entry, entries
+/// also expire after the `ttl` so changes made by another instance are picked up.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub struct SessionCache {
+    capacity: usize,
+    ttl: Duration,
+    /// by the id the session was presented with, which may be its id before a rotation
+    sessions: Arc<Mutex<TtlMap<String, ValidateSession>>>,
+    users: Arc<Mutex<TtlMap<Uuid, APIUserData>>>,
+    /// what encrypted session cookies are checked against, by the session id they hold
+    cookie_standings: Arc<Mutex<TtlMap<String, (Uuid, SessionCookieStanding)>>>,
+    counters: Arc<CacheCounters>,
+    /// bumped by every invalidation, see `epoch`
+    invalidations: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Default)]
+struct CacheCounters {
+    session_hits: AtomicU64,
+    session_misses: AtomicU64,
+    user_hits: AtomicU64,
+    user_misses: AtomicU64,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+struct TtlMap<K, V> {
+    entries: HashMap<K, (V, Instant)>,
+}
+
+#[cfg(feature = "ssr")]
+impl<K: Clone + Eq + Hash, V: Clone> TtlMap<K, V> {
+    fn new() -> Self {
+        TtlMap {
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get<Q>(&mut self, key: &Q, ttl: Duration) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.entries.get(key) {
+            Some((value, stored)) if stored.elapsed() < ttl => Some(value.clone()),
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores `value`, making room by dropping expired entries and then the oldest one.
+    fn insert(&mut self, key: K, value: V, capacity: usize, ttl: Duration) {
+        if capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= capacity && !self.entries.contains_key(&key) {
+            self.prune(ttl);
+        }
+        if self.entries.len() >= capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, stored))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (value, Instant::now()));
+    }
+
+    fn prune(&mut self, ttl: Duration) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, (_, stored)| stored.elapsed() < ttl);
+        before - self.entries.len()
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl SessionCache {
+    /// A cache of at most `capacity` sessions and `capacity` users, each kept at most `ttl`.
+    /// A `capacity` of 0 turns the cache off.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        SessionCache {
+            capacity,
+            ttl,
+            sessions: Arc::new(Mutex::new(TtlMap::new())),
+            users: Arc::new(Mutex::new(TtlMap::new())),
+            cookie_standings: Arc::new(Mutex::new(TtlMap::new())),
+            counters: Arc::default(),
+            invalidations: Arc::default(),
+        }
+    }
+
+    /// A cache that never stores anything, for callers without one in context.
+    pub fn disabled() -> Self {
+        SessionCache::new(0, Duration::ZERO)
+    }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, TtlMap<String, ValidateSession>> {
+        self.sessions
+            .lock()
+            .expect("session cache lock to not be poisoned")
+    }
+
+    fn lock_users(&self) -> std::sync::MutexGuard<'_, TtlMap<Uuid, APIUserData>> {
+        self.users
+            .lock()
+            .expect("session cache lock to not be poisoned")
+    }
+
+    fn lock_cookie_standings(
+        &self,
+    ) -> std::sync::MutexGuard<'_, TtlMap<String, (Uuid, SessionCookieStanding)>> {
+        self.cookie_standings
+            .lock()
+            .expect("session cache lock to not be poisoned")
+    }
+
+    /// Read before looking up what to store, and pass to `store_session` or `store_user`. A
+    /// row read while something was invalidated may already be stale, so it is not stored.
+    pub fn epoch(&self) -> u64 {
+        self.invalidations.load(Ordering::SeqCst)
+    }
+
+    /// The session row last read for the presented id `session_id`.
+    pub fn session(&self, session_id: &str) -> Option<ValidateSession> {
+        let cached = self.lock_sessions().get(session_id, self.ttl);
+        let counter = match cached {
+            Some(_) => &self.counters.session_hits,
+            None => &self.counters.session_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub fn store_session(&self, session_id: &str, session: ValidateSession, epoch: u64) {
+        let mut sessions = self.lock_sessions();
+        if self.epoch() == epoch {
+            sessions.insert(session_id.to_string(), session, self.capacity, self.ttl);
+        }
+    }
+
+    /// Forgets a session that was dropped or given a new id, whichever id it was cached by.
+    pub fn forget_session(&self, session_id: &str) {
+        self.forget_sessions(&[session_id.to_string()]);
+    }
+
+    pub fn forget_sessions(&self, session_ids: &[String]) {
+        if session_ids.is_empty() {
+            return;
+        }
+        let mut sessions = self.lock_sessions();
+        let mut cookie_standings = self.lock_cookie_standings();
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        sessions.entries.retain(|presented, (session, _)| {
+            !session_ids.contains(presented) && !session_ids.contains(&session.session_id)
+        });
+        cookie_standings
+            .entries
+            .retain(|session_id, _| !session_ids.contains(session_id));
+    }
+
+    /// The session generation of the user and whether the encrypted session `session_id` was
+    /// logged out, as last read.
+    pub fn cookie_standing(&self, session_id: &str) -> Option<(Uuid, SessionCookieStanding)> {
+        let cached = self.lock_cookie_standings().get(session_id, self.ttl);
+        let counter = match cached {
+            Some(_) => &self.counters.session_hits,
+            None => &self.counters.session_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub fn store_cookie_standing(
+        &self,
+        session_id: &str,
+        user_id: Uuid,
+        standing: SessionCookieStanding,
+        epoch: u64,
+    ) {
+        let mut cookie_standings = self.lock_cookie_standings();
+        if self.epoch() == epoch {
+            cookie_standings.insert(
+                session_id.to_string(),
+                (user_id, standing),
+                self.capacity,
+                self.ttl,
+            );
+        }
+    }
+
+    pub fn user(&self, user_id: Uuid) -> Option<APIUserData> {
+        let cached = self.lock_users().get(&user_id, self.ttl);
+        let counter = match cached {
+            Some(_) => &self.counters.user_hits,
+            None => &self.counters.user_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    pub fn store_user(&self, user_id: Uuid, data: APIUserData, epoch: u64) {
+        let mut users = self.lock_users();
+        if self.epoch() == epoch {
+            users.insert(user_id, data, self.capacity, self.ttl);
+        }
+    }
+
+    /// Forgets the data of `user_id` and the standing of their encrypted sessions, which
+    /// holds their session generation. Call after any change to their row.
+    pub fn forget_user(&self, user_id: Uuid) {
+        let mut users = self.lock_users();
+        let mut cookie_standings = self.lock_cookie_standings();
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        users.entries.remove(&user_id);
+        cookie_standings
+            .entries
+            .retain(|_, ((owner, _), _)| *owner != user_id);
+    }
+
+    /// Drops expired entries, returning how many there were.
+    pub fn prune(&self) -> usize {
+        self.lock_sessions().prune(self.ttl)
+            + self.lock_users().prune(self.ttl)
+            + self.lock_cookie_standings().prune(self.ttl)
+    }
+
+    pub fn stats(&self) -> SessionCacheStats {
+        SessionCacheStats {
+            session_hits: self.counters.session_hits.load(Ordering::Relaxed),
+            session_misses: self.counters.session_misses.load(Ordering::Relaxed),
+            user_hits: self.counters.user_hits.load(Ordering::Relaxed),
+            user_misses: self.counters.user_misses.load(Ordering::Relaxed),
+            entries: (self.lock_sessions().entries.len()
+                + self.lock_users().entries.len()
+                + self.lock_cookie_standings().entries.len()) as u64,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn user_data(display_name: &str) -> APIUserData {
+        APIUserData {
+            display_name: display_name.to_string(),
+            button_presses: 0,
+            rename_required: false,
+        }
+    }
+
+    #[test]
+    fn forgotten_user_is_read_again() {
+        let cache = SessionCache::new(8, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        cache.store_user(user_id, user_data("Alice"), cache.epoch());
+        assert_eq!(cache.user(user_id), Some(user_data("Alice")));
+        cache.forget_user(user_id);
+        assert_eq!(cache.user(user_id), None);
+        let stats = cache.stats();
+        assert_eq!((stats.user_hits, stats.user_misses), (1, 1));
+    }
+
+    #[test]
+    fn row_read_during_an_invalidation_is_not_stored() {
+        let cache = SessionCache::new(8, Duration::from_secs(60));
+        let user_id = Uuid::new_v4();
+        let epoch = cache.epoch();
+        // the row changed after it was read
+        cache.forget_user(user_id);
+        cache.store_user(user_id, user_data("Alice"), epoch);
+        assert_eq!(cache.user(user_id), None);
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let cache = SessionCache::disabled();
+        let user_id = Uuid::new_v4();
+        cache.store_user(user_id, user_data("Alice"), cache.epoch());
+        assert_eq!(cache.user(user_id), None);
+    }
+}
@@ -9,9 +9,16 @@ use std::{
 use web_sys::{CloseEvent, Event, WebSocket as WebSysWebSocket};
 
 cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
-    use crate::database::{validate_token_with_pool, user_data_with_pool};
+    use crate::database::{
+        validate_token_with_pool, user_data_with_pool,
+        verification::email_verified_with_pool,
+    };
     use crate::cookies::parse_session_header_cookie;
-    use crate::defs::AppState;
+    use crate::defs::{ApiTokenScope, AppState, SessionStore, UnverifiedPolicy};
+    use crate::security::api_tokens::{
+        api_token_socket_key, authenticate_api_token_with_pool, bearer_token,
+    };
+    use crate::security::session_cookie::validate_session_cookie_with_pool;
     use axum::{
         extract::{
             State,
@@ -19,10 +26,17 @@ cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
             //Request,
             connect_info::ConnectInfo,
         },
-        response::IntoResponse,
+        response::{IntoResponse, Response},
         http::{StatusCode, header::HeaderMap},
     };
-    use std::{ops::ControlFlow, net::SocketAddr};
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        ops::ControlFlow,
+        sync::{Arc, Mutex},
+    };
+    use tokio::sync::watch;
+    use uuid::Uuid;
     //allows to split the websocket stream into separate TX and RX branches
     use futures::{sink::SinkExt, stream::StreamExt};
 } else {
@@ -150,6 +164,7 @@ where
     pub send_bytes: SendBytesFn,
 }
 
+#[allow(clippy::type_complexity)]
 pub fn web_sys_websocket(
     url: &str,
     options: WebSysWebSocketOptions,
@@ -196,7 +211,7 @@ pub fn web_sys_websocket(
                 if reconnect_times_ref.get_value() < reconnect_limit
                     && ws
                         .clone()
-                        .map_or(false, |ws: WebSysWebSocket| ws.ready_state() != WebSysWebSocket::OPEN)
+                        .is_some_and(|ws: WebSysWebSocket| ws.ready_state() != WebSysWebSocket::OPEN)
                 {
                     reconnect_timer_ref.set_value(
                         set_timeout_with_handle(
@@ -216,7 +231,6 @@ pub fn web_sys_websocket(
 
         connect_ref.set_value({
             let ws = ws_ref.get_value();
-            let url = url;
 
             Some(Rc::new(move || {
                 reconnect_timer_ref.set_value(None);
@@ -417,6 +431,88 @@ pub fn web_sys_websocket(
     }
 }
 
+/// Open websocket connections by the session they were authenticated with, so revoking a
+/// session can close them.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Default)]
+pub struct SessionSockets(Arc<Mutex<HashMap<String, SessionSocket>>>);
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+struct SessionSocket {
+    /// the account of a session, `None` for sockets opened with an access token
+    user_id: Option<Uuid>,
+    revoked: watch::Sender<bool>,
+}
+
+#[cfg(feature = "ssr")]
+impl SessionSockets {
+    /// Returns a receiver that changes once `session_id` is revoked.
+    pub fn subscribe(&self, session_id: &str, user_id: Option<Uuid>) -> watch::Receiver<bool> {
+        let mut sockets = self
+            .0
+            .lock()
+            .expect("session sockets lock to not be poisoned");
+        sockets
+            .entry(session_id.to_string())
+            .or_insert_with(|| SessionSocket {
+                user_id,
+                revoked: watch::channel(false).0,
+            })
+            .revoked
+            .subscribe()
+    }
+
+    /// Forgets the sessions none of whose sockets are open anymore, after a socket closed.
+    /// The closed socket's session may have been renamed since it was opened.
+    pub fn release(&self) {
+        let mut sockets = self
+            .0
+            .lock()
+            .expect("session sockets lock to not be poisoned");
+        sockets.retain(|_, socket| socket.revoked.receiver_count() > 0);
+    }
+
+    /// Keeps the sockets of a session that was given a new id revocable under that id.
+    pub fn rename(&self, session_id: &str, new_session_id: &str) {
+        let mut sockets = self
+            .0
+            .lock()
+            .expect("session sockets lock to not be poisoned");
+        if let Some(socket) = sockets.remove(session_id) {
+            sockets.insert(new_session_id.to_string(), socket);
+        }
+    }
+
+    /// Closes every socket authenticated with `session_id`.
+    pub fn revoke(&self, session_id: &str) {
+        let mut sockets = self
+            .0
+            .lock()
+            .expect("session sockets lock to not be poisoned");
+        if let Some(socket) = sockets.remove(session_id) {
+            let _ = socket.revoked.send(true);
+        }
+    }
+
+    /// Closes the sockets of every session of `user_id` except `keep_session_id`, for
+    /// sessions that cannot be listed by id.
+    pub fn revoke_user(&self, user_id: Uuid, keep_session_id: Option<&str>) {
+        let mut sockets = self
+            .0
+            .lock()
+            .expect("session sockets lock to not be poisoned");
+        sockets.retain(|session_id, socket| {
+            if socket.user_id != Some(user_id) || Some(session_id.as_str()) == keep_session_id
+            {
+                return true;
+            }
+            let _ = socket.revoked.send(true);
+            false
+        });
+    }
+}
+
 /// The handler for the HTTP request (this gets called when the HTTP GET lands at the start
 /// of websocket negotiation). After this completes, the actual switching from HTTP to
 /// websocket protocol will occur.
@@ -433,22 +529,18 @@ pub async fn axum_ws_handler(
     //origin: Option<TypedHeader<http::request::Origin>>,
     //header_cookies: Option<TypedHeader<http::headers::Cookie>>,
     let user_agent = match headers.get(http::header::USER_AGENT) {
-        Some(thing) => match thing.to_str() {
-            Ok(agent) => agent,
-            Err(_) => "INVALID USER_AGENT ASCII",
-        },
+        Some(thing) => thing.to_str().unwrap_or("INVALID USER_AGENT ASCII"),
         None => "No USER_AGENT",
     };
     let origin = match headers.get(http::header::ORIGIN) {
-        Some(thing) => match thing.to_str() {
-            Ok(origin) => origin,
-            Err(e) => "INVALID ORIGIN ASCII",
-        },
+        Some(thing) => thing.to_str().unwrap_or("INVALID ORIGIN ASCII"),
         None => "No ORIGIN",
     };
-    let site_url = format!("https://{}", app_state.leptos_options.site_addr.to_string());
-    // validate origin header
-    if origin != site_url {
+    let site_url = format!("https://{}", app_state.leptos_options.site_addr);
+    let bearer = bearer_token(&headers);
+    // validate origin header, scripts sending a personal access token are not browsers and a
+    // cross site page cannot attach one
+    if bearer.is_none() && origin != site_url {
         log::debug!(
             "`{user_agent}` from {addr} with origin {origin} websocket rejected due to \
              invalid origin."
@@ -459,37 +551,49 @@ pub async fn axum_ws_handler(
         )
             .into_response();
     }
-    let cookies_raw = match headers.get(http::header::COOKIE) {
-        Some(thing) => match thing.to_str() {
-            Ok(cookie_raw_string) => cookie_raw_string,
-            Err(e) => {
+    let (user_uuid, socket_key, session_user) = match bearer {
+        Some(token) => match authenticate_api_token_with_pool(
+            &token,
+            ApiTokenScope::Websocket,
+            &app_state.pool,
+        )
+        .await
+        {
+            Ok(Some(grant)) => (grant.user_id, api_token_socket_key(grant.token_id), None),
+            Ok(None) => {
                 log::debug!(
-                    "`{user_agent}` from {addr} wtih invalid cookie_raw_string rejected."
+                    "`{user_agent}` from {addr} websocket rejected due to invalid access token."
                 );
-                return (StatusCode::UNAUTHORIZED, "please sign in first").into_response();
+                return (StatusCode::UNAUTHORIZED, "invalid access token").into_response();
+            }
+            Err(_) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
             }
         },
-        None => {
-            log::debug!(
-                "`{user_agent}` from {addr} wtih no cookies websocket rejected due to no \
-                 cookies."
-            );
-            return (StatusCode::UNAUTHORIZED, "please sign in first").into_response();
-        }
+        None => match session_from_cookies(&headers, &app_state, user_agent, addr).await {
+            Ok((user_id, session_id)) => (user_id, session_id, Some(user_id)),
+            Err(response) => return response,
+        },
     };
-    // validate Uuid and pass into handler
-    let unverified_session_id = parse_session_header_cookie(cookies_raw);
-    let user_uuid =
-        match validate_token_with_pool(unverified_session_id, app_state.pool.clone()).await {
-            Ok(Some(id)) => id,
-            Ok(None) => {
+    log::trace!("`{user_agent}` from {addr} websocket request is valid for uuid {user_uuid}.");
+    if app_state.vars.unverified_policy != UnverifiedPolicy::Allow {
+        match email_verified_with_pool(user_uuid, app_state.pool.clone()).await {
+            Ok(true) => {}
+            Ok(false) => {
                 log::debug!(
-                    "`{user_agent}` from {addr} wtih cookies {:#?} websocket rejected due to \
-                 invalid session.",
-                    cookies_raw
+                    "`{user_agent}` from {addr} websocket rejected due to unverified email."
                 );
-                return (StatusCode::UNAUTHORIZED, "please sign in first").into_response();
+                return (StatusCode::FORBIDDEN, "please verify your email first")
+                    .into_response();
+            }
+            Err(_) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
             }
+        }
+    }
+    let display_name =
+        match user_data_with_pool(user_uuid, app_state.pool, &app_state.session_cache).await {
+            Ok(data) => data.display_name,
             Err(e) => match e {
                 crate::defs::DatabaseError::CouldNotFindPool => {
                     return (StatusCode::INTERNAL_SERVER_ERROR, "try again later")
@@ -500,7 +604,8 @@ pub async fn axum_ws_handler(
                         .into_response()
                 }
                 crate::defs::DatabaseError::NoEntries => {
-                    return (StatusCode::UNAUTHORIZED, "please sign in first").into_response()
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "try again later")
+                        .into_response()
                 }
                 crate::defs::DatabaseError::IncorrectRowsAffected => {
                     return (StatusCode::INTERNAL_SERVER_ERROR, "try again later")
@@ -508,35 +613,109 @@ pub async fn axum_ws_handler(
                 }
             },
         };
-    log::trace!("`{user_agent}` from {addr} websocket request is valid for uuid {user_uuid}.");
-    let display_name = match user_data_with_pool(user_uuid, app_state.pool).await {
-        Ok(data) => data.display_name,
+    log::trace!(
+        "{user_uuid} is correctly identified as {display_name} and websocket request accepted"
+    );
+    // finalize the upgrade process by returning upgrade callback.
+    // we can customize the callback by sending additional info such as address.
+    let sockets = app_state.sockets.clone();
+    // subscribe with the current id, the cookie may still hold one that was just rotated away
+    let revoked = sockets.subscribe(&socket_key, session_user);
+    ws.on_upgrade(move |socket| async move {
+        handle_socket(socket, addr, display_name, revoked).await;
+        sockets.release();
+    })
+}
+
+/// Validates the session cookie of a websocket request, returning the user and session id or
+/// the response rejecting it.
+#[cfg(feature = "ssr")]
+async fn session_from_cookies(
+    headers: &HeaderMap,
+    app_state: &AppState,
+    user_agent: &str,
+    addr: SocketAddr,
+) -> Result<(Uuid, String), Response> {
+    let cookies_raw = match headers.get(http::header::COOKIE) {
+        Some(thing) => match thing.to_str() {
+            Ok(cookie_raw_string) => cookie_raw_string,
+            Err(e) => {
+                log::debug!(
+                    "`{user_agent}` from {addr} wtih invalid cookie_raw_string rejected."
+                );
+                return Err((StatusCode::UNAUTHORIZED, "please sign in first").into_response());
+            }
+        },
+        None => {
+            log::debug!(
+                "`{user_agent}` from {addr} wtih no cookies websocket rejected due to no \
+                 cookies."
+            );
+            return Err((StatusCode::UNAUTHORIZED, "please sign in first").into_response());
+        }
+    };
+    // validate Uuid and pass into handler
+    let unverified_session_id = parse_session_header_cookie(cookies_raw);
+    let session = match app_state.vars.session_store {
+        SessionStore::Database => validate_token_with_pool(
+            unverified_session_id,
+            app_state.pool.clone(),
+            app_state.vars.session_policy,
+            &app_state.session_cache,
+        )
+        .await
+        .map(|session| session.map(|session| (session.user_id, session.session_id))),
+        SessionStore::Cookie => validate_session_cookie_with_pool(
+            &unverified_session_id,
+            &app_state.session_keys,
+            &app_state.pool,
+            &app_state.session_cache,
+        )
+        .await
+        .map(|claims| claims.map(|claims| (claims.user_id, claims.session_id))),
+    };
+    let session = match session {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            log::debug!(
+                "`{user_agent}` from {addr} wtih cookies {:#?} websocket rejected due to \
+                 invalid session.",
+                cookies_raw
+            );
+            return Err((StatusCode::UNAUTHORIZED, "please sign in first").into_response());
+        }
         Err(e) => match e {
             crate::defs::DatabaseError::CouldNotFindPool => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                return Err(
+                    (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                )
             }
             crate::defs::DatabaseError::QueryFailed => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                return Err(
+                    (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                )
             }
             crate::defs::DatabaseError::NoEntries => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                return Err((StatusCode::UNAUTHORIZED, "please sign in first").into_response())
             }
             crate::defs::DatabaseError::IncorrectRowsAffected => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                return Err(
+                    (StatusCode::INTERNAL_SERVER_ERROR, "try again later").into_response()
+                )
             }
         },
     };
-    log::trace!(
-        "{user_uuid} is correctly identified as {display_name} and websocket request accepted"
-    );
-    // finalize the upgrade process by returning upgrade callback.
-    // we can customize the callback by sending additional info such as address.
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, display_name))
+    Ok(session)
 }
 
 #[cfg(feature = "ssr")]
 /// Actual websocket statemachine (one will be spawned per connection)
-async fn handle_socket(mut socket: AxumWebSocket, who: SocketAddr, display_name: String) {
+async fn handle_socket(
+    mut socket: AxumWebSocket,
+    who: SocketAddr,
+    display_name: String,
+    mut revoked: watch::Receiver<bool>,
+) {
     //send a ping (unsupported by some browsers) just to kick things off and get a response
     if socket.send(Message::Ping(vec![1, 2, 3])).await.is_ok() {
         log::trace!("Pinged {display_name}->{who}...");
@@ -610,8 +789,8 @@ async fn handle_socket(mut socket: AxumWebSocket, who: SocketAddr, display_name:
             .await
         {
             log::trace!("Could not send Close due to {}, probably it is ok?", e);
-        }*/
-        n_msg
+        }
+        n_msg*/
     });
 
     // This second task will receive messages from client and print them on server console
@@ -646,6 +825,11 @@ async fn handle_socket(mut socket: AxumWebSocket, who: SocketAddr, display_name:
             //log::trace!("recv_task caused abort");
             send_task.abort();
         }
+        _ = revoked.changed() => {
+            log::trace!("session of {display_name}->{who} was revoked");
+            send_task.abort();
+            recv_task.abort();
+        }
     }
 
     // returning from the handler closes the websocket connection
@@ -0,0 +1,322 @@
+//! Runs the OpenID Connect relying party against a local mock provider.
+//! `cargo test --features ssr --test oidc_mock`
+#![cfg(feature = "ssr")]
+
+use auth_sessions_example::{
+    defs::OidcError,
+    security::oidc::{FederatedIdentity, OidcProvider, OidcProviderConfig},
+};
+use axum::{
+    extract::{Form, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::Engine;
+use chrono::{Duration, Utc};
+use openidconnect::{
+    core::{
+        CoreIdToken, CoreIdTokenClaims, CoreJsonWebKeySet, CoreJwsSigningAlgorithm,
+        CoreProviderMetadata, CoreResponseType, CoreRsaPrivateSigningKey,
+        CoreSubjectIdentifierType,
+    },
+    reqwest,
+    url::Url,
+    AccessToken, Audience, AuthUrl, EmptyAdditionalClaims, EmptyAdditionalProviderMetadata,
+    EndUserEmail, IssuerUrl, JsonWebKeyId, JsonWebKeySetUrl, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, PrivateSigningKey, ResponseTypes, StandardClaims, SubjectIdentifier,
+    TokenUrl,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+const CLIENT_ID: &str = "relying-party";
+const CLIENT_SECRET: &str = "relying-party-secret";
+const SUBJECT: &str = "248289761001";
+const EMAIL: &str = "jane@example.com";
+const REDIRECT_URL: &str = "https://localhost/oidc/mock/callback";
+
+/// What the mock provider remembers about an authorization until its code is redeemed
+struct Grant {
+    nonce: String,
+    code_challenge: String,
+}
+
+#[derive(Clone)]
+struct Mock {
+    issuer: String,
+    email_verified: bool,
+    key: Arc<CoreRsaPrivateSigningKey>,
+    grants: Arc<Mutex<HashMap<String, Grant>>>,
+}
+
+fn signing_key() -> CoreRsaPrivateSigningKey {
+    CoreRsaPrivateSigningKey::from_pem(
+        include_str!("fixtures/oidc_mock_key.pem"),
+        Some(JsonWebKeyId::new(String::from("mock"))),
+    )
+    .expect("fixture key to parse")
+}
+
+/// Starts a provider on a free local port and returns its issuer url.
+async fn spawn_mock(email_verified: bool) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("a free port");
+    let issuer = format!("http://{}", listener.local_addr().expect("an address"));
+    let mock = Mock {
+        issuer: issuer.clone(),
+        email_verified,
+        key: Arc::new(signing_key()),
+        grants: Arc::default(),
+    };
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(mock);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    issuer
+}
+
+async fn discovery(State(mock): State<Mock>) -> Json<CoreProviderMetadata> {
+    Json(
+        CoreProviderMetadata::new(
+            IssuerUrl::new(mock.issuer.clone()).unwrap(),
+            AuthUrl::new(format!("{}/authorize", mock.issuer)).unwrap(),
+            JsonWebKeySetUrl::new(format!("{}/jwks", mock.issuer)).unwrap(),
+            vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+            vec![CoreSubjectIdentifierType::Public],
+            vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+            EmptyAdditionalProviderMetadata {},
+        )
+        .set_token_endpoint(Some(
+            TokenUrl::new(format!("{}/token", mock.issuer)).unwrap(),
+        )),
+    )
+}
+
+async fn jwks(State(mock): State<Mock>) -> Json<CoreJsonWebKeySet> {
+    Json(CoreJsonWebKeySet::new(vec![mock.key.as_verification_key()]))
+}
+
+/// Approves every request straight away, as if the user had signed in and consented.
+async fn authorize(
+    State(mock): State<Mock>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if query.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        || query.get("redirect_uri").map(String::as_str) != Some(REDIRECT_URL)
+        || query.get("code_challenge_method").map(String::as_str) != Some("S256")
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let code = format!("code-{}", mock.grants.lock().unwrap().len());
+    mock.grants.lock().unwrap().insert(
+        code.clone(),
+        Grant {
+            nonce: query["nonce"].clone(),
+            code_challenge: query["code_challenge"].clone(),
+        },
+    );
+    Redirect::to(&format!(
+        "{REDIRECT_URL}?code={code}&state={}",
+        query["state"]
+    ))
+    .into_response()
+}
+
+async fn token(
+    State(mock): State<Mock>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let credentials = base64::engine::general_purpose::STANDARD
+        .encode(format!("{CLIENT_ID}:{CLIENT_SECRET}"));
+    if headers.get(AUTHORIZATION).and_then(|h| h.to_str().ok())
+        != Some(&format!("Basic {credentials}"))
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let invalid_grant = (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": "invalid_grant" })),
+    );
+    // codes can only be redeemed once
+    let grant = match form
+        .get("code")
+        .and_then(|code| mock.grants.lock().unwrap().remove(code))
+    {
+        Some(grant) => grant,
+        None => return invalid_grant.into_response(),
+    };
+    let verifier =
+        PkceCodeVerifier::new(form.get("code_verifier").cloned().unwrap_or_default());
+    if PkceCodeChallenge::from_code_verifier_sha256(&verifier).as_str() != grant.code_challenge
+    {
+        return invalid_grant.into_response();
+    }
+    let access_token = AccessToken::new(String::from("mock-access-token"));
+    let claims = CoreIdTokenClaims::new(
+        IssuerUrl::new(mock.issuer.clone()).unwrap(),
+        vec![Audience::new(String::from(CLIENT_ID))],
+        Utc::now() + Duration::seconds(300),
+        Utc::now(),
+        StandardClaims::new(SubjectIdentifier::new(String::from(SUBJECT)))
+            .set_email(Some(EndUserEmail::new(String::from(EMAIL))))
+            .set_email_verified(Some(mock.email_verified)),
+        EmptyAdditionalClaims {},
+    )
+    .set_nonce(Some(Nonce::new(grant.nonce)));
+    let id_token = CoreIdToken::new(
+        claims,
+        mock.key.as_ref(),
+        CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+        Some(&access_token),
+        None,
+    )
+    .expect("id token to be signed");
+    Json(serde_json::json!({
+        "access_token": access_token.secret(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token.to_string(),
+    }))
+    .into_response()
+}
+
+fn provider(issuer: &str) -> OidcProvider {
+    OidcProvider::new(OidcProviderConfig {
+        id: String::from("mock"),
+        name: String::from("Mock"),
+        issuer: issuer.to_string(),
+        client_id: String::from(CLIENT_ID),
+        client_secret: String::from(CLIENT_SECRET).into(),
+        redirect_url: String::from(REDIRECT_URL),
+    })
+    .expect("a valid provider config")
+}
+
+/// Follows the authorization url like a browser would and returns the code and state the
+/// provider sends back.
+async fn approve(url: &str) -> (String, String) {
+    let http = reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = http.get(url).send().await.expect("the mock to answer");
+    assert_eq!(response.status(), reqwest::StatusCode::SEE_OTHER);
+    let location = response.headers()["location"].to_str().unwrap();
+    let location = Url::parse(location).expect("a redirect url");
+    let query: HashMap<String, String> = location.query_pairs().into_owned().collect();
+    (query["code"].clone(), query["state"].clone())
+}
+
+#[tokio::test]
+async fn signs_in_with_authorization_code_and_pkce() {
+    let issuer = spawn_mock(true).await;
+    let provider = provider(&issuer);
+    let request = provider.authorization_request().await.unwrap();
+    let (code, state) = approve(&request.url).await;
+    assert_eq!(state, request.state);
+    let identity = provider
+        .verify_callback(code, request.pkce_verifier, request.nonce)
+        .await
+        .unwrap();
+    assert_eq!(
+        identity,
+        FederatedIdentity {
+            issuer,
+            subject: String::from(SUBJECT),
+            email: Some(String::from(EMAIL)),
+            email_verified: true,
+        }
+    );
+}
+
+#[tokio::test]
+async fn passes_on_unverified_email() {
+    let issuer = spawn_mock(false).await;
+    let provider = provider(&issuer);
+    let request = provider.authorization_request().await.unwrap();
+    let (code, _) = approve(&request.url).await;
+    let identity = provider
+        .verify_callback(code, request.pkce_verifier, request.nonce)
+        .await
+        .unwrap();
+    assert!(!identity.email_verified);
+}
+
+#[tokio::test]
+async fn rejects_wrong_pkce_verifier() {
+    let issuer = spawn_mock(true).await;
+    let provider = provider(&issuer);
+    let request = provider.authorization_request().await.unwrap();
+    let other = provider.authorization_request().await.unwrap();
+    let (code, _) = approve(&request.url).await;
+    let result = provider
+        .verify_callback(code, other.pkce_verifier, request.nonce)
+        .await;
+    assert!(matches!(result, Err(OidcError::ExchangeFailed)));
+}
+
+#[tokio::test]
+async fn rejects_wrong_nonce() {
+    let issuer = spawn_mock(true).await;
+    let provider = provider(&issuer);
+    let request = provider.authorization_request().await.unwrap();
+    let other = provider.authorization_request().await.unwrap();
+    let (code, _) = approve(&request.url).await;
+    let result = provider
+        .verify_callback(code, request.pkce_verifier, other.nonce)
+        .await;
+    assert!(matches!(result, Err(OidcError::InvalidIdToken)));
+}
+
+#[tokio::test]
+async fn rejects_redeemed_code() {
+    let issuer = spawn_mock(true).await;
+    let provider = provider(&issuer);
+    let request = provider.authorization_request().await.unwrap();
+    let (code, _) = approve(&request.url).await;
+    provider
+        .verify_callback(
+            code.clone(),
+            request.pkce_verifier.clone(),
+            request.nonce.clone(),
+        )
+        .await
+        .unwrap();
+    let result = provider
+        .verify_callback(code, request.pkce_verifier, request.nonce)
+        .await;
+    assert!(matches!(result, Err(OidcError::ExchangeFailed)));
+}
+
+#[tokio::test]
+async fn reports_unreachable_provider() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let result = provider(&issuer).authorization_request().await;
+    assert!(matches!(result, Err(OidcError::ProviderUnavailable)));
+}
+
+#[test]
+fn rejects_invalid_provider_ids() {
+    for id in ["", "Mock", "mock-provider", "averyveryverylongprovider"] {
+        let config = OidcProviderConfig {
+            id: String::from(id),
+            name: String::from("Mock"),
+            issuer: String::from("https://127.0.0.1"),
+            client_id: String::from(CLIENT_ID),
+            client_secret: String::from(CLIENT_SECRET).into(),
+            redirect_url: String::from(REDIRECT_URL),
+        };
+        assert!(OidcProvider::new(config).is_err(), "{id} was accepted");
+    }
+}